@@ -0,0 +1,701 @@
+use crate::clock::{
+    Clock, DateTime, SystemClock, TimeOfDay, UtcOffset, format_duration, parse_duration,
+};
+use crate::config::{offset_sections, read_config, unknown_key, with_line};
+use crate::{DeviceKind, Home, SmartHomeError, SmartSocket};
+use std::{
+    fmt,
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+// Rules file is an ini-like file with one section per rule:
+//
+//   utc_offset = +03:00
+//
+//   [Cool down]
+//   when = Room2/Thermometer2 temperature > 26
+//   and = time 08:00-22:00
+//   for = 5m
+//   hysteresis = 0.5
+//   then = Room1/Socket1 on
+//   else = Room1/Socket1 off
+//
+// Rule fires `then` actions once all conditions hold for the `for` period and
+// `else` actions once they stop holding. Threshold conditions of a fired rule are
+// released only after the value goes back over the hysteresis band.
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceRef {
+    pub room: String,
+    pub device: String,
+}
+
+impl DeviceRef {
+    pub fn new(room: impl Into<String>, device: impl Into<String>) -> Self {
+        Self {
+            room: room.into(),
+            device: device.into(),
+        }
+    }
+}
+
+impl fmt::Display for DeviceRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.room, self.device)
+    }
+}
+
+impl FromStr for DeviceRef {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().split_once('/') {
+            Some((room, device)) if !room.trim().is_empty() && !device.trim().is_empty() => {
+                Ok(DeviceRef::new(room.trim(), device.trim()))
+            }
+            _ => Err(SmartHomeError::ConfigError(format!(
+                "Invalid device '{s}', expected <room>/<device>"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+impl Comparison {
+    // Hysteresis widens the band for an already fired rule.
+    fn check(&self, value: f32, threshold: f32, hysteresis: f32) -> bool {
+        match self {
+            Comparison::Above => value > threshold - hysteresis,
+            Comparison::Below => value < threshold + hysteresis,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparison::Above => write!(f, ">"),
+            Comparison::Below => write!(f, "<"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Temperature {
+        device: DeviceRef,
+        comparison: Comparison,
+        value: f32,
+    },
+    Power {
+        device: DeviceRef,
+        comparison: Comparison,
+        value: f32,
+    },
+    SocketState {
+        device: DeviceRef,
+        on: bool,
+    },
+    Time {
+        from: TimeOfDay,
+        to: TimeOfDay,
+    },
+}
+
+impl Condition {
+    fn evaluate(
+        &self,
+        home: &Home,
+        now: &DateTime,
+        hysteresis: f32,
+    ) -> Result<bool, SmartHomeError> {
+        let device = |device: &DeviceRef| home.find_device(&device.room, &device.device);
+        Ok(match self {
+            Condition::Temperature {
+                device: d,
+                comparison,
+                value,
+            } => {
+                let temperature = device(d)?.as_thermometer()?.get_temperature();
+                comparison.check(temperature, *value, hysteresis)
+            }
+            Condition::Power {
+                device: d,
+                comparison,
+                value,
+            } => {
+                let power = device(d)?.as_socket()?.get_power()?;
+                comparison.check(power, *value, hysteresis)
+            }
+            Condition::SocketState { device: d, on } => device(d)?.as_socket()?.is_on()? == *on,
+            Condition::Time { from, to } => now.time.is_between(*from, *to),
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Temperature {
+                device,
+                comparison,
+                value,
+            } => write!(f, "{device} temperature {comparison} {value}"),
+            Condition::Power {
+                device,
+                comparison,
+                value,
+            } => write!(f, "{device} power {comparison} {value}"),
+            Condition::SocketState { device, on } => {
+                write!(f, "{device} is {}", if *on { "on" } else { "off" })
+            }
+            Condition::Time { from, to } => write!(f, "time {from}-{to}"),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || SmartHomeError::ConfigError(format!("Invalid condition '{s}'"));
+        if let Some(interval) = s.strip_prefix("time ") {
+            let (from, to) = interval.split_once('-').ok_or_else(invalid)?;
+            return Ok(Condition::Time {
+                from: from.parse()?,
+                to: to.parse()?,
+            });
+        }
+        if let Some((device, state)) = s.rsplit_once(" is ") {
+            let on = match state.trim() {
+                "on" => true,
+                "off" => false,
+                _ => return Err(invalid()),
+            };
+            return Ok(Condition::SocketState {
+                device: device.parse()?,
+                on,
+            });
+        }
+
+        let mut tokens = s.rsplitn(4, char::is_whitespace);
+        let (Some(value), Some(comparison), Some(metric), Some(device)) =
+            (tokens.next(), tokens.next(), tokens.next(), tokens.next())
+        else {
+            return Err(invalid());
+        };
+        let value: f32 = value.parse().map_err(|_| invalid())?;
+        let comparison = match comparison {
+            ">" => Comparison::Above,
+            "<" => Comparison::Below,
+            _ => return Err(invalid()),
+        };
+        let device = device.parse()?;
+        match metric {
+            "temperature" => Ok(Condition::Temperature {
+                device,
+                comparison,
+                value,
+            }),
+            "power" => Ok(Condition::Power {
+                device,
+                comparison,
+                value,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Switch,
+    On,
+    Off,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Switch => write!(f, "switch"),
+            Command::On => write!(f, "on"),
+            Command::Off => write!(f, "off"),
+        }
+    }
+}
+
+impl Command {
+    pub fn run(&self, socket: &SmartSocket) -> Result<(), SmartHomeError> {
+        match self {
+            Command::Switch => socket.switch(),
+            Command::On => socket.turn_on(),
+            Command::Off => socket.turn_off(),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "switch" => Ok(Command::Switch),
+            "on" => Ok(Command::On),
+            "off" => Ok(Command::Off),
+            _ => Err(SmartHomeError::ConfigError(format!(
+                "Unknown command '{s}'"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Action {
+    pub device: DeviceRef,
+    pub command: Command,
+}
+
+impl Action {
+    pub fn new(device: DeviceRef, command: Command) -> Self {
+        Self { device, command }
+    }
+    // Device may be a path pattern like `*/Heater*`, then every matching socket is
+    // commanded and the first error is returned.
+    pub fn execute(&self, home: &Home) -> Result<(), SmartHomeError> {
+        let mut result = Ok(());
+        for (room, name, device) in home.resolve(&self.device)? {
+            if self.device.is_pattern() && device.kind() != DeviceKind::Socket {
+                continue;
+            }
+            let done = device
+                .as_socket()
+                .and_then(|socket| self.command.run(socket));
+            result = result.and(done.map_err(|err| err.in_device(room, name)));
+        }
+        result
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.device, self.command)
+    }
+}
+
+impl FromStr for Action {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (device, command) =
+            s.trim()
+                .rsplit_once(char::is_whitespace)
+                .ok_or(SmartHomeError::ConfigError(format!(
+                    "Invalid action '{s}', expected <room>/<device> <command>"
+                )))?;
+        Ok(Action::new(device.parse()?, command.parse()?))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub conditions: Vec<Condition>,
+    pub sustain: Duration,
+    pub hysteresis: f32,
+    pub actions: Vec<Action>,
+    pub release_actions: Vec<Action>,
+}
+
+impl Rule {
+    pub fn new(name: impl Into<String>, condition: Condition) -> Self {
+        Self {
+            name: name.into(),
+            conditions: vec![condition],
+            sustain: Duration::ZERO,
+            hysteresis: 0.0,
+            actions: Vec::new(),
+            release_actions: Vec::new(),
+        }
+    }
+    pub fn and(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+    pub fn sustained_for(mut self, sustain: Duration) -> Self {
+        self.sustain = sustain;
+        self
+    }
+    pub fn with_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+    pub fn then(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+    pub fn otherwise(mut self, action: Action) -> Self {
+        self.release_actions.push(action);
+        self
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[{}]", self.name)?;
+        for (i, condition) in self.conditions.iter().enumerate() {
+            writeln!(f, "{} = {condition}", if i == 0 { "when" } else { "and" })?;
+        }
+        if !self.sustain.is_zero() {
+            writeln!(f, "for = {}", format_duration(self.sustain))?;
+        }
+        if self.hysteresis != 0.0 {
+            writeln!(f, "hysteresis = {}", self.hysteresis)?;
+        }
+        for action in &self.actions {
+            writeln!(f, "then = {action}")?;
+        }
+        for action in &self.release_actions {
+            writeln!(f, "else = {action}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RuleSet {
+    pub utc_offset: UtcOffset,
+    pub rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn load(path: impl AsRef<Path>) -> Result<RuleSet, SmartHomeError> {
+        read_config(path.as_ref())?.parse()
+    }
+}
+
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "utc_offset = {}", self.utc_offset)?;
+        for rule in &self.rules {
+            writeln!(f)?;
+            write!(f, "{rule}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RuleSet {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (utc_offset, sections) = offset_sections(s)?;
+        let mut set = RuleSet {
+            utc_offset,
+            rules: Vec::new(),
+        };
+
+        for (name, values) in sections {
+            let mut rule = Rule {
+                name: name.clone(),
+                conditions: Vec::new(),
+                sustain: Duration::ZERO,
+                hysteresis: 0.0,
+                actions: Vec::new(),
+                release_actions: Vec::new(),
+            };
+            for (number, key, value) in values {
+                let at_line = |err| with_line(number, err);
+                match key {
+                    "when" | "and" => rule.conditions.push(value.parse().map_err(at_line)?),
+                    "for" => rule.sustain = parse_duration(value).map_err(at_line)?,
+                    "hysteresis" => {
+                        rule.hysteresis = value.parse().map_err(|_| {
+                            SmartHomeError::ConfigError(format!(
+                                "Line {number}: invalid hysteresis '{value}'"
+                            ))
+                        })?
+                    }
+                    "then" => rule.actions.push(value.parse().map_err(at_line)?),
+                    "else" => rule.release_actions.push(value.parse().map_err(at_line)?),
+                    _ => return Err(unknown_key(number, key)),
+                }
+            }
+            if rule.conditions.is_empty() {
+                return Err(SmartHomeError::ConfigError(format!(
+                    "Rule '{name}' has no conditions"
+                )));
+            }
+            set.rules.push(rule);
+        }
+        Ok(set)
+    }
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Fired {
+        rule: String,
+        results: Vec<(Action, Result<(), SmartHomeError>)>,
+    },
+    Released {
+        rule: String,
+        results: Vec<(Action, Result<(), SmartHomeError>)>,
+    },
+    // Conditions could not be checked, rule keeps its state.
+    Failed {
+        rule: String,
+        error: SmartHomeError,
+    },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (rule, event, results) = match self {
+            Outcome::Fired { rule, results } => (rule, "fired", results),
+            Outcome::Released { rule, results } => (rule, "released", results),
+            Outcome::Failed { error, .. } => return write!(f, "{error}"),
+        };
+        write!(f, "Rule '{rule}' {event}")?;
+        for (action, result) in results {
+            match result {
+                Ok(()) => write!(f, "\n  {action}: ok")?,
+                Err(err) => write!(f, "\n  {action}: {err}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct RuleState {
+    active: bool,
+    holding_since: Option<SystemTime>,
+}
+
+pub struct AutomationEngine<C: Clock = SystemClock> {
+    rules: Vec<(Rule, RuleState)>,
+    utc_offset: UtcOffset,
+    clock: C,
+}
+
+impl AutomationEngine<SystemClock> {
+    pub fn new(rules: RuleSet) -> Self {
+        Self::with_clock(rules, SystemClock)
+    }
+}
+
+impl<C: Clock> AutomationEngine<C> {
+    pub fn with_clock(rules: RuleSet, clock: C) -> Self {
+        Self {
+            rules: rules
+                .rules
+                .into_iter()
+                .map(|rule| (rule, RuleState::default()))
+                .collect(),
+            utc_offset: rules.utc_offset,
+            clock,
+        }
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter().map(|(rule, _)| rule)
+    }
+
+    pub fn is_active(&self, name: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|(rule, state)| rule.name == name && state.active)
+    }
+
+    // Evaluates every rule once and runs actions of the rules which changed state.
+    pub fn tick(&mut self, home: &Home) -> Vec<Outcome> {
+        let now = self.clock.now();
+        let date = DateTime::from_system_time(now, self.utc_offset);
+        let mut outcomes = Vec::new();
+        for (rule, state) in &mut self.rules {
+            let hysteresis = if state.active { rule.hysteresis } else { 0.0 };
+            let holds = rule.conditions.iter().try_fold(true, |holds, condition| {
+                Ok::<bool, SmartHomeError>(holds && condition.evaluate(home, &date, hysteresis)?)
+            });
+            let holds = match holds {
+                Ok(holds) => holds,
+                Err(error) => {
+                    outcomes.push(Outcome::Failed {
+                        rule: rule.name.clone(),
+                        error: error.in_rule(&rule.name),
+                    });
+                    continue;
+                }
+            };
+            let execute = |actions: &[Action]| {
+                actions
+                    .iter()
+                    .map(|action| (action.clone(), action.execute(home)))
+                    .collect()
+            };
+
+            match (holds, state.active) {
+                (true, false) => {
+                    let since = *state.holding_since.get_or_insert(now);
+                    if now.duration_since(since).unwrap_or_default() >= rule.sustain {
+                        state.active = true;
+                        outcomes.push(Outcome::Fired {
+                            rule: rule.name.clone(),
+                            results: execute(&rule.actions),
+                        });
+                    }
+                }
+                (false, true) => {
+                    state.active = false;
+                    state.holding_since = None;
+                    outcomes.push(Outcome::Released {
+                        rule: rule.name.clone(),
+                        results: execute(&rule.release_actions),
+                    });
+                }
+                (false, false) => state.holding_since = None,
+                (true, true) => {}
+            }
+        }
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::emulator::{EmulatedSensor, socket_home, socket_is_on};
+    use crate::{Room, SmartThermometer};
+    use std::thread;
+    use std::time::UNIX_EPOCH;
+
+    const RULES: &str = "
+utc_offset = +03:00
+
+[Cool down]
+when = Room2/Thermometer2 temperature > 26
+and = time 08:00-22:00
+for = 5m
+hysteresis = 0.5
+then = Room1/Socket1 on
+else = Room1/Socket1 off
+
+[Night]
+when = Room 1/Socket 1 is on
+then = Room 1/Socket 1 off
+";
+
+    #[test]
+    fn test_parse_rules() {
+        let set: RuleSet = RULES.parse().unwrap();
+        assert_eq!(set.utc_offset, UtcOffset::from_minutes(180));
+        assert_eq!(set.rules.len(), 2);
+        let rule = &set.rules[0];
+        assert_eq!(
+            rule.conditions[0],
+            Condition::Temperature {
+                device: DeviceRef::new("Room2", "Thermometer2"),
+                comparison: Comparison::Above,
+                value: 26.
+            }
+        );
+        assert_eq!(
+            rule.conditions[1],
+            Condition::Time {
+                from: TimeOfDay::new(8, 0),
+                to: TimeOfDay::new(22, 0)
+            }
+        );
+        assert_eq!(rule.sustain, Duration::from_secs(300));
+        assert_eq!(
+            rule.release_actions,
+            vec![Action::new(
+                DeviceRef::new("Room1", "Socket1"),
+                Command::Off
+            )]
+        );
+        assert_eq!(
+            set.rules[1].conditions[0],
+            Condition::SocketState {
+                device: DeviceRef::new("Room 1", "Socket 1"),
+                on: true
+            }
+        );
+
+        let reparsed: RuleSet = set.to_string().parse().unwrap();
+        assert_eq!(reparsed, set);
+
+        assert!("[Empty]\nthen = R/S on\n".parse::<RuleSet>().is_err());
+        assert!(
+            "[Bad]\nwhen = R/S humidity > 3\n"
+                .parse::<RuleSet>()
+                .is_err()
+        );
+        assert!(
+            "[Bad]\nwhen = R/S is on\nthen = R/S dance\n"
+                .parse::<RuleSet>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_engine() {
+        let mut home = socket_home("Room1", &["Socket1"]);
+        let mut room = Room::default();
+        let sensor = EmulatedSensor::new(27.0);
+        let thermometer = SmartThermometer::new(sensor.clone());
+        room.add_device("Thermometer2", thermometer.into());
+        home.add_room("Room2", room);
+        thread::sleep(Duration::from_millis(100));
+
+        // 10:00 at +03:00
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(7 * 3600));
+        let rules: RuleSet = RULES.parse().unwrap();
+        let rules = RuleSet {
+            rules: rules.rules[..1].to_vec(),
+            ..rules
+        };
+        let mut engine = AutomationEngine::with_clock(rules, clock.clone());
+        let socket = || socket_is_on(&home, "Room1", "Socket1");
+
+        assert!(engine.tick(&home).is_empty());
+        clock.advance(Duration::from_secs(240));
+        assert!(engine.tick(&home).is_empty());
+        clock.advance(Duration::from_secs(60));
+        let outcomes = engine.tick(&home);
+        assert!(matches!(&outcomes[..], [Outcome::Fired { results, .. }] if results[0].1.is_ok()));
+        assert!(engine.is_active("Cool down"));
+        assert!(socket());
+
+        // Inside the hysteresis band the rule stays fired
+        sensor.set(25.8);
+        thread::sleep(Duration::from_millis(1100));
+        assert!(engine.tick(&home).is_empty());
+
+        sensor.set(25.0);
+        thread::sleep(Duration::from_millis(1100));
+        let outcomes = engine.tick(&home);
+        assert!(matches!(&outcomes[..], [Outcome::Released { .. }]));
+        assert!(!socket());
+    }
+
+    #[test]
+    fn test_failed_rule() {
+        let home = Home::new("Test");
+        let rules: RuleSet = "[Rule]\nwhen = Room/Socket is on\n".parse().unwrap();
+        let mut engine = AutomationEngine::new(rules);
+        let outcomes = engine.tick(&home);
+        let [Outcome::Failed { error, .. }] = &outcomes[..] else {
+            panic!("rule should fail: {outcomes:?}");
+        };
+        assert!(matches!(error.root(), SmartHomeError::RoomNotFound(_)));
+        assert_eq!(
+            outcomes[0].to_string(),
+            "Rule 'Rule' failed: Room Room not found"
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use smart_home::{
+    Home,
+    dashboard::{Dashboard, RefreshTimer},
+};
+
+const HOME_CONFIG: &str = "home.cfg";
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+fn main() {
+    let config = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| HOME_CONFIG.to_string());
+    let home = match Home::load(&config) {
+        Ok(home) => home,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut terminal = ratatui::init();
+    let mut dashboard = Dashboard::new(&home);
+    let mut timer = RefreshTimer::new(REFRESH_INTERVAL);
+    let result = (|| -> std::io::Result<()> {
+        while !dashboard.should_quit() {
+            terminal.draw(|frame| dashboard.draw(frame))?;
+            if event::poll(timer.timeout())?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                dashboard.handle_key(key.code, &home);
+            }
+            if timer.tick() {
+                dashboard.refresh(&home);
+            }
+        }
+        Ok(())
+    })();
+    ratatui::restore();
+
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,27 @@
+use smart_home::emulator::{Fleet, FleetDescription};
+
+const DESCRIPTION: &str = "fleet.cfg";
+const HOME_CONFIG: &str = "home.cfg";
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let description_path = args.next().unwrap_or_else(|| DESCRIPTION.to_string());
+    let config_path = args.next().unwrap_or_else(|| HOME_CONFIG.to_string());
+
+    let description =
+        FleetDescription::load(&description_path).expect("Can't read fleet description");
+    let fleet = Fleet::new(&description).expect("Can't start fleet");
+    fleet
+        .config()
+        .save(&config_path)
+        .expect("Can't write home config");
+    println!(
+        "Fleet '{}' with {} devices is running, home config written to '{config_path}'",
+        description.name,
+        fleet.config().device_count()
+    );
+    if let Some(address) = fleet.discovery_address() {
+        println!("Answering discovery probes on {address}");
+    }
+    fleet.start().join();
+}
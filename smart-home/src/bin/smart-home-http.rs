@@ -0,0 +1,48 @@
+use std::sync::{Arc, RwLock};
+
+use smart_home::{
+    config::HomeConfig, http::HttpServer, metrics::MetricsServer, polling::Refresher,
+};
+
+const HOME_CONFIG: &str = "home.cfg";
+const LISTEN_ADDRESS: &str = "127.0.0.1:8080";
+const USAGE: &str = "Usage: smart-home-http [<config>] [<address>] [--metrics <address>]
+
+Metrics are always served on /metrics, --metrics serves them on a separate address too.";
+
+fn main() {
+    let mut positional = Vec::new();
+    let mut metrics_address = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--metrics" => match args.next() {
+                Some(address) => metrics_address = Some(address),
+                None => return eprintln!("{USAGE}"),
+            },
+            "--help" | "-h" => return println!("{USAGE}"),
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+    let config = positional.next().unwrap_or_else(|| HOME_CONFIG.to_string());
+    let address = positional
+        .next()
+        .unwrap_or_else(|| LISTEN_ADDRESS.to_string());
+
+    let home_config = HomeConfig::load(&config).expect("Can't load home");
+    let home = Arc::new(RwLock::new(home_config.connect().expect("Can't load home")));
+    // With socket caches enabled they are refreshed twice per max-age
+    let _refresher = home_config
+        .cache_max_age
+        .map(|max_age| Refresher::start(home.clone(), max_age / 2));
+    if let Some(metrics_address) = metrics_address {
+        let metrics =
+            MetricsServer::bind(&metrics_address, home.clone()).expect("can't bind metrics server");
+        println!("Serving metrics on http://{metrics_address}/metrics");
+        metrics.spawn();
+    }
+    let server = HttpServer::bind(&address, home).expect("can't bind http server");
+    println!("Serving home from '{config}' on http://{address}");
+    server.run().expect("http server failed");
+}
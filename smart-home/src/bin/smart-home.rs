@@ -0,0 +1,830 @@
+use std::{
+    path::Path,
+    process::ExitCode,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use smart_home::{
+    DeviceKind, Home, Report, Room, SmartHomeError,
+    automation::{Action, AutomationEngine, DeviceRef, Outcome, RuleSet},
+    clock::{self, DateTime, UtcOffset},
+    config::HomeConfig,
+    discovery,
+    errors::ErrorCode,
+    history::{Aggregate, History, Metric, Recorder, Sample},
+    json::Json,
+    polling::{self, REPORT_DEADLINE},
+    scenes::{Scene, SceneReport, SceneSet},
+    scheduler::{Run, Scheduler},
+    thermostat::Thermostat,
+};
+
+#[cfg(feature = "sqlite")]
+use smart_home::{database::Database, scheduler::ScheduleSet};
+
+const HOME_CONFIG: &str = "home.cfg";
+const FIRST_READING: Duration = Duration::from_secs(2);
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+const USAGE: &str = "Usage: smart-home [--config <path>] [--json] [--sorted] <command>
+
+Commands:
+  list                      rooms and devices of the home
+  report [<path>] [--deadline <seconds>]
+                            state of all devices or of devices matching the path,
+                            devices not answering within the deadline (5 seconds)
+                            are reported as timed out
+  switch <room> <device>    toggle a socket
+  on <room> <device>        turn a socket on
+  off <room> <device>       turn a socket off
+  switch|on|off <path>      the same for every socket matching the path
+  power <room> <device>     current power of a socket
+  temp <room> <device>      current temperature of a thermometer
+  watch [<room> <device>] [--interval <seconds>] [--deadline <seconds>]
+                            print state periodically until interrupted
+  automate <rules> [--interval <seconds>]
+                            run automation rules from the file until interrupted
+  schedule <schedules> [--interval <seconds>]
+                            run schedules from the file until interrupted,
+                            pending runs are saved back to the file
+  group <tag> [on|off|switch]
+                            report devices with the tag, optionally switching its sockets
+  scene <scenes> <name> [--rollback]
+                            apply a scene from the file, with --rollback nothing is
+                            left switched when some socket fails
+  capture <scenes> <name>   save current socket states as a scene into the file,
+                            sockets which can not be read are skipped
+  thermostat <room> <thermometer> <socket> <setpoint> [heat|cool] [--interval <seconds>]
+                            keep the room temperature with a socket driven heater or cooler
+  record <dir> [--retention <duration>] [--interval <seconds>]
+                            append device readings to day files in the directory until
+                            interrupted, files older than the retention are deleted
+  history <dir> <room> <device> temperature|power|on [--since <duration>] [--bucket <duration>]
+                            recorded readings of the last day or of the given duration,
+                            with --bucket as min/mean/max per bucket
+  import-db <db> [<schedules>]
+                            store the config, and optionally schedules, in a database
+                            (sqlite builds only)
+  discover [<room>] [--timeout <seconds>]
+                            list devices answering on the local network, with a room
+                            the new ones are added to it in the config
+
+Reports list rooms and devices in the config order, or by name with --sorted.
+In sqlite builds a config, record or history path ending in .db is a database.
+Paths are <room>/<device> with optional * and ? wildcards, like */Socket* or Room2/*.
+A \\ before a wildcard makes it literal.
+
+Exit codes:
+  0 success, 2 usage error, 3 config error, 4 room not found,
+  5 device not found, 6 command not supported by device, 7 connection error,
+  8 name already exists, 9 protocol error, 10 timeout, 11 invalid value,
+  12 automation error, 13 storage error";
+
+enum CliError {
+    Usage(String),
+    Home(SmartHomeError),
+}
+
+impl From<SmartHomeError> for CliError {
+    fn from(err: SmartHomeError) -> Self {
+        CliError::Home(err)
+    }
+}
+
+impl CliError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) => 2,
+            CliError::Home(err) => match err.code() {
+                ErrorCode::Config => 3,
+                ErrorCode::RoomNotFound => 4,
+                ErrorCode::DeviceNotFound => 5,
+                ErrorCode::WrongDeviceType => 6,
+                ErrorCode::Connection => 7,
+                ErrorCode::AlreadyExists => 8,
+                ErrorCode::Protocol => 9,
+                ErrorCode::Timeout => 10,
+                ErrorCode::Validation => 11,
+                ErrorCode::Automation => 12,
+                ErrorCode::Storage => 13,
+            },
+        }
+    }
+    fn kind(&self) -> &'static str {
+        match self {
+            CliError::Usage(_) => "usage",
+            CliError::Home(err) => err.code().as_str(),
+        }
+    }
+    fn message(&self) -> String {
+        match self {
+            CliError::Usage(msg) => msg.clone(),
+            CliError::Home(err) => err.to_string(),
+        }
+    }
+}
+
+struct Options {
+    config: String,
+    json: bool,
+    interval: Duration,
+    deadline: Duration,
+    sorted: bool,
+    help: bool,
+    command: Vec<String>,
+}
+
+fn discover(options: &Options, args: &[&str]) -> Result<(), CliError> {
+    let (room, timeout) = match args {
+        [] => (None, DISCOVERY_TIMEOUT),
+        [room] => (Some(room), DISCOVERY_TIMEOUT),
+        [rest @ .., "--timeout", seconds] if rest.len() <= 1 => {
+            let seconds =
+                seconds
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|s| *s > 0.0)
+                    .ok_or(CliError::Usage(
+                        "--timeout needs a positive number of seconds".to_string(),
+                    ))?;
+            (rest.first(), Duration::from_secs_f64(seconds))
+        }
+        _ => return Err(CliError::Usage(USAGE.to_string())),
+    };
+    let candidates = discovery::discover(timeout)?;
+    let mut text: Vec<String> = candidates.iter().map(ToString::to_string).collect();
+    if let Some(room) = room {
+        let mut config = match HomeConfig::load(&options.config) {
+            Err(SmartHomeError::ConfigError(_)) if !Path::new(&options.config).exists() => {
+                HomeConfig::new("Home")
+            }
+            config => config?,
+        };
+        let added = config.add_discovered(room, &candidates);
+        if added > 0 {
+            config.save(&options.config)?;
+        }
+        text.push(format!("{added} new devices added to {room}"));
+    }
+    if options.json {
+        let json: Vec<Json> = candidates
+            .iter()
+            .map(|candidate| {
+                Json::object()
+                    .with("kind", candidate.kind.to_string())
+                    .with("id", &candidate.id)
+                    .with("address", candidate.address.to_string())
+            })
+            .collect();
+        println!("{}", Json::from(json));
+    } else if candidates.is_empty() {
+        println!("No devices found");
+    } else {
+        println!("{}", text.join("\n"));
+    }
+    Ok(())
+}
+
+fn history(options: &Options, args: &[&str]) -> Result<(), CliError> {
+    let [dir, room, device, metric, flags @ ..] = args else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+    let metric: Metric = metric.parse()?;
+    let mut since = Duration::from_secs(86400);
+    let mut bucket = None;
+    for pair in flags.chunks(2) {
+        match pair {
+            ["--since", duration] => since = clock::parse_duration(duration)?,
+            ["--bucket", duration] => bucket = Some(clock::parse_duration(duration)?),
+            _ => return Err(CliError::Usage(USAGE.to_string())),
+        }
+    }
+    let to = SystemTime::now();
+    let from = to.checked_sub(since).unwrap_or(UNIX_EPOCH);
+    let (text, json): (Vec<String>, Vec<Json>) = match bucket {
+        None => range(dir, room, device, metric, from, to)?
+            .iter()
+            .map(|sample| {
+                (
+                    format!("{} {}", timestamp(sample.time), sample.value),
+                    Json::object()
+                        .with("time", timestamp(sample.time))
+                        .with("value", sample.value),
+                )
+            })
+            .unzip(),
+        Some(bucket) => aggregate(dir, room, device, metric, from, to, bucket)?
+            .iter()
+            .map(|aggregate| {
+                (
+                    format!(
+                        "{} min {:.2} mean {:.2} max {:.2} ({} readings)",
+                        timestamp(aggregate.start),
+                        aggregate.min,
+                        aggregate.mean,
+                        aggregate.max,
+                        aggregate.count
+                    ),
+                    Json::object()
+                        .with("start", timestamp(aggregate.start))
+                        .with("count", aggregate.count)
+                        .with("min", aggregate.min)
+                        .with("mean", aggregate.mean)
+                        .with("max", aggregate.max),
+                )
+            })
+            .unzip(),
+    };
+    if options.json {
+        println!("{}", Json::from(json));
+    } else if text.is_empty() {
+        println!("No readings of {room}/{device} {metric}");
+    } else {
+        println!("{}", text.join("\n"));
+    }
+    Ok(())
+}
+
+// Paths ending in .db are databases, others config files and history directories.
+#[cfg(feature = "sqlite")]
+fn is_database(path: &str) -> bool {
+    path.ends_with(".db")
+}
+
+fn load_home(path: &str) -> Result<Home, SmartHomeError> {
+    #[cfg(feature = "sqlite")]
+    if is_database(path) {
+        return Database::open(path)?.load_home();
+    }
+    Home::load(path)
+}
+
+fn range(
+    path: &str,
+    room: &str,
+    device: &str,
+    metric: Metric,
+    from: SystemTime,
+    to: SystemTime,
+) -> Result<Vec<Sample>, SmartHomeError> {
+    #[cfg(feature = "sqlite")]
+    if is_database(path) {
+        return Database::open(path)?.range(room, device, metric, from, to);
+    }
+    History::open(path).range(room, device, metric, from, to)
+}
+
+fn aggregate(
+    path: &str,
+    room: &str,
+    device: &str,
+    metric: Metric,
+    from: SystemTime,
+    to: SystemTime,
+    bucket: Duration,
+) -> Result<Vec<Aggregate>, SmartHomeError> {
+    #[cfg(feature = "sqlite")]
+    if is_database(path) {
+        return Database::open(path)?.aggregate(room, device, metric, from, to, bucket);
+    }
+    History::open(path).aggregate(room, device, metric, from, to, bucket)
+}
+
+#[cfg(feature = "sqlite")]
+fn import_db(options: &Options, args: &[&str]) -> Result<(), CliError> {
+    let (path, schedules) = match args {
+        [path] => (path, None),
+        [path, schedules] => (path, Some(ScheduleSet::load(schedules)?)),
+        _ => return Err(CliError::Usage(USAGE.to_string())),
+    };
+    let config = HomeConfig::load(&options.config)?;
+    let mut database = Database::open(path)?;
+    database.save_config(&config)?;
+    let mut json = Json::object()
+        .with("rooms", config.rooms.len())
+        .with("devices", config.device_count());
+    let mut text = format!(
+        "Stored {} rooms and {} devices in {path}",
+        config.rooms.len(),
+        config.device_count()
+    );
+    if let Some(schedules) = schedules {
+        database.save_schedules(&schedules)?;
+        json = json.with("schedules", schedules.schedules.len());
+        text += &format!(", {} schedules", schedules.schedules.len());
+    }
+    if options.json {
+        println!("{json}");
+    } else {
+        println!("{text}");
+    }
+    Ok(())
+}
+
+// UTC time like `2025-06-02 07:00:00`.
+fn timestamp(time: SystemTime) -> String {
+    let time = DateTime::from_system_time(time, UtcOffset::default());
+    format!(
+        "{:04}-{:02}-{:02} {}:{:02}",
+        time.year, time.month, time.day, time.time, time.second
+    )
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, CliError> {
+    let mut options = Options {
+        config: HOME_CONFIG.to_string(),
+        json: false,
+        interval: Duration::from_secs(1),
+        deadline: REPORT_DEADLINE,
+        sorted: false,
+        help: false,
+        command: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => {
+                options.config = args
+                    .next()
+                    .ok_or(CliError::Usage("--config needs a path".to_string()))?
+            }
+            "--json" => options.json = true,
+            "--sorted" => options.sorted = true,
+            "--interval" => {
+                let seconds = args
+                    .next()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .filter(|s| *s > 0.0)
+                    .ok_or(CliError::Usage(
+                        "--interval needs a positive number of seconds".to_string(),
+                    ))?;
+                options.interval = Duration::from_secs_f64(seconds);
+            }
+            "--deadline" => {
+                let seconds = args
+                    .next()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .filter(|s| *s > 0.0)
+                    .ok_or(CliError::Usage(
+                        "--deadline needs a positive number of seconds".to_string(),
+                    ))?;
+                options.deadline = Duration::from_secs_f64(seconds);
+            }
+            "--help" | "-h" => options.help = true,
+            _ => options.command.push(arg),
+        }
+    }
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err.message());
+            return ExitCode::from(err.exit_code());
+        }
+    };
+    if options.help {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            if options.json {
+                let json = Json::object()
+                    .with("error", err.message())
+                    .with("code", err.exit_code() as usize)
+                    .with("kind", err.kind());
+                eprintln!("{json}");
+            } else {
+                eprintln!("{}", err.message());
+            }
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<(), CliError> {
+    let command: Vec<&str> = options.command.iter().map(String::as_str).collect();
+    if command.is_empty() {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+    if let ["discover", rest @ ..] = command.as_slice() {
+        return discover(options, rest);
+    }
+    if let ["history", rest @ ..] = command.as_slice() {
+        return history(options, rest);
+    }
+    #[cfg(feature = "sqlite")]
+    if let ["import-db", rest @ ..] = command.as_slice() {
+        return import_db(options, rest);
+    }
+    let mut home = load_home(&options.config)?;
+    if options.sorted {
+        home.sort_rooms();
+        let rooms: Vec<String> = home.room_names().map(str::to_string).collect();
+        for room in rooms {
+            home.get_room_mut(&room).map(Room::sort_devices);
+        }
+    }
+    let has_thermometers = home
+        .devices()
+        .any(|(_, _, device)| device.kind() == DeviceKind::Thermometer);
+    if has_thermometers
+        && matches!(
+            command[0],
+            "report" | "temp" | "watch" | "automate" | "thermostat" | "record"
+        )
+    {
+        thread::sleep(FIRST_READING); // Что бы термометры успели получить значения
+    }
+    let output = |text: String, json: Json| {
+        if options.json {
+            println!("{json}");
+        } else {
+            println!("{text}");
+        }
+    };
+
+    match command.as_slice() {
+        ["list"] => output(list(&home), list_json(&home)),
+        ["report"] => {
+            let snapshot = home.poll(options.deadline);
+            output(
+                home.snapshot_report(&snapshot),
+                Json::snapshot(&home, &snapshot),
+            );
+        }
+        ["report", path] => {
+            let path: DeviceRef = path.parse()?;
+            let mut text = String::new();
+            let mut devices = Vec::new();
+            let resolved = home.resolve(&path)?;
+            let snapshot = polling::poll(resolved.iter().copied(), options.deadline);
+            for (room, name, device) in resolved {
+                let polled = snapshot.get(room, name);
+                text.push_str(&format!(
+                    "{room}/{name}: {}\n",
+                    polled.map_or(String::new(), Report::report)
+                ));
+                devices.push(Json::polled(name, device, polled).with("room", room));
+            }
+            output(text.trim_end().to_string(), Json::Array(devices));
+        }
+        ["switch" | "on" | "off", path] => {
+            let action = Action::new(path.parse()?, command[0].parse()?);
+            let result = action.execute(&home);
+            for (room, name, device) in home.resolve(&action.device)? {
+                if let Ok(on) = device.as_socket().and_then(|socket| socket.is_on()) {
+                    output(
+                        format!("{room}/{name}: {}", if on { "on" } else { "off" }),
+                        device_json(room, name).with("on", on),
+                    );
+                }
+            }
+            result?;
+        }
+        ["switch" | "on" | "off", room, device] => {
+            let socket = home.get_device(room, device)?.as_socket()?;
+            match command[0] {
+                "switch" => socket.switch()?,
+                "on" => socket.turn_on()?,
+                _ => socket.turn_off()?,
+            }
+            let on = socket.is_on()?;
+            output(
+                format!("{room}/{device}: {}", if on { "on" } else { "off" }),
+                device_json(room, device).with("on", on),
+            );
+        }
+        ["power", room, device] => {
+            let power = home.get_device(room, device)?.as_socket()?.get_power()?;
+            output(
+                format!("{power:.2}"),
+                device_json(room, device).with("power", power),
+            );
+        }
+        ["temp", room, device] => {
+            let temperature = home
+                .get_device(room, device)?
+                .as_thermometer()?
+                .get_temperature();
+            output(
+                format!("{temperature:.2}"),
+                device_json(room, device).with("temperature", temperature),
+            );
+        }
+        ["watch"] => loop {
+            let snapshot = home.poll(options.deadline);
+            output(
+                home.snapshot_report(&snapshot),
+                Json::snapshot(&home, &snapshot),
+            );
+            thread::sleep(options.interval);
+        },
+        ["watch", room, device] => loop {
+            let device_ref = home.get_device(room, device)?;
+            let json = Json::device(device, device_ref).with("room", *room);
+            output(format!("{room}/{device}: {}", device_ref.report()), json);
+            thread::sleep(options.interval);
+        },
+        ["record", dir, rest @ ..] if matches!(rest, [] | ["--retention", _]) => {
+            let retention = match rest {
+                [_, retention] => Some(clock::parse_duration(retention)?),
+                _ => None,
+            };
+            #[cfg(feature = "sqlite")]
+            if is_database(dir) {
+                let mut database = Database::open(dir)?;
+                loop {
+                    let snapshot = home.poll(options.deadline);
+                    let now = SystemTime::now();
+                    let recorded = database.record_snapshot(&snapshot, now)?;
+                    if let Some(before) = retention.and_then(|retention| now.checked_sub(retention))
+                    {
+                        database.remove_before(before)?;
+                    }
+                    output(
+                        format!("{recorded} readings recorded"),
+                        Json::object().with("recorded", recorded),
+                    );
+                    thread::sleep(options.interval);
+                }
+            }
+            let mut recorder = Recorder::open(dir)?;
+            if let Some(retention) = retention {
+                recorder = recorder.with_retention(retention);
+            }
+            loop {
+                let snapshot = home.poll(options.deadline);
+                let recorded = recorder.record_snapshot(&snapshot)?;
+                output(
+                    format!("{recorded} readings recorded"),
+                    Json::object().with("recorded", recorded),
+                );
+                thread::sleep(options.interval);
+            }
+        }
+        ["automate", rules] => {
+            let mut engine = AutomationEngine::new(RuleSet::load(rules)?);
+            loop {
+                for outcome in engine.tick(&home) {
+                    output(outcome.to_string(), outcome_json(&outcome));
+                }
+                thread::sleep(options.interval);
+            }
+        }
+        ["schedule", path] => {
+            let mut scheduler = Scheduler::load(path)?;
+            scheduler.save(path)?;
+            loop {
+                let runs = scheduler.run_due(&home);
+                for run in &runs {
+                    output(run.to_string(), run_json(run));
+                }
+                if !runs.is_empty() {
+                    scheduler.save(path)?;
+                }
+                thread::sleep(options.interval);
+            }
+        }
+        ["group", tag, command @ ..] if command.len() <= 1 => {
+            let group = home.group(tag);
+            if group.is_empty() {
+                return Err(CliError::Usage(format!("No devices tagged '{tag}'")));
+            }
+            let mut failed = None;
+            if let [command] = command {
+                for (device, result) in group.execute(command.parse()?) {
+                    if let Err(err) = result {
+                        eprintln!("{device}: {err}");
+                        failed.get_or_insert(err);
+                    }
+                }
+            }
+            let power = group.power();
+            let members: Vec<Json> = group
+                .members
+                .iter()
+                .map(|(device, member)| {
+                    Json::device(&device.device, member).with("room", &device.room)
+                })
+                .collect();
+            output(
+                group.report(),
+                Json::object()
+                    .with("tag", *tag)
+                    .with("devices", members)
+                    .with("power", power.total),
+            );
+            if let Some(err) = failed {
+                return Err(err.into());
+            }
+        }
+        ["scene", path, name, rest @ ..] if matches!(rest, [] | ["--rollback"]) => {
+            let scenes = SceneSet::load(path)?;
+            let scene = scenes
+                .get(name)
+                .ok_or(CliError::Usage(format!("Unknown scene '{name}'")))?;
+            let report = scene.apply(&home, !rest.is_empty());
+            output(report.to_string(), scene_json(&report));
+            if !report.is_success() {
+                // Exit code of the first failure
+                if let Some(Err(err)) = report
+                    .results
+                    .into_iter()
+                    .map(|r| r.result)
+                    .find(Result::is_err)
+                {
+                    return Err(err.into());
+                }
+            }
+        }
+        ["capture", path, name] => {
+            let mut scenes = match SceneSet::load(path) {
+                Err(SmartHomeError::ConfigError(_)) if !Path::new(path).exists() => {
+                    SceneSet::default()
+                }
+                scenes => scenes?,
+            };
+            let (scene, skipped) = Scene::capture(*name, &home);
+            let count = scene.sockets.len();
+            scenes.insert(scene);
+            scenes.save(path)?;
+            let mut text = format!("Scene '{name}' captured with {count} sockets");
+            for (device, err) in &skipped {
+                text.push_str(&format!("\n{device} skipped: {err}"));
+            }
+            let skipped: Vec<Json> = skipped
+                .iter()
+                .map(|(device, err)| {
+                    device_json(&device.room, &device.device).with("error", err.to_string())
+                })
+                .collect();
+            output(
+                text,
+                Json::object()
+                    .with("scene", *name)
+                    .with("sockets", count)
+                    .with("skipped", skipped),
+            );
+        }
+        ["thermostat", room, thermometer, socket, setpoint, mode @ ..] if mode.len() <= 1 => {
+            let setpoint = setpoint
+                .parse()
+                .map_err(|_| CliError::Usage(format!("Invalid setpoint '{setpoint}'")))?;
+            let mut thermostat = Thermostat::new(
+                DeviceRef::new(*room, *thermometer),
+                DeviceRef::new(*room, *socket),
+                setpoint,
+            );
+            if let [mode] = mode {
+                thermostat.mode = mode.parse()?;
+            }
+            loop {
+                // Errors are reported and retried on the next tick
+                let _ = thermostat.tick(&home);
+                output(
+                    thermostat.report(),
+                    device_json(room, socket)
+                        .with("thermometer", *thermometer)
+                        .with("report", thermostat.report()),
+                );
+                thread::sleep(options.interval);
+            }
+        }
+        _ => return Err(CliError::Usage(USAGE.to_string())),
+    }
+    Ok(())
+}
+
+fn outcome_json(outcome: &Outcome) -> Json {
+    let (rule, event, results) = match outcome {
+        Outcome::Fired { rule, results } => (rule, "fired", results),
+        Outcome::Released { rule, results } => (rule, "released", results),
+        Outcome::Failed { rule, error } => {
+            return Json::object()
+                .with("rule", rule)
+                .with("event", "failed")
+                .with("error", error.to_string());
+        }
+    };
+    let results: Vec<Json> = results
+        .iter()
+        .map(|(action, result)| {
+            let json = Json::object().with("action", action.to_string());
+            match result {
+                Ok(()) => json,
+                Err(err) => json.with("error", err.to_string()),
+            }
+        })
+        .collect();
+    Json::object()
+        .with("rule", rule)
+        .with("event", event)
+        .with("results", results)
+}
+
+fn run_json(run: &Run) -> Json {
+    match run {
+        Run::Executed {
+            schedule,
+            action,
+            late,
+            result,
+            ..
+        } => {
+            let json = Json::object()
+                .with("schedule", schedule)
+                .with("event", "executed")
+                .with("action", action.to_string())
+                .with("late", *late);
+            match result {
+                Ok(()) => json,
+                Err(err) => json.with("error", err.to_string()),
+            }
+        }
+        Run::Skipped {
+            schedule, action, ..
+        } => Json::object()
+            .with("schedule", schedule)
+            .with("event", "skipped")
+            .with("action", action.to_string()),
+    }
+}
+
+fn scene_json(report: &SceneReport) -> Json {
+    let results: Vec<Json> = report
+        .results
+        .iter()
+        .map(|result| {
+            let json = Json::object()
+                .with("room", &result.device.room)
+                .with("device", &result.device.device)
+                .with("on", result.on)
+                .with("previous", result.previous);
+            let json = match &result.result {
+                Ok(()) => json,
+                Err(err) => json.with("error", err.to_string()),
+            };
+            match &result.restored {
+                Some(Ok(())) => json.with("restored", true),
+                Some(Err(err)) => json
+                    .with("restored", false)
+                    .with("restore_error", err.to_string()),
+                None => json,
+            }
+        })
+        .collect();
+    Json::object()
+        .with("scene", &report.scene)
+        .with("success", report.is_success())
+        .with("rolled_back", report.rolled_back)
+        .with("results", results)
+}
+
+fn device_json(room: &str, device: &str) -> Json {
+    Json::object().with("room", room).with("device", device)
+}
+
+fn list(home: &Home) -> String {
+    let mut list = format!("Home: {}\n", home.name);
+    for (room_name, room) in home {
+        list.push_str(&format!("{room_name}\n"));
+        for (device_name, device) in room {
+            let tags: Vec<&str> = room.device_tags(device_name).map(String::as_str).collect();
+            match tags.is_empty() {
+                true => list.push_str(&format!("  {device_name} ({})\n", device.kind())),
+                false => list.push_str(&format!(
+                    "  {device_name} ({}) [{}]\n",
+                    device.kind(),
+                    tags.join(", ")
+                )),
+            }
+        }
+    }
+    list
+}
+
+fn list_json(home: &Home) -> Json {
+    let rooms: Vec<Json> = home
+        .into_iter()
+        .map(|(room_name, room)| {
+            let devices: Vec<Json> = room
+                .into_iter()
+                .map(|(device_name, device)| {
+                    let tags: Vec<&String> = room.device_tags(device_name).collect();
+                    Json::object()
+                        .with("name", device_name)
+                        .with("kind", device.kind().to_string())
+                        .with("tags", tags)
+                })
+                .collect();
+            Json::object()
+                .with("name", room_name)
+                .with("devices", devices)
+        })
+        .collect();
+    Json::object().with("name", &home.name).with("rooms", rooms)
+}
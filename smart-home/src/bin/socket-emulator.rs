@@ -3,7 +3,11 @@ use std::{
     net::{SocketAddr, TcpListener},
 };
 
-use smart_home::devices::smartsocket::{SocketCommand, SocketResponse};
+use smart_home::{
+    DeviceKind,
+    discovery::{Announcement, DISCOVERY_PORT, Responder},
+    emulator::EmulatedSocket,
+};
 
 fn main() {
     let mut args = std::env::args();
@@ -15,7 +19,20 @@ fn main() {
         .parse::<SocketAddr>()
         .expect("invalid socket address");
     let listener = TcpListener::bind(server_address).expect("can't bind tcp listener");
-    let mut smart_socket = MockScoket::new();
+    let announcement = Announcement::new(
+        DeviceKind::Socket,
+        format!("socket-{}", server_address.port()),
+        server_address.port(),
+    );
+    // Emulators of the same host share the discovery port.
+    let discovery = SocketAddr::from(([0, 0, 0, 0], DISCOVERY_PORT));
+    std::thread::spawn(move || {
+        println!(
+            "Discovery stopped: {}",
+            Responder::share(discovery, vec![announcement])
+        )
+    });
+    let mut smart_socket = EmulatedSocket::with_id(format!("socket-{}", server_address.port()));
     while let Some(connection) = listener.incoming().next() {
         let mut stream = match connection {
             Ok(conn) => conn,
@@ -34,8 +51,7 @@ fn main() {
         let mut in_buffer = [0u8];
         while stream.read_exact(&mut in_buffer).is_ok() {
             let response = smart_socket.process_command(in_buffer[0].into());
-            let response_buf: [u8; 5] = response.into();
-            if stream.write_all(&response_buf).is_err() {
+            if stream.write_all(&response.to_bytes()).is_err() {
                 break;
             };
         }
@@ -43,29 +59,3 @@ fn main() {
         println!("Connection with {peer} lost. Waiting for new connections...");
     }
 }
-
-struct MockScoket {
-    is_on: bool,
-    power: f32,
-}
-
-impl MockScoket {
-    pub fn new() -> Self {
-        Self {
-            is_on: false,
-            power: 0.0,
-        }
-    }
-    pub fn process_command(&mut self, command: SocketCommand) -> SocketResponse {
-        match command {
-            SocketCommand::Switch => {
-                self.is_on = !self.is_on;
-                self.power = if self.is_on { 1000. } else { 0.0 };
-                SocketResponse::On(self.is_on)
-            }
-            SocketCommand::GetPower => SocketResponse::Power(self.power),
-            SocketCommand::IsOn => SocketResponse::On(self.is_on),
-            _ => SocketResponse::Unknown,
-        }
-    }
-}
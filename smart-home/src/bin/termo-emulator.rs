@@ -5,6 +5,11 @@ use std::{
     time::Duration,
 };
 
+use smart_home::{
+    DeviceKind,
+    discovery::{Announcement, DISCOVERY_PORT, Responder},
+};
+
 const CONFIG: &str = "termo-emulator.cfg";
 const TERMO_EMULATOR_IP: &str = "127.0.0.1:4322";
 fn main() {
@@ -13,6 +18,20 @@ fn main() {
         .parse::<SocketAddr>()
         .expect("invalid socket address");
 
+    let announcement = Announcement::new(
+        DeviceKind::Thermometer,
+        format!("thermometer-{}", ip.port()),
+        ip.port(),
+    );
+    // Emulators of the same host share the discovery port.
+    let discovery = SocketAddr::from(([0, 0, 0, 0], DISCOVERY_PORT));
+    thread::spawn(move || {
+        println!(
+            "Discovery stopped: {}",
+            Responder::share(discovery, vec![announcement])
+        )
+    });
+
     let socket = UdpSocket::bind(TERMO_EMULATOR_IP).unwrap();
     socket
         .set_read_timeout(Some(Duration::from_secs(1)))
@@ -0,0 +1,430 @@
+use crate::SmartHomeError;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+// Clock moved by hand, clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(now)))
+    }
+    pub fn set(&self, now: SystemTime) {
+        match self.0.lock() {
+            Ok(mut g) => *g = now,
+            Err(poison_error) => *poison_error.into_inner() = now,
+        }
+    }
+    pub fn advance(&self, duration: Duration) {
+        self.set(self.now() + duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        match self.0.lock() {
+            Ok(g) => *g,
+            Err(poison_error) => *poison_error.into_inner(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl TimeOfDay {
+    pub fn new(hour: u8, minute: u8) -> Self {
+        Self { hour, minute }
+    }
+    pub fn minutes(&self) -> u32 {
+        self.hour as u32 * 60 + self.minute as u32
+    }
+    // Checks `self` is in [from, to), intervals may pass midnight.
+    pub fn is_between(&self, from: TimeOfDay, to: TimeOfDay) -> bool {
+        if from <= to {
+            from <= *self && *self < to
+        } else {
+            from <= *self || *self < to
+        }
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SmartHomeError::ConfigError(format!("Invalid time '{s}', expected HH:MM"));
+        let (hour, minute) = s.trim().split_once(':').ok_or_else(invalid)?;
+        let hour: u8 = hour.parse().map_err(|_| invalid())?;
+        let minute: u8 = minute.parse().map_err(|_| invalid())?;
+        if hour > 23 || minute > 59 {
+            return Err(invalid());
+        }
+        Ok(TimeOfDay { hour, minute })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    pub const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for Weekday {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"][self.index()];
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Weekday {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        let full_names = [
+            "monday",
+            "tuesday",
+            "wednesday",
+            "thursday",
+            "friday",
+            "saturday",
+            "sunday",
+        ];
+        Weekday::ALL
+            .into_iter()
+            .find(|day| day.to_string() == name || full_names[day.index()] == name)
+            .ok_or(SmartHomeError::ConfigError(format!(
+                "Invalid weekday '{s}'"
+            )))
+    }
+}
+
+// Calendar date without time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    pub fn new(year: i64, month: u8, day: u8) -> Self {
+        Self { year, month, day }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl FromStr for Date {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || SmartHomeError::ConfigError(format!("Invalid date '{s}', expected YYYY-MM-DD"));
+        let mut parts = s.trim().splitn(3, '-').map(str::parse::<i64>);
+        let (Some(Ok(year @ 1..=9999)), Some(Ok(month @ 1..=12)), Some(Ok(day @ 1..=31))) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let date = Date::new(year, month as u8, day as u8);
+        // Days past the end of the month like 02-30 come back as another date
+        if civil_from_days(days_from_civil(date.year, date.month, date.day))
+            != (date.year, date.month, date.day)
+        {
+            return Err(invalid());
+        }
+        Ok(date)
+    }
+}
+
+// Calendar time at a fixed offset from UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub weekday: Weekday,
+    pub time: TimeOfDay,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn from_system_time(time: SystemTime, utc_offset: UtcOffset) -> DateTime {
+        let seconds = unix_seconds(time) + utc_offset.seconds();
+        let days = seconds.div_euclid(86400);
+        let of_day = seconds.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            weekday: weekday_from_days(days),
+            time: TimeOfDay::new((of_day / 3600) as u8, (of_day % 3600 / 60) as u8),
+            second: (of_day % 60) as u8,
+        }
+    }
+    // Start of the minute `time` on the given day.
+    pub fn to_system_time(
+        year: i64,
+        month: u8,
+        day: u8,
+        time: TimeOfDay,
+        utc_offset: UtcOffset,
+    ) -> SystemTime {
+        from_unix_seconds(
+            days_from_civil(year, month, day) * 86400 + time.minutes() as i64 * 60
+                - utc_offset.seconds(),
+        )
+    }
+}
+
+pub fn from_unix_seconds(seconds: i64) -> SystemTime {
+    if seconds >= 0 {
+        UNIX_EPOCH + Duration::from_secs(seconds as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+    }
+}
+
+pub fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs_f64().ceil() as i64),
+    }
+}
+
+// Days since 1970-01-01 to (year, month, day), see http://howardhinnant.github.io/date_algorithms.html
+pub(crate) fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// (year, month, day) to days since 1970-01-01.
+pub(crate) fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+pub(crate) fn weekday_from_days(days: i64) -> Weekday {
+    // 1970-01-01 was a Thursday
+    Weekday::ALL[(days + 3).rem_euclid(7) as usize]
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UtcOffset(i32);
+
+impl UtcOffset {
+    pub fn from_minutes(minutes: i32) -> Self {
+        Self(minutes)
+    }
+    pub fn seconds(&self) -> i64 {
+        self.0 as i64 * 60
+    }
+}
+
+impl fmt::Display for UtcOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { '-' } else { '+' };
+        write!(f, "{sign}{:02}:{:02}", self.0.abs() / 60, self.0.abs() % 60)
+    }
+}
+
+impl FromStr for UtcOffset {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || SmartHomeError::ConfigError(format!("Invalid UTC offset '{s}', expected +HH:MM"));
+        let s = s.trim();
+        let (sign, rest) = match s.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let time: TimeOfDay = rest.parse().map_err(|_| invalid())?;
+        Ok(UtcOffset(sign * time.minutes() as i32))
+    }
+}
+
+// Longest accepted duration, longer ones would overflow when added to a time.
+const MAX_DURATION: Duration = Duration::from_secs(100 * 365 * 86400);
+
+// Parses durations like `90`, `30s`, `5m`, `2h`, `1d`.
+pub fn parse_duration(s: &str) -> Result<Duration, SmartHomeError> {
+    let s = s.trim();
+    let invalid = || SmartHomeError::ConfigError(format!("Invalid duration '{s}'"));
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let value: u64 = s[..split].parse().map_err(|_| invalid())?;
+    let unit = match s[split..].trim() {
+        "" | "s" | "sec" | "seconds" => 1,
+        "m" | "min" | "minutes" => 60,
+        "h" | "hours" => 3600,
+        "d" | "days" => 86400,
+        _ => return Err(invalid()),
+    };
+    value
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .filter(|duration| *duration <= MAX_DURATION)
+        .ok_or(SmartHomeError::ValidationError(format!(
+            "duration '{s}' is too long"
+        )))
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        s if s != 0 && s % 86400 == 0 => format!("{}d", s / 86400),
+        s if s != 0 && s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s != 0 && s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{s}s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_time() {
+        // 2024-02-29 13:45:10 UTC, Thursday
+        let time = UNIX_EPOCH + Duration::from_secs(1709214310);
+        let date = DateTime::from_system_time(time, UtcOffset::default());
+        assert_eq!((date.year, date.month, date.day), (2024, 2, 29));
+        assert_eq!(date.weekday, Weekday::Thursday);
+        assert_eq!(date.time, TimeOfDay::new(13, 45));
+        assert_eq!(date.second, 10);
+
+        let date = DateTime::from_system_time(time, "+11:30".parse().unwrap());
+        assert_eq!((date.month, date.day), (3, 1));
+        assert_eq!(date.weekday, Weekday::Friday);
+        assert_eq!(date.time, TimeOfDay::new(1, 15));
+    }
+
+    #[test]
+    fn test_days_roundtrip() {
+        for days in [-800_000, -1, 0, 59, 19_782, 2_000_000] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        let offset: UtcOffset = "+03:00".parse().unwrap();
+        let time = DateTime::to_system_time(2024, 2, 29, TimeOfDay::new(16, 45), offset);
+        assert_eq!(unix_seconds(time), 1709214300);
+        assert_eq!(from_unix_seconds(-60), UNIX_EPOCH - Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_date() {
+        let date: Date = "2024-02-29".parse().unwrap();
+        assert_eq!(date, Date::new(2024, 2, 29));
+        assert_eq!(date.to_string(), "2024-02-29");
+        assert!("2023-02-29".parse::<Date>().is_err());
+        assert!("2024-13-01".parse::<Date>().is_err());
+        assert!("yesterday".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn test_time_of_day() {
+        let time: TimeOfDay = "07:05".parse().unwrap();
+        assert_eq!(time, TimeOfDay::new(7, 5));
+        assert_eq!(time.to_string(), "07:05");
+        assert!("24:00".parse::<TimeOfDay>().is_err());
+        assert!(time.is_between(TimeOfDay::new(7, 0), TimeOfDay::new(8, 0)));
+        assert!(time.is_between(TimeOfDay::new(22, 0), TimeOfDay::new(8, 0)));
+        assert!(!time.is_between(TimeOfDay::new(8, 0), TimeOfDay::new(22, 0)));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(
+            parse_duration("30 minutes").unwrap(),
+            Duration::from_secs(1800)
+        );
+        assert!(parse_duration("soon").is_err());
+        assert!(matches!(
+            parse_duration("18446744073709551615d"),
+            Err(SmartHomeError::ValidationError(_))
+        ));
+        assert!(parse_duration("200000000000000d").is_err());
+        assert!(parse_duration("36500d").is_ok());
+        assert!("999999999999-01-01".parse::<Date>().is_err());
+        assert!("9999-12-31".parse::<Date>().is_ok());
+        assert_eq!(format_duration(Duration::from_secs(7200)), "2h");
+        assert_eq!("Saturday".parse::<Weekday>().unwrap(), Weekday::Saturday);
+        assert_eq!("sat".parse::<Weekday>().unwrap(), Weekday::Saturday);
+        assert!("monkey".parse::<Weekday>().is_err());
+        assert!("thurs".parse::<Weekday>().is_err());
+        assert_eq!("-03:30".parse::<UtcOffset>().unwrap(), UtcOffset(-210));
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let shared = clock.clone();
+        shared.advance(Duration::from_secs(60));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(60));
+    }
+}
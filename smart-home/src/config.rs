@@ -0,0 +1,438 @@
+use crate::clock::UtcOffset;
+use crate::devices::SmartDeviceConnect;
+use crate::metadata::{DeviceInfo, RoomInfo};
+use crate::{Home, Room, SmartDevice, SmartHomeError, SmartSocket, SmartThermometer};
+use std::{fmt, fs, path::Path, str::FromStr, time::Duration};
+
+// Home config is a simple ini-like text file:
+//
+//   name = Home
+//   cache_max_age_ms = 2000
+//   [Room1]
+//   Socket1 = socket 127.0.0.1:4331
+//   Thermometer2 = thermometer 127.0.0.1:4321
+//   Heater = socket 127.0.0.1:4332 [heaters, critical]
+//
+// Optional list in brackets after the address holds the device tags.
+// Metadata of the room is set with `.<field> = value` and of a device declared above
+// with `<device>.<field> = value`:
+//
+//   [Living]
+//   .display_name = Living room
+//   .floor = 1
+//   .area = 24.5
+//   Lamp = socket 127.0.0.1:4333
+//   Lamp.model = S-100
+//   Lamp.installed = 2024-03-01
+//
+// Room fields are display_name, floor, area, description and icon, device fields are
+// manufacturer, model, location and installed. Device names can not contain `.`.
+// Empty lines and lines starting with '#' are ignored.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Socket,
+    Thermometer,
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceKind::Socket => write!(f, "socket"),
+            DeviceKind::Thermometer => write!(f, "thermometer"),
+        }
+    }
+}
+
+impl FromStr for DeviceKind {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "socket" => Ok(DeviceKind::Socket),
+            "thermometer" => Ok(DeviceKind::Thermometer),
+            _ => Err(SmartHomeError::ConfigError(format!(
+                "Unknown device kind '{s}'"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceConfig {
+    pub name: String,
+    pub kind: DeviceKind,
+    pub address: String,
+    pub tags: Vec<String>,
+    pub info: DeviceInfo,
+}
+
+impl DeviceConfig {
+    pub fn connect(&self) -> Result<SmartDevice, SmartHomeError> {
+        Ok(match self.kind {
+            DeviceKind::Socket => SmartSocket::connect(&self.address)?.into(),
+            DeviceKind::Thermometer => SmartThermometer::connect(&self.address)?.into(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomConfig {
+    pub name: String,
+    pub devices: Vec<DeviceConfig>,
+    pub info: RoomInfo,
+}
+
+impl RoomConfig {
+    pub fn connect(&self) -> Result<Room, SmartHomeError> {
+        let mut room = Room::default();
+        room.info = self.info.clone();
+        for device in &self.devices {
+            room.try_add_device(&device.name, device.connect()?)?;
+            for tag in &device.tags {
+                room.tag_device(&device.name, tag)?;
+            }
+            if !device.info.is_empty() {
+                room.set_device_info(&device.name, device.info.clone())?;
+            }
+        }
+        Ok(room)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HomeConfig {
+    pub name: String,
+    // Max-age of the socket state caches, None disables them.
+    pub cache_max_age: Option<Duration>,
+    pub rooms: Vec<RoomConfig>,
+}
+
+impl HomeConfig {
+    pub fn new<T>(name: T) -> HomeConfig
+    where
+        T: Into<String>,
+    {
+        HomeConfig {
+            name: name.into(),
+            cache_max_age: None,
+            rooms: Vec::new(),
+        }
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<HomeConfig, SmartHomeError> {
+        read_config(path.as_ref())?.parse()
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SmartHomeError> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+    pub fn connect(&self) -> Result<Home, SmartHomeError> {
+        let mut home = Home::new(&self.name);
+        for room in &self.rooms {
+            home.try_add_room(&room.name, room.connect()?)?;
+        }
+        if self.cache_max_age.is_some() {
+            for (_, _, device) in home.devices() {
+                if let SmartDevice::SmartSocket(socket) = device {
+                    socket.set_max_age(self.cache_max_age);
+                }
+            }
+        }
+        Ok(home)
+    }
+    pub fn device_count(&self) -> usize {
+        self.rooms.iter().map(|room| room.devices.len()).sum()
+    }
+}
+
+impl fmt::Display for HomeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "name = {}", self.name)?;
+        if let Some(max_age) = self.cache_max_age {
+            writeln!(f, "cache_max_age_ms = {}", max_age.as_millis())?;
+        }
+        for room in &self.rooms {
+            writeln!(f)?;
+            writeln!(f, "[{}]", room.name)?;
+            for (key, value) in room.info.entries() {
+                writeln!(f, ".{key} = {value}")?;
+            }
+            for device in &room.devices {
+                write!(f, "{} = {} {}", device.name, device.kind, device.address)?;
+                if !device.tags.is_empty() {
+                    write!(f, " [{}]", device.tags.join(", "))?;
+                }
+                writeln!(f)?;
+                for (key, value) in device.info.entries() {
+                    writeln!(f, "{}.{key} = {value}", device.name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for HomeConfig {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = HomeConfig::new("Home");
+        for line in ConfigLines::new(s) {
+            match line? {
+                ConfigLine::Section(name) => config.rooms.push(RoomConfig {
+                    name: name.to_string(),
+                    devices: Vec::new(),
+                    info: RoomInfo::default(),
+                }),
+                ConfigLine::Value(number, key, value) => match config.rooms.last_mut() {
+                    None if key == "name" => config.name = value.to_string(),
+                    None if key == "cache_max_age_ms" => {
+                        let millis = value.parse().map_err(|_| {
+                            SmartHomeError::ConfigError(format!(
+                                "Line {number}: invalid value '{value}' for '{key}'"
+                            ))
+                        })?;
+                        config.cache_max_age = Some(Duration::from_millis(millis));
+                    }
+                    None => return Err(unknown_key(number, key)),
+                    Some(room) if key.starts_with('.') => room
+                        .info
+                        .set(&key[1..], value)
+                        .map_err(|err| with_line(number, err))?,
+                    Some(room)
+                        if key.rsplit_once('.').is_some_and(|(device, _)| {
+                            room.devices.iter().any(|d| d.name == device)
+                        }) =>
+                    {
+                        let (device, field) = key.rsplit_once('.').unwrap_or_default();
+                        if let Some(device) = room.devices.iter_mut().find(|d| d.name == device) {
+                            device
+                                .info
+                                .set(field, value)
+                                .map_err(|err| with_line(number, err))?;
+                        }
+                    }
+                    Some(room) => {
+                        let invalid = || {
+                            SmartHomeError::ConfigError(format!(
+                                "Line {number}: expected '<kind> <address> [<tags>]'"
+                            ))
+                        };
+                        let (value, tags) = match value.strip_suffix(']') {
+                            Some(rest) => {
+                                let (value, tags) = rest.rsplit_once('[').ok_or_else(invalid)?;
+                                let tags = tags
+                                    .split(',')
+                                    .map(str::trim)
+                                    .filter(|tag| !tag.is_empty())
+                                    .map(str::to_string)
+                                    .collect();
+                                (value, tags)
+                            }
+                            None => (value, Vec::new()),
+                        };
+                        let (kind, address) = value
+                            .trim()
+                            .split_once(char::is_whitespace)
+                            .ok_or_else(invalid)?;
+                        if key.contains('.') {
+                            return Err(SmartHomeError::ConfigError(format!(
+                                "Line {number}: device name '{key}' can not contain '.'"
+                            )));
+                        }
+                        room.devices.push(DeviceConfig {
+                            name: key.to_string(),
+                            kind: kind.parse()?,
+                            address: address.trim().to_string(),
+                            tags,
+                            info: DeviceInfo::default(),
+                        });
+                    }
+                },
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl Home {
+    pub fn load(path: impl AsRef<Path>) -> Result<Home, SmartHomeError> {
+        HomeConfig::load(path)?.connect()
+    }
+}
+
+pub(crate) enum ConfigLine<'a> {
+    Section(&'a str),
+    Value(usize, &'a str, &'a str),
+}
+
+// Splits ini-like text into sections and `key = value` pairs, shared by all config readers.
+pub(crate) struct ConfigLines<'a> {
+    lines: std::iter::Enumerate<std::str::Lines<'a>>,
+}
+
+impl<'a> ConfigLines<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Self {
+            lines: text.lines().enumerate(),
+        }
+    }
+}
+
+impl<'a> Iterator for ConfigLines<'a> {
+    type Item = Result<ConfigLine<'a>, SmartHomeError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        for (index, line) in self.lines.by_ref() {
+            let number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[') {
+                return Some(match section.strip_suffix(']') {
+                    Some(name) if !name.trim().is_empty() => Ok(ConfigLine::Section(name.trim())),
+                    _ => Err(SmartHomeError::ConfigError(format!(
+                        "Line {number}: invalid section header"
+                    ))),
+                });
+            }
+            return Some(match line.split_once('=') {
+                Some((key, value)) if !key.trim().is_empty() => {
+                    Ok(ConfigLine::Value(number, key.trim(), value.trim()))
+                }
+                _ => Err(SmartHomeError::ConfigError(format!(
+                    "Line {number}: expected 'key = value'"
+                ))),
+            });
+        }
+        None
+    }
+}
+
+pub(crate) fn read_config(path: &Path) -> Result<String, SmartHomeError> {
+    fs::read_to_string(path).map_err(|err| {
+        SmartHomeError::ConfigError(format!("Can't read '{}': {err}", path.display()))
+    })
+}
+
+// Section name with its (line number, key, value) entries.
+pub(crate) type Section<'a> = (String, Vec<(usize, &'a str, &'a str)>);
+
+// Reads rules and schedules files: `utc_offset` before the first section and the sections,
+// which are parsed by the caller once the offset is known.
+pub(crate) fn offset_sections(text: &str) -> Result<(UtcOffset, Vec<Section<'_>>), SmartHomeError> {
+    let mut utc_offset = UtcOffset::default();
+    let mut sections: Vec<Section> = Vec::new();
+    for line in ConfigLines::new(text) {
+        match (line?, sections.last_mut()) {
+            (ConfigLine::Section(name), _) => sections.push((name.to_string(), Vec::new())),
+            (ConfigLine::Value(_, "utc_offset", value), None) => utc_offset = value.parse()?,
+            (ConfigLine::Value(number, key, _), None) => return Err(unknown_key(number, key)),
+            (ConfigLine::Value(number, key, value), Some((_, values))) => {
+                values.push((number, key, value))
+            }
+        }
+    }
+    Ok((utc_offset, sections))
+}
+
+pub(crate) fn with_line(number: usize, err: SmartHomeError) -> SmartHomeError {
+    match err {
+        SmartHomeError::ConfigError(msg) => {
+            SmartHomeError::ConfigError(format!("Line {number}: {msg}"))
+        }
+        err => err,
+    }
+}
+
+pub(crate) fn unknown_key(number: usize, key: &str) -> SmartHomeError {
+    SmartHomeError::ConfigError(format!("Line {number}: unknown key '{key}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+# test home
+name = Test Home
+cache_max_age_ms = 1500
+
+[Room 1]
+Socket1 = socket 127.0.0.1:4331
+Thermometer1 = thermometer 127.0.0.1:4321
+
+[Room 2]
+.display_name = Bathroom
+.floor = 2
+Heater = socket 127.0.0.1:4332 [heaters, critical]
+Heater.manufacturer = Acme
+Heater.installed = 2024-03-01
+";
+
+    #[test]
+    fn test_parse_config() {
+        let config: HomeConfig = CONFIG.parse().unwrap();
+        assert_eq!(config.name, "Test Home");
+        assert_eq!(config.cache_max_age, Some(Duration::from_millis(1500)));
+        assert_eq!(config.rooms.len(), 2);
+        assert_eq!(config.rooms[0].name, "Room 1");
+        assert_eq!(
+            config.rooms[0].devices[0],
+            DeviceConfig {
+                name: "Socket1".to_string(),
+                kind: DeviceKind::Socket,
+                address: "127.0.0.1:4331".to_string(),
+                tags: Vec::new(),
+                info: DeviceInfo::default(),
+            }
+        );
+        assert_eq!(config.rooms[0].devices[1].kind, DeviceKind::Thermometer);
+        assert_eq!(config.rooms[1].devices[0].address, "127.0.0.1:4332");
+        assert_eq!(config.rooms[1].devices[0].tags, ["heaters", "critical"]);
+        assert_eq!(
+            config.rooms[1].info.display_name.as_deref(),
+            Some("Bathroom")
+        );
+        assert_eq!(config.rooms[1].info.floor, Some(2));
+        let heater = &config.rooms[1].devices[0].info;
+        assert_eq!(heater.manufacturer.as_deref(), Some("Acme"));
+        assert_eq!(heater.installed, Some(crate::clock::Date::new(2024, 3, 1)));
+        assert_eq!(config.device_count(), 3);
+    }
+
+    #[test]
+    fn test_config_roundtrip() {
+        let config: HomeConfig = CONFIG.parse().unwrap();
+        let parsed: HomeConfig = config.to_string().parse().unwrap();
+        assert_eq!(config, parsed);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("[Room\n".parse::<HomeConfig>().is_err());
+        assert!("color = red\n".parse::<HomeConfig>().is_err());
+        assert!("cache_max_age_ms = soon\n".parse::<HomeConfig>().is_err());
+        assert!("[Room]\nSocket1 = socket\n".parse::<HomeConfig>().is_err());
+        assert!(
+            "[Room]\nLamp = lamp 127.0.0.1:1\n"
+                .parse::<HomeConfig>()
+                .is_err()
+        );
+        assert!("[Room]\njust text\n".parse::<HomeConfig>().is_err());
+        assert!(
+            "[Room]\nLamp = socket 127.0.0.1:1 tags]\n"
+                .parse::<HomeConfig>()
+                .is_err()
+        );
+        assert!("[Room]\n.floor = top\n".parse::<HomeConfig>().is_err());
+        assert!(
+            "[Room]\nLamp = socket 127.0.0.1:1\nLamp.colour = red\n"
+                .parse::<HomeConfig>()
+                .is_err()
+        );
+        assert!(
+            "[Room]\nLamp.v2 = socket 127.0.0.1:1\n"
+                .parse::<HomeConfig>()
+                .is_err()
+        );
+    }
+}
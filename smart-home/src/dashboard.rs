@@ -0,0 +1,361 @@
+use crate::polling::Polled;
+use crate::{DeviceKind, DeviceState, Home, SmartDevice, SmartHomeError};
+use ratatui::{
+    Frame,
+    crossterm::event::KeyCode,
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    widgets::{Block, Paragraph, Row, Table, TableState},
+};
+use std::time::{Duration, Instant};
+
+const STALE_AFTER: Duration = Duration::from_secs(5);
+// Slow sockets must not freeze the screen for long.
+const POLL_DEADLINE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceStatus {
+    Online(DeviceState),
+    // Thermometer which did not receive a value for too long
+    Stale(DeviceState, Option<Duration>),
+    Offline(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceRow {
+    pub room: String,
+    pub device: String,
+    pub kind: DeviceKind,
+    pub status: DeviceStatus,
+}
+
+// Live view of a home: rows are rebuilt from the home on every refresh, in home order
+// unless sorted by name.
+#[derive(Debug)]
+pub struct Dashboard {
+    title: String,
+    rows: Vec<DeviceRow>,
+    selected: usize,
+    stale_after: Duration,
+    message: String,
+    sorted: bool,
+    quit: bool,
+}
+
+impl Dashboard {
+    pub fn new(home: &Home) -> Self {
+        let mut dashboard = Dashboard {
+            title: home.name.clone(),
+            rows: Vec::new(),
+            selected: 0,
+            stale_after: STALE_AFTER,
+            message: String::new(),
+            sorted: false,
+            quit: false,
+        };
+        dashboard.refresh(home);
+        dashboard
+    }
+
+    pub fn with_stale_after(mut self, stale_after: Duration) -> Self {
+        self.stale_after = stale_after;
+        self
+    }
+
+    pub fn rows(&self) -> &[DeviceRow] {
+        &self.rows
+    }
+
+    pub fn selected(&self) -> Option<&DeviceRow> {
+        self.rows.get(self.selected)
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    pub fn refresh(&mut self, home: &Home) {
+        let stale_after = self.stale_after;
+        self.title = home.name.clone();
+        let snapshot = home.poll(POLL_DEADLINE);
+        self.rows = home
+            .devices()
+            .map(|(room, name, device)| DeviceRow {
+                room: room.to_string(),
+                device: name.to_string(),
+                kind: device.kind(),
+                status: device_status(device, snapshot.get(room, name), stale_after),
+            })
+            .collect();
+        if self.sorted {
+            self.rows
+                .sort_by(|a, b| (&a.room, &a.device).cmp(&(&b.room, &b.device)));
+        }
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode, home: &Home) {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.rows.len().saturating_sub(1))
+            }
+            KeyCode::Char('r') => {
+                self.refresh(home);
+                self.message = "Refreshed".to_string();
+            }
+            KeyCode::Char('s') => {
+                let selected = self
+                    .selected()
+                    .map(|row| (row.room.clone(), row.device.clone()));
+                self.sorted = !self.sorted;
+                self.refresh(home);
+                if let Some(index) = selected.and_then(|(room, device)| {
+                    self.rows
+                        .iter()
+                        .position(|row| row.room == room && row.device == device)
+                }) {
+                    self.selected = index;
+                }
+                self.message = if self.sorted {
+                    "Sorted by name"
+                } else {
+                    "Home order"
+                }
+                .to_string();
+            }
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                self.switch_selected(home, |socket| socket.switch())
+            }
+            KeyCode::Char('o') => self.switch_selected(home, |socket| socket.turn_on()),
+            KeyCode::Char('f') => self.switch_selected(home, |socket| socket.turn_off()),
+            _ => {}
+        }
+    }
+
+    fn switch_selected(
+        &mut self,
+        home: &Home,
+        action: impl Fn(&crate::SmartSocket) -> Result<(), SmartHomeError>,
+    ) {
+        let Some(row) = self.selected() else {
+            return;
+        };
+        let (room, device) = (row.room.clone(), row.device.clone());
+        let result = home
+            .find_device(&room, &device)
+            .and_then(|d| action(d.as_socket()?));
+        self.message = match result {
+            Ok(()) => format!("{room}/{device} switched"),
+            Err(err) => format!("{room}/{device}: {err}"),
+        };
+        self.refresh(home);
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Paragraph::new(format!("Home: {}", self.title)).bold(),
+            header,
+        );
+
+        let rows = self.rows.iter().map(|row| {
+            let (state, status, style) = match &row.status {
+                DeviceStatus::Online(state) => {
+                    (state_text(state), "online".to_string(), Style::new())
+                }
+                DeviceStatus::Stale(state, age) => (
+                    state_text(state),
+                    match age {
+                        Some(age) => format!("stale {}s", age.as_secs()),
+                        None => "no data".to_string(),
+                    },
+                    Style::new().fg(Color::Yellow),
+                ),
+                DeviceStatus::Offline(err) => (
+                    err.clone(),
+                    "offline".to_string(),
+                    Style::new().fg(Color::Red),
+                ),
+            };
+            Row::new(vec![
+                row.room.clone(),
+                row.device.clone(),
+                row.kind.to_string(),
+                state,
+                status,
+            ])
+            .style(style)
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Percentage(20),
+                Constraint::Percentage(20),
+                Constraint::Length(12),
+                Constraint::Fill(1),
+                Constraint::Length(10),
+            ],
+        )
+        .header(Row::new(vec!["Room", "Device", "Kind", "State", "Status"]).bold())
+        .block(Block::bordered())
+        .row_highlight_style(Style::new().reversed());
+        let mut state = TableState::new().with_selected(Some(self.selected));
+        frame.render_stateful_widget(table, body, &mut state);
+
+        frame.render_widget(
+            Paragraph::new(vec![
+                self.message.clone().into(),
+                "↑/↓ select  enter switch  o on  f off  r refresh  s sort  q quit"
+                    .dim()
+                    .into(),
+            ]),
+            footer,
+        );
+    }
+}
+
+fn device_status(
+    device: &SmartDevice,
+    polled: Option<&Polled>,
+    stale_after: Duration,
+) -> DeviceStatus {
+    let state = match polled {
+        Some(Polled::State(state)) => *state,
+        Some(Polled::Cached(state, age)) if *age > stale_after => {
+            return DeviceStatus::Stale(*state, Some(*age));
+        }
+        Some(Polled::Cached(state, _)) => *state,
+        Some(Polled::Failed(err)) => return DeviceStatus::Offline(err.to_string()),
+        Some(Polled::TimedOut) => {
+            return DeviceStatus::Offline("no answer before the deadline".to_string());
+        }
+        None => return DeviceStatus::Offline("not polled".to_string()),
+    };
+    match device {
+        SmartDevice::SmartThermometer(thermometer) => {
+            let age = thermometer.last_update().map(|updated| updated.elapsed());
+            match age {
+                Some(age) if age <= stale_after => DeviceStatus::Online(state),
+                _ => DeviceStatus::Stale(state, age),
+            }
+        }
+        SmartDevice::SmartSocket(_) => DeviceStatus::Online(state),
+    }
+}
+
+fn state_text(state: &DeviceState) -> String {
+    match state {
+        DeviceState::Socket { on, power } => {
+            format!("{} {power:.2} W", if *on { "on " } else { "off" })
+        }
+        DeviceState::Thermometer { temperature } => format!("{temperature:.2} °C"),
+    }
+}
+
+// Rebuilds the dashboard from the home at most once per `interval`.
+pub struct RefreshTimer {
+    interval: Duration,
+    last: Instant,
+}
+
+impl RefreshTimer {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: Instant::now(),
+        }
+    }
+    pub fn timeout(&self) -> Duration {
+        self.interval.saturating_sub(self.last.elapsed())
+    }
+    pub fn tick(&mut self) -> bool {
+        if self.last.elapsed() >= self.interval {
+            self.last = Instant::now();
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Room, SmartSocket, emulator::EmulatedStream};
+    use ratatui::{Terminal, backend::TestBackend};
+    use std::io::Cursor;
+
+    fn home() -> Home {
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        room.add_device("Socket", SmartSocket::new(EmulatedStream::new()).into());
+        room.add_device("Broken", SmartSocket::new(Cursor::new(Vec::new())).into());
+        home.add_room("Room", room);
+        home
+    }
+
+    fn screen(dashboard: &Dashboard) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(80, 10)).unwrap();
+        terminal.draw(|frame| dashboard.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content
+            .chunks(buffer.area.width as usize)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_dashboard_rows() {
+        let dashboard = Dashboard::new(&home());
+        let rows = dashboard.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].device, "Broken");
+        assert!(matches!(rows[1].status, DeviceStatus::Offline(_)));
+        assert_eq!(
+            rows[0].status,
+            DeviceStatus::Online(DeviceState::Socket {
+                on: false,
+                power: 0.
+            })
+        );
+        let screen = screen(&dashboard);
+        assert!(screen.contains("Home: Test"));
+        assert!(screen.contains("offline"));
+        assert!(screen.contains("off 0.00 W"));
+    }
+
+    #[test]
+    fn test_dashboard_keys() {
+        let home = home();
+        let mut dashboard = Dashboard::new(&home);
+        assert_eq!(dashboard.selected().unwrap().device, "Socket");
+        dashboard.handle_key(KeyCode::Enter, &home);
+        assert!(screen(&dashboard).contains("on  1000.00 W"));
+        dashboard.handle_key(KeyCode::Char('f'), &home);
+        assert!(screen(&dashboard).contains("off 0.00 W"));
+
+        // Sorting keeps the selected device
+        dashboard.handle_key(KeyCode::Char('s'), &home);
+        assert_eq!(dashboard.rows()[0].device, "Broken");
+        assert_eq!(dashboard.selected().unwrap().device, "Socket");
+        dashboard.handle_key(KeyCode::Char('s'), &home);
+        assert_eq!(dashboard.rows()[0].device, "Socket");
+
+        dashboard.handle_key(KeyCode::Down, &home);
+        dashboard.handle_key(KeyCode::Char('o'), &home);
+        assert!(screen(&dashboard).contains("Room/Broken: Connection error"));
+
+        assert!(!dashboard.should_quit());
+        dashboard.handle_key(KeyCode::Char('q'), &home);
+        assert!(dashboard.should_quit());
+    }
+}
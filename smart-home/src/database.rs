@@ -0,0 +1,639 @@
+use crate::config::{DeviceConfig, HomeConfig, RoomConfig};
+use crate::history::{Aggregate, Metric, Sample, from_unix_millis, unix_millis};
+use crate::metadata::{DeviceInfo, RoomInfo};
+use crate::polling::Snapshot;
+use crate::scheduler::{Schedule, ScheduleSet, When};
+use crate::{Home, SmartHomeError, history};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+// Home config, schedules and recorded readings in one SQLite file. The schema version is
+// kept in `PRAGMA user_version`, opening a database applies the migrations it misses.
+// Rows keep the config order in `position` columns.
+
+// Migration n brings the schema from version n to n + 1. Released migrations are never
+// changed, new ones are appended.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE rooms (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        position INTEGER NOT NULL
+    );
+    CREATE TABLE devices (
+        id INTEGER PRIMARY KEY,
+        room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        address TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        UNIQUE (room_id, name)
+    );
+    CREATE TABLE device_tags (
+        device_id INTEGER NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+        tag TEXT NOT NULL,
+        position INTEGER NOT NULL
+    );
+    CREATE TABLE samples (
+        time INTEGER NOT NULL,
+        room TEXT NOT NULL,
+        device TEXT NOT NULL,
+        metric TEXT NOT NULL,
+        value REAL NOT NULL
+    );
+    CREATE INDEX samples_by_device ON samples (room, device, metric, time);",
+    "CREATE TABLE room_info (
+        room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (room_id, key)
+    );
+    CREATE TABLE device_info (
+        device_id INTEGER NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (device_id, key)
+    );",
+    "CREATE TABLE schedules (
+        name TEXT PRIMARY KEY,
+        position INTEGER NOT NULL,
+        when_text TEXT NOT NULL,
+        action TEXT NOT NULL,
+        duration_ms INTEGER,
+        missed TEXT NOT NULL,
+        next_ms INTEGER
+    );",
+];
+
+#[derive(Debug)]
+pub struct Database {
+    connection: Connection,
+}
+
+impl From<rusqlite::Error> for SmartHomeError {
+    fn from(err: rusqlite::Error) -> Self {
+        SmartHomeError::StorageError(err.to_string())
+    }
+}
+
+impl Database {
+    // Creates the file if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Database, SmartHomeError> {
+        Database::migrate(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Database, SmartHomeError> {
+        Database::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(mut connection: Connection) -> Result<Database, SmartHomeError> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        let version = schema_version(&connection)?;
+        if version > MIGRATIONS.len() {
+            return Err(SmartHomeError::StorageError(format!(
+                "database schema version {version} is newer than the supported {}",
+                MIGRATIONS.len()
+            )));
+        }
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()?;
+        }
+        Ok(Database { connection })
+    }
+
+    pub fn schema_version(&self) -> Result<usize, SmartHomeError> {
+        schema_version(&self.connection)
+    }
+
+    // Replaces the stored home config.
+    pub fn save_config(&mut self, config: &HomeConfig) -> Result<(), SmartHomeError> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM rooms", [])?;
+        set_setting(&transaction, "name", Some(config.name.clone()))?;
+        set_setting(
+            &transaction,
+            "cache_max_age_ms",
+            config
+                .cache_max_age
+                .map(|max_age| max_age.as_millis().to_string()),
+        )?;
+        for (position, room) in config.rooms.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO rooms (name, position) VALUES (?1, ?2)",
+                params![room.name, position],
+            )?;
+            let room_id = transaction.last_insert_rowid();
+            for (key, value) in room.info.entries() {
+                transaction.execute(
+                    "INSERT INTO room_info (room_id, key, value) VALUES (?1, ?2, ?3)",
+                    params![room_id, key, value],
+                )?;
+            }
+            for (position, device) in room.devices.iter().enumerate() {
+                transaction.execute(
+                    "INSERT INTO devices (room_id, name, kind, address, position)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        room_id,
+                        device.name,
+                        device.kind.to_string(),
+                        device.address,
+                        position
+                    ],
+                )?;
+                let device_id = transaction.last_insert_rowid();
+                for (position, tag) in device.tags.iter().enumerate() {
+                    transaction.execute(
+                        "INSERT INTO device_tags (device_id, tag, position) VALUES (?1, ?2, ?3)",
+                        params![device_id, tag, position],
+                    )?;
+                }
+                for (key, value) in device.info.entries() {
+                    transaction.execute(
+                        "INSERT INTO device_info (device_id, key, value) VALUES (?1, ?2, ?3)",
+                        params![device_id, key, value],
+                    )?;
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // Fails with a config error when no config was saved yet.
+    pub fn load_config(&self) -> Result<HomeConfig, SmartHomeError> {
+        let name = self.setting("name")?.ok_or_else(|| {
+            SmartHomeError::ConfigError("database has no home config".to_string())
+        })?;
+        let mut config = HomeConfig::new(name);
+        if let Some(millis) = self.setting("cache_max_age_ms")? {
+            config.cache_max_age = Some(Duration::from_millis(millis.parse().map_err(|_| {
+                SmartHomeError::StorageError(format!("invalid cache_max_age_ms '{millis}'"))
+            })?));
+        }
+
+        let mut rooms = self
+            .connection
+            .prepare("SELECT id, name FROM rooms ORDER BY position")?;
+        let mut room_info = self
+            .connection
+            .prepare("SELECT key, value FROM room_info WHERE room_id = ?1")?;
+        let mut devices = self.connection.prepare(
+            "SELECT id, name, kind, address FROM devices WHERE room_id = ?1 ORDER BY position",
+        )?;
+        let mut tags = self
+            .connection
+            .prepare("SELECT tag FROM device_tags WHERE device_id = ?1 ORDER BY position")?;
+        let mut device_info = self
+            .connection
+            .prepare("SELECT key, value FROM device_info WHERE device_id = ?1")?;
+
+        let room_rows: Vec<(i64, String)> = rooms
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for (room_id, name) in room_rows {
+            let mut room = RoomConfig {
+                name,
+                devices: Vec::new(),
+                info: RoomInfo::default(),
+            };
+            for entry in room_info.query_map([room_id], key_value)? {
+                let (key, value) = entry?;
+                room.info.set(&key, &value)?;
+            }
+            let device_rows: Vec<(i64, String, String, String)> = devices
+                .query_map([room_id], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })?
+                .collect::<Result<_, _>>()?;
+            for (device_id, name, kind, address) in device_rows {
+                let mut device = DeviceConfig {
+                    name,
+                    kind: kind.parse()?,
+                    address,
+                    tags: tags
+                        .query_map([device_id], |row| row.get(0))?
+                        .collect::<Result<_, _>>()?,
+                    info: DeviceInfo::default(),
+                };
+                for entry in device_info.query_map([device_id], key_value)? {
+                    let (key, value) = entry?;
+                    device.info.set(&key, &value)?;
+                }
+                room.devices.push(device);
+            }
+            config.rooms.push(room);
+        }
+        Ok(config)
+    }
+
+    // Connects the devices of the stored config.
+    pub fn load_home(&self) -> Result<Home, SmartHomeError> {
+        self.load_config()?.connect()
+    }
+
+    // Replaces the stored schedules.
+    pub fn save_schedules(&mut self, set: &ScheduleSet) -> Result<(), SmartHomeError> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM schedules", [])?;
+        set_setting(&transaction, "utc_offset", Some(set.utc_offset.to_string()))?;
+        for (position, schedule) in set.schedules.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO schedules (name, position, when_text, action, duration_ms, missed, next_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    schedule.name,
+                    position,
+                    schedule.when.format(set.utc_offset),
+                    schedule.action.to_string(),
+                    schedule.duration.map(|duration| duration.as_millis() as i64),
+                    schedule.missed.to_string(),
+                    schedule.next.map(unix_millis),
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // Empty set when no schedules were saved.
+    pub fn load_schedules(&self) -> Result<ScheduleSet, SmartHomeError> {
+        let mut set = ScheduleSet::default();
+        if let Some(utc_offset) = self.setting("utc_offset")? {
+            set.utc_offset = utc_offset.parse()?;
+        }
+        let mut statement = self.connection.prepare(
+            "SELECT name, when_text, action, duration_ms, missed, next_ms
+             FROM schedules ORDER BY position",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+            ))
+        })?;
+        for row in rows {
+            let (name, when, action, duration, missed, next) = row?;
+            set.schedules.push(Schedule {
+                name,
+                when: When::parse(&when, set.utc_offset)?,
+                action: action.parse()?,
+                duration: duration.map(|millis| Duration::from_millis(millis.max(0) as u64)),
+                missed: missed.parse()?,
+                next: next.map(from_unix_millis),
+            });
+        }
+        Ok(set)
+    }
+
+    pub fn record(&mut self, samples: &[Sample]) -> Result<(), SmartHomeError> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare(
+                "INSERT INTO samples (time, room, device, metric, value)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for sample in samples {
+                insert.execute(params![
+                    unix_millis(sample.time),
+                    sample.room,
+                    sample.device,
+                    sample.metric.to_string(),
+                    sample.value
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // Records the readings of a snapshot, returns their number.
+    pub fn record_snapshot(
+        &mut self,
+        snapshot: &Snapshot,
+        now: SystemTime,
+    ) -> Result<usize, SmartHomeError> {
+        let samples = history::samples(snapshot, now);
+        self.record(&samples)?;
+        Ok(samples.len())
+    }
+
+    // Samples of the device in [from, to), oldest first.
+    pub fn range(
+        &self,
+        room: &str,
+        device: &str,
+        metric: Metric,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<Vec<Sample>, SmartHomeError> {
+        let mut statement = self.connection.prepare(
+            "SELECT time, value FROM samples
+             WHERE room = ?1 AND device = ?2 AND metric = ?3 AND time >= ?4 AND time < ?5
+             ORDER BY time",
+        )?;
+        let rows = statement.query_map(
+            params![
+                room,
+                device,
+                metric.to_string(),
+                unix_millis(from),
+                unix_millis(to)
+            ],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?)),
+        )?;
+        let mut samples = Vec::new();
+        for row in rows {
+            let (time, value) = row?;
+            samples.push(Sample::new(
+                from_unix_millis(time),
+                room,
+                device,
+                metric,
+                value,
+            ));
+        }
+        Ok(samples)
+    }
+
+    // Like `History::aggregate`, but the buckets are computed by the database.
+    pub fn aggregate(
+        &self,
+        room: &str,
+        device: &str,
+        metric: Metric,
+        from: SystemTime,
+        to: SystemTime,
+        bucket: Duration,
+    ) -> Result<Vec<Aggregate>, SmartHomeError> {
+        let bucket_millis = bucket.as_millis().max(1) as i64;
+        // Integer division truncates towards zero, times before the epoch are floored by hand.
+        let mut statement = self.connection.prepare(
+            "SELECT (time - ((time % ?6) + ?6) % ?6) AS start, COUNT(*), MIN(value), MAX(value), AVG(value)
+             FROM samples
+             WHERE room = ?1 AND device = ?2 AND metric = ?3 AND time >= ?4 AND time < ?5
+             GROUP BY start ORDER BY start",
+        )?;
+        let rows = statement.query_map(
+            params![
+                room,
+                device,
+                metric.to_string(),
+                unix_millis(from),
+                unix_millis(to),
+                bucket_millis
+            ],
+            |row| {
+                Ok(Aggregate {
+                    start: from_unix_millis(row.get(0)?),
+                    count: row.get::<_, i64>(1)? as usize,
+                    min: row.get(2)?,
+                    max: row.get(3)?,
+                    mean: row.get(4)?,
+                })
+            },
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    // Removes samples older than the given time, returns how many.
+    pub fn remove_before(&self, time: SystemTime) -> Result<usize, SmartHomeError> {
+        Ok(self
+            .connection
+            .execute("DELETE FROM samples WHERE time < ?1", [unix_millis(time)])?)
+    }
+
+    fn setting(&self, key: &str) -> Result<Option<String>, SmartHomeError> {
+        Ok(self
+            .connection
+            .query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+}
+
+fn schema_version(connection: &Connection) -> Result<usize, SmartHomeError> {
+    let version: i64 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(version.max(0) as usize)
+}
+
+// None removes the setting.
+fn set_setting(
+    transaction: &Transaction,
+    key: &str,
+    value: Option<String>,
+) -> Result<(), SmartHomeError> {
+    match value {
+        Some(value) => transaction.execute(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?,
+        None => transaction.execute("DELETE FROM settings WHERE key = ?1", [key])?,
+    };
+    Ok(())
+}
+
+fn key_value(row: &rusqlite::Row) -> rusqlite::Result<(String, String)> {
+    Ok((row.get(0)?, row.get(1)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::UtcOffset;
+    use crate::config::DeviceKind;
+    use crate::scheduler::Missed;
+    use std::time::UNIX_EPOCH;
+
+    fn config() -> HomeConfig {
+        let mut config = HomeConfig::new("Cottage");
+        config.cache_max_age = Some(Duration::from_millis(1500));
+        let mut kitchen = RoomConfig {
+            name: "Kitchen".to_string(),
+            devices: Vec::new(),
+            info: RoomInfo::default(),
+        };
+        kitchen.info.set("floor", "1").unwrap();
+        kitchen.info.set("display_name", "Kitchen, north").unwrap();
+        let mut kettle = DeviceConfig {
+            name: "Kettle".to_string(),
+            kind: DeviceKind::Socket,
+            address: "127.0.0.1:5000".to_string(),
+            tags: vec!["appliances".to_string(), "heavy".to_string()],
+            info: DeviceInfo::default(),
+        };
+        kettle.info.set("installed", "2024-03-01").unwrap();
+        kitchen.devices.push(kettle);
+        kitchen.devices.push(DeviceConfig {
+            name: "Outdoor".to_string(),
+            kind: DeviceKind::Thermometer,
+            address: "127.0.0.1:5001".to_string(),
+            tags: Vec::new(),
+            info: DeviceInfo::default(),
+        });
+        config.rooms.push(kitchen);
+        config.rooms.push(RoomConfig {
+            name: "Attic".to_string(),
+            devices: Vec::new(),
+            info: RoomInfo::default(),
+        });
+        config
+    }
+
+    #[test]
+    fn test_migrations() {
+        let path = std::env::temp_dir().join(format!("smart-home-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut database = Database::open(&path).unwrap();
+        assert_eq!(database.schema_version().unwrap(), MIGRATIONS.len());
+        database.save_config(&config()).unwrap();
+        drop(database);
+
+        // Opening again applies nothing and keeps the data
+        let database = Database::open(&path).unwrap();
+        assert_eq!(database.load_config().unwrap(), config());
+        database
+            .connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        drop(database);
+        let err = Database::open(&path).unwrap_err();
+        assert!(matches!(err, SmartHomeError::StorageError(_)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_config() {
+        let mut database = Database::in_memory().unwrap();
+        assert!(matches!(
+            database.load_config(),
+            Err(SmartHomeError::ConfigError(_))
+        ));
+        database.save_config(&config()).unwrap();
+        assert_eq!(database.load_config().unwrap(), config());
+
+        // Saving replaces the previous config
+        let mut smaller = config();
+        smaller.rooms.truncate(1);
+        smaller.rooms[0].devices.truncate(1);
+        smaller.cache_max_age = None;
+        database.save_config(&smaller).unwrap();
+        assert_eq!(database.load_config().unwrap(), smaller);
+        let tags: i64 = database
+            .connection
+            .query_row("SELECT COUNT(*) FROM device_tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tags, 2);
+    }
+
+    #[test]
+    fn test_schedules() {
+        let mut database = Database::in_memory().unwrap();
+        assert_eq!(database.load_schedules().unwrap(), ScheduleSet::default());
+        let set: ScheduleSet = "utc_offset = +02:00
+
+[Morning]
+when = weekdays 07:00
+do = Kitchen/Kettle on
+for = 10m
+next = 2025-01-06 07:00
+
+[Once]
+when = at 2025-01-05 18:30
+do = Kitchen/Kettle off
+missed = skip
+"
+        .parse()
+        .unwrap();
+        database.save_schedules(&set).unwrap();
+        let loaded = database.load_schedules().unwrap();
+        assert_eq!(loaded, set);
+        assert_eq!(loaded.utc_offset, "+02:00".parse::<UtcOffset>().unwrap());
+        assert_eq!(loaded.schedules[1].missed, Missed::Skip);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut database = Database::in_memory().unwrap();
+        let at = |seconds: u64| UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds);
+        let samples: Vec<Sample> = [(0, 20.0), (30, 22.0), (60, 21.0), (150, 25.0)]
+            .into_iter()
+            .map(|(seconds, value)| {
+                Sample::new(
+                    at(seconds),
+                    "Kitchen",
+                    "Outdoor",
+                    Metric::Temperature,
+                    value,
+                )
+            })
+            .chain([Sample::new(at(10), "Kitchen", "Kettle", Metric::Power, 1.5)])
+            .collect();
+        database.record(&samples).unwrap();
+
+        let range = database
+            .range("Kitchen", "Outdoor", Metric::Temperature, at(0), at(150))
+            .unwrap();
+        assert_eq!(range, samples[..3]);
+
+        let aggregates = database
+            .aggregate(
+                "Kitchen",
+                "Outdoor",
+                Metric::Temperature,
+                at(0),
+                at(200),
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        // 1_700_000_000 is 20 s into a minute
+        let minute = |start: u64| UNIX_EPOCH + Duration::from_secs(start);
+        assert_eq!(
+            aggregates,
+            [
+                Aggregate {
+                    start: minute(1_699_999_980),
+                    count: 2,
+                    min: 20.0,
+                    max: 22.0,
+                    mean: 21.0,
+                },
+                Aggregate {
+                    start: minute(1_700_000_040),
+                    count: 1,
+                    min: 21.0,
+                    max: 21.0,
+                    mean: 21.0,
+                },
+                Aggregate {
+                    start: minute(1_700_000_100),
+                    count: 1,
+                    min: 25.0,
+                    max: 25.0,
+                    mean: 25.0,
+                },
+            ]
+        );
+
+        assert_eq!(database.remove_before(at(60)).unwrap(), 3);
+        let left = database
+            .range("Kitchen", "Outdoor", Metric::Temperature, at(0), at(200))
+            .unwrap();
+        assert_eq!(left.len(), 2);
+    }
+}
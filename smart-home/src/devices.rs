@@ -1,6 +1,7 @@
 use std::net::ToSocketAddrs;
 
 use crate::SmartHomeError;
+use smartsocket::SocketStatus;
 
 pub mod smartsocket;
 pub mod termo;
@@ -10,3 +11,18 @@ pub trait SmartDeviceConnect {
     where
         Self: Sized;
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceState {
+    Socket { on: bool, power: f32 },
+    Thermometer { temperature: f32 },
+}
+
+impl From<SocketStatus> for DeviceState {
+    fn from(status: SocketStatus) -> Self {
+        DeviceState::Socket {
+            on: status.on,
+            power: status.power,
+        }
+    }
+}
@@ -1,25 +1,58 @@
 use crate::SmartHomeError;
+use crate::events::{DeviceEvent, Notifier, Observable};
+use crate::metrics::DeviceStats;
 
 use super::SmartDeviceConnect;
 use std::{
-    cell::RefCell,
     fmt::Debug,
-    io::{Read, Write},
-    net::{TcpStream, ToSocketAddrs},
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        Mutex, MutexGuard, TryLockError,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
 };
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SocketCommand {
     Switch,
     GetPower,
     IsOn,
+    // Identity of the device, answered with text or the capabilities bitmap.
+    GetId,
+    GetFirmware,
+    GetModel,
+    GetCapabilities,
+    // On-state and power in one response.
+    GetStatus,
     Unknown,
 }
 
+impl SocketCommand {
+    pub const ALL: [SocketCommand; 8] = [
+        SocketCommand::Switch,
+        SocketCommand::IsOn,
+        SocketCommand::GetPower,
+        SocketCommand::GetId,
+        SocketCommand::GetFirmware,
+        SocketCommand::GetModel,
+        SocketCommand::GetCapabilities,
+        SocketCommand::GetStatus,
+    ];
+}
+
 impl From<SocketCommand> for u8 {
     fn from(cmd: SocketCommand) -> Self {
         match cmd {
             SocketCommand::Switch => 0,
             SocketCommand::IsOn => 1,
             SocketCommand::GetPower => 2,
+            SocketCommand::GetId => 3,
+            SocketCommand::GetFirmware => 4,
+            SocketCommand::GetModel => 5,
+            SocketCommand::GetCapabilities => 6,
+            SocketCommand::GetStatus => 7,
             SocketCommand::Unknown => 255,
         }
     }
@@ -31,75 +64,544 @@ impl From<u8> for SocketCommand {
             0 => SocketCommand::Switch,
             1 => SocketCommand::IsOn,
             2 => SocketCommand::GetPower,
+            3 => SocketCommand::GetId,
+            4 => SocketCommand::GetFirmware,
+            5 => SocketCommand::GetModel,
+            6 => SocketCommand::GetCapabilities,
+            7 => SocketCommand::GetStatus,
             _ => SocketCommand::Unknown,
         }
     }
 }
 
+// Commands a socket understands, bit N is set for the command with code N.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub fn of(commands: &[SocketCommand]) -> Self {
+        Capabilities(
+            commands
+                .iter()
+                .filter(|command| **command != SocketCommand::Unknown)
+                .fold(0, |bits, command| bits | 1 << u8::from(*command)),
+        )
+    }
+    pub fn supports(&self, command: SocketCommand) -> bool {
+        command != SocketCommand::Unknown && self.0 & (1 << u8::from(command)) != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketStatus {
+    pub on: bool,
+    pub power: f32,
+}
+
+// Value from the cache and how long ago the socket reported it.
 #[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cached<T> {
+    pub value: T,
+    pub age: Duration,
+}
+
+// Last values the socket answered with, kept even when the cache is not used.
+#[derive(Debug, Default)]
+struct StateCache {
+    on: Option<(bool, Instant)>,
+    power: Option<(f32, Instant)>,
+    // Cached values younger than this are used instead of asking the socket, None
+    // disables the cache.
+    max_age: Option<Duration>,
+}
+
+// Last reported state, events are emitted only when it changes.
+#[derive(Debug, Default)]
+struct Seen {
+    online: Option<bool>,
+    on: Option<bool>,
+    power: Option<f32>,
+}
+
+// Every response starts with a 5 byte header: the response type and 4 bytes of value.
+// Text responses carry the text length in the header and the UTF-8 text after it, status
+// responses the on-state in the header and the power in 4 more bytes.
+#[derive(Debug, Clone, PartialEq)]
 pub enum SocketResponse {
     On(bool),
     Power(f32),
+    Text(String),
+    Capabilities(Capabilities),
+    Status(SocketStatus),
     Unknown,
 }
 
-impl From<SocketResponse> for [u8; 5] {
-    fn from(response: SocketResponse) -> Self {
-        let mut buffer = [0u8; 5];
-        match response {
+const MAX_TEXT: usize = 1024;
+
+impl SocketResponse {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = vec![0u8; 5];
+        match self {
             SocketResponse::On(is_on) => {
                 buffer[0] = 0;
-                buffer[1] = is_on as u8;
+                buffer[1] = *is_on as u8;
             }
             SocketResponse::Power(power) => {
                 buffer[0] = 1;
                 buffer[1..].copy_from_slice(&power.to_be_bytes())
             }
+            SocketResponse::Text(text) => {
+                // Longer texts are cut as a reader would reject them.
+                let mut len = text.len().min(MAX_TEXT);
+                while !text.is_char_boundary(len) {
+                    len -= 1;
+                }
+                buffer[0] = 2;
+                buffer[1..].copy_from_slice(&(len as u32).to_be_bytes());
+                buffer.extend_from_slice(&text.as_bytes()[..len]);
+            }
+            SocketResponse::Capabilities(capabilities) => {
+                buffer[0] = 3;
+                buffer[1..].copy_from_slice(&capabilities.0.to_be_bytes())
+            }
+            SocketResponse::Status(status) => {
+                buffer[0] = 4;
+                buffer[1] = status.on as u8;
+                buffer.extend_from_slice(&status.power.to_be_bytes());
+            }
             SocketResponse::Unknown => buffer[0] = 255,
         }
         buffer
     }
-}
 
-impl From<[u8; 5]> for SocketResponse {
-    fn from(bytes: [u8; 5]) -> Self {
-        match bytes {
-            [0, ..] => Self::On(bytes[1] != 0u8),
-            [1, ..] => {
-                let mut buf = [0u8; 4];
-                buf.copy_from_slice(&bytes[1..]);
-                Self::Power(f32::from_be_bytes(buf))
+    pub fn read_from(reader: &mut impl Read) -> io::Result<SocketResponse> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        let mut value = [0u8; 4];
+        value.copy_from_slice(&header[1..]);
+        Ok(match header[0] {
+            0 => Self::On(header[1] != 0u8),
+            1 => Self::Power(f32::from_be_bytes(value)),
+            2 => {
+                let len = u32::from_be_bytes(value) as usize;
+                if len > MAX_TEXT {
+                    // The body is skipped so the next response is read from its start.
+                    io::copy(&mut reader.take(len as u64), &mut io::sink())?;
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("text response of {len} bytes is too long"),
+                    ));
+                }
+                let mut text = vec![0u8; len];
+                reader.read_exact(&mut text)?;
+                Self::Text(
+                    String::from_utf8(text)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                )
+            }
+            3 => Self::Capabilities(Capabilities(u32::from_be_bytes(value))),
+            4 => {
+                let mut power = [0u8; 4];
+                reader.read_exact(&mut power)?;
+                Self::Status(SocketStatus {
+                    on: header[1] != 0u8,
+                    power: f32::from_be_bytes(power),
+                })
             }
             _ => Self::Unknown,
-        }
+        })
     }
 }
 
+// What the socket tells about itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocketIdentity {
+    pub id: String,
+    pub firmware: String,
+    pub model: String,
+    pub capabilities: Capabilities,
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: Box<dyn ReadWrite>,
+    // Second handle of a TCP stream to set its timeouts.
+    tcp: Option<TcpStream>,
+    timeout: Option<Duration>,
+    // False after a timeout, the late response would be taken as the answer to the next
+    // command.
+    in_step: bool,
+}
+
 #[derive(Debug)]
 pub struct SmartSocket {
-    stream: RefCell<Box<dyn ReadWrite>>,
+    connection: Mutex<Connection>,
+    address: Option<String>,
+    notifier: Notifier<DeviceEvent>,
+    seen: Mutex<Seen>,
+    // Cleared when the socket turns out to be too old for GetStatus.
+    has_status: AtomicBool,
+    cache: Mutex<StateCache>,
+    // Boxed to keep SmartDevice variants of similar size.
+    stats: Box<DeviceStats>,
 }
 impl SmartSocket {
     pub fn new(stream: impl ReadWrite + 'static) -> Self {
         Self {
-            stream: RefCell::new(Box::new(stream)),
+            connection: Mutex::new(Connection {
+                stream: Box::new(stream),
+                tcp: None,
+                timeout: None,
+                in_step: true,
+            }),
+            address: None,
+            notifier: Notifier::default(),
+            seen: Mutex::new(Seen::default()),
+            has_status: AtomicBool::new(true),
+            cache: Mutex::new(StateCache::default()),
+            stats: Box::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &DeviceStats {
+        &self.stats
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        lock(&self.connection)
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.connection().timeout
+    }
+
+    // Limits how long a command waits for the device. Only TCP connections have timeouts,
+    // for other streams it is a no-op.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), SmartHomeError> {
+        self.connection().apply_timeout(timeout)
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        lock(&self.cache).max_age
+    }
+
+    pub fn set_max_age(&self, max_age: Option<Duration>) {
+        lock(&self.cache).max_age = max_age;
+    }
+
+    // Last known state, however old.
+    pub fn cached_status(&self) -> Option<Cached<SocketStatus>> {
+        let cache = lock(&self.cache);
+        let ((on, on_updated), (power, power_updated)) = (cache.on?, cache.power?);
+        Some(Cached {
+            value: SocketStatus { on, power },
+            age: on_updated.min(power_updated).elapsed(),
+        })
+    }
+
+    // Cached state allowed by the max-age policy.
+    pub fn fresh_status(&self) -> Option<Cached<SocketStatus>> {
+        let max_age = self.max_age()?;
+        self.cached_status().filter(|cached| cached.age <= max_age)
+    }
+
+    fn fresh<T: Copy>(&self, value: impl FnOnce(&StateCache) -> Option<(T, Instant)>) -> Option<T> {
+        let max_age = self.max_age()?;
+        let (value, updated) = value(&lock(&self.cache))?;
+        (updated.elapsed() <= max_age).then_some(value)
+    }
+
+    fn remember(&self, command: SocketCommand, response: &SocketResponse) {
+        let mut cache = lock(&self.cache);
+        let now = Instant::now();
+        match *response {
+            SocketResponse::On(on) => {
+                cache.on = Some((on, now));
+                // Power follows the switch, the old value is no longer right.
+                if command == SocketCommand::Switch {
+                    cache.power = None;
+                }
+            }
+            SocketResponse::Power(power) => cache.power = Some((power, now)),
+            SocketResponse::Status(SocketStatus { on, power }) => {
+                cache.on = Some((on, now));
+                cache.power = Some((power, now));
+            }
+            _ => {}
         }
     }
 
+    // Stores the new value and returns the previous one.
+    fn see<T>(&self, field: impl FnOnce(&mut Seen) -> &mut T, value: T) -> T {
+        std::mem::replace(field(&mut lock(&self.seen)), value)
+    }
+
+    // Notifies listeners when a response shows the socket state has changed.
+    fn track(&self, response: &SocketResponse) {
+        match *response {
+            SocketResponse::On(on) if self.see(|seen| &mut seen.on, Some(on)) != Some(on) => {
+                self.notifier.emit(&DeviceEvent::SocketSwitched(on))
+            }
+            SocketResponse::Power(power)
+                if self.see(|seen| &mut seen.power, Some(power)) != Some(power) =>
+            {
+                self.notifier.emit(&DeviceEvent::PowerChanged(power))
+            }
+            SocketResponse::Status(SocketStatus { on, power }) => {
+                self.track(&SocketResponse::On(on));
+                self.track(&SocketResponse::Power(power));
+            }
+            _ => {}
+        }
+    }
+
+    fn set_online(&self, online: bool) {
+        if self.see(|seen| &mut seen.online, Some(online)) != Some(online) {
+            self.notifier.emit(&if online {
+                DeviceEvent::DeviceOnline
+            } else {
+                DeviceEvent::DeviceOffline
+            });
+        }
+    }
+
+    // Address the socket was connected to, None for sockets made from a stream.
+    pub fn address(&self) -> Option<&str> {
+        self.address.as_deref()
+    }
+
     pub(crate) fn run_command(
         &self,
         command: SocketCommand,
     ) -> Result<SocketResponse, SmartHomeError> {
-        self.stream.borrow_mut().write_all(&[command.into()])?;
-        let mut buffer = [0u8; 5];
-        self.stream.borrow_mut().read_exact(&mut buffer)?;
-        Ok(buffer.into())
+        Ok(self.run_batch(&[command])?.remove(0))
+    }
+
+    // Sends all commands at once and then reads the responses, so the batch costs one
+    // round-trip. All responses are read even if one is wrong to keep the stream in step,
+    // the first wrong one is returned as the error.
+    pub fn run_batch(
+        &self,
+        commands: &[SocketCommand],
+    ) -> Result<Vec<SocketResponse>, SmartHomeError> {
+        self.run_batch_until(commands, None)
+    }
+
+    // With an end the batch fails with a timeout once it passes, also while another thread
+    // is using the connection.
+    fn run_batch_until(
+        &self,
+        commands: &[SocketCommand],
+        end: Option<Instant>,
+    ) -> Result<Vec<SocketResponse>, SmartHomeError> {
+        if commands.is_empty() {
+            return Ok(Vec::new());
+        }
+        let in_command = |err: SmartHomeError, command: String| {
+            err.with_context(|context| {
+                context.address = self.address.clone();
+                context.command = Some(command);
+            })
+        };
+        let started = Instant::now();
+        let result = self.exchange(commands, end);
+        if result.is_ok() {
+            self.stats.command(started.elapsed());
+        }
+        self.set_online(result.is_ok());
+        let responses = result.map_err(|err| {
+            self.stats.error(&err);
+            let names: Vec<String> = commands.iter().map(|c| format!("{c:?}")).collect();
+            in_command(err, names.join("+"))
+        })?;
+        let mut wrong = None;
+        for (command, response) in commands.iter().zip(&responses) {
+            match check(*command, response) {
+                Ok(()) => {
+                    self.remember(*command, response);
+                    self.track(response);
+                }
+                Err(err) => {
+                    wrong = wrong.or(Some(in_command(err, format!("{command:?}"))));
+                }
+            }
+        }
+        match wrong {
+            Some(err) => {
+                self.stats.error(&err);
+                Err(err)
+            }
+            None => Ok(responses),
+        }
+    }
+
+    fn exchange(
+        &self,
+        commands: &[SocketCommand],
+        end: Option<Instant>,
+    ) -> Result<Vec<SocketResponse>, SmartHomeError> {
+        let Some(end) = end else {
+            return self.exchange_on(&mut self.connection(), commands);
+        };
+        // Timeouts are cut to the time left for this exchange only.
+        let mut connection = lock_before(&self.connection, end)?;
+        let previous = connection.timeout;
+        let left = time_left(end)?;
+        connection.apply_timeout(Some(previous.map_or(left, |timeout| timeout.min(left))))?;
+        let result = self.exchange_on(&mut connection, commands);
+        connection.apply_timeout(previous)?;
+        result
+    }
+
+    fn exchange_on(
+        &self,
+        connection: &mut Connection,
+        commands: &[SocketCommand],
+    ) -> Result<Vec<SocketResponse>, SmartHomeError> {
+        if !connection.in_step {
+            self.reconnect(connection)?;
+        }
+        // After any failure, a timeout, a closed connection or a broken frame, it is unknown
+        // what is left on the stream.
+        let result = write_and_read(&mut connection.stream, commands);
+        if result.is_err() {
+            connection.in_step = false;
+        }
+        Ok(result?)
+    }
+
+    fn reconnect(&self, connection: &mut Connection) -> Result<(), SmartHomeError> {
+        let Some(address) = &self.address else {
+            return Err(SmartHomeError::ConnectionError(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "stream is out of step after an error and has no address to connect again",
+            )));
+        };
+        // Connecting is limited by the timeout too, a host which drops packets would keep
+        // the connection locked for minutes otherwise.
+        let stream = match connection.timeout {
+            Some(timeout) => {
+                let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+                    SmartHomeError::ValidationError(format!("no address for '{address}'"))
+                })?;
+                TcpStream::connect_timeout(&address, timeout)?
+            }
+            None => TcpStream::connect(address.as_str())?,
+        };
+        stream.set_read_timeout(connection.timeout)?;
+        stream.set_write_timeout(connection.timeout)?;
+        connection.tcp = Some(stream.try_clone()?);
+        connection.stream = Box::new(stream);
+        connection.in_step = true;
+        self.stats.reconnect();
+        Ok(())
+    }
+}
+
+fn write_and_read(
+    stream: &mut Box<dyn ReadWrite>,
+    commands: &[SocketCommand],
+) -> io::Result<Vec<SocketResponse>> {
+    let bytes: Vec<u8> = commands.iter().map(|command| u8::from(*command)).collect();
+    stream.write_all(&bytes)?;
+    let mut responses = Vec::with_capacity(commands.len());
+    for _ in commands {
+        responses.push(SocketResponse::read_from(stream)?);
+    }
+    Ok(responses)
+}
+
+impl Connection {
+    // Only TCP connections have timeouts, for other streams it is just remembered.
+    fn apply_timeout(&mut self, timeout: Option<Duration>) -> Result<(), SmartHomeError> {
+        if let Some(tcp) = &self.tcp {
+            tcp.set_read_timeout(timeout)?;
+            tcp.set_write_timeout(timeout)?;
+        }
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(g) => g,
+        Err(poison_error) => poison_error.into_inner(),
+    }
+}
+
+// Waits for the lock until the end at most.
+fn lock_before<T>(mutex: &Mutex<T>, end: Instant) -> Result<MutexGuard<'_, T>, SmartHomeError> {
+    loop {
+        match mutex.try_lock() {
+            Ok(g) => return Ok(g),
+            Err(TryLockError::Poisoned(poison_error)) => return Ok(poison_error.into_inner()),
+            Err(TryLockError::WouldBlock) => {
+                time_left(end)?;
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+fn time_left(end: Instant) -> Result<Duration, SmartHomeError> {
+    match end.checked_duration_since(Instant::now()) {
+        Some(left) if !left.is_zero() => Ok(left),
+        _ => Err(io::Error::new(io::ErrorKind::TimedOut, "deadline passed").into()),
+    }
+}
+
+const UNSUPPORTED: &str = "command is not supported by the device";
+
+fn check(command: SocketCommand, response: &SocketResponse) -> Result<(), SmartHomeError> {
+    let expected = matches!(
+        (command, response),
+        (
+            SocketCommand::Switch | SocketCommand::IsOn,
+            SocketResponse::On(_)
+        ) | (SocketCommand::GetPower, SocketResponse::Power(_))
+            | (
+                SocketCommand::GetId | SocketCommand::GetFirmware | SocketCommand::GetModel,
+                SocketResponse::Text(_)
+            )
+            | (
+                SocketCommand::GetCapabilities,
+                SocketResponse::Capabilities(_)
+            )
+            | (SocketCommand::GetStatus, SocketResponse::Status(_))
+            | (SocketCommand::Unknown, SocketResponse::Unknown)
+    );
+    match response {
+        _ if expected => Ok(()),
+        SocketResponse::Unknown => Err(SmartHomeError::ProtocolError(UNSUPPORTED.to_string())),
+        _ => Err(SmartHomeError::ProtocolError(format!(
+            "unexpected response {response:?}"
+        ))),
+    }
+}
+
+fn is_unsupported(err: &SmartHomeError) -> bool {
+    matches!(err.root(), SmartHomeError::ProtocolError(msg) if msg == UNSUPPORTED)
+}
+
+impl Observable for SmartSocket {
+    type Event = DeviceEvent;
+    fn notifier(&self) -> &Notifier<DeviceEvent> {
+        &self.notifier
     }
 }
 impl SmartDeviceConnect for SmartSocket {
     fn connect(address: impl ToSocketAddrs) -> Result<Self, SmartHomeError> {
-        let stream = TcpStream::connect(address)?;
-        Ok(SmartSocket::new(stream))
+        let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        let address = addresses.first().map(ToString::to_string);
+        let stream = TcpStream::connect(&addresses[..]).map_err(|err| {
+            SmartHomeError::from(err).with_context(|context| context.address = address.clone())
+        })?;
+        let tcp = stream.try_clone()?;
+        let mut socket = SmartSocket::new(stream);
+        socket.connection().tcp = Some(tcp);
+        socket.address = address;
+        Ok(socket)
     }
 }
 
@@ -108,33 +610,139 @@ impl SmartSocket {
         match self.run_command(SocketCommand::Switch) {
             Ok(SocketResponse::On(_)) => Ok(()),
             Err(e) => Err(e),
-            _ => unreachable!("Invalid response for switch command"),
+            _ => unreachable!("Response to switch command is checked by run_command"),
         }
     }
     pub fn get_power(&self) -> Result<f32, SmartHomeError> {
+        if let Some(power) = self.fresh(|cache| cache.power) {
+            return Ok(power);
+        }
         match self.run_command(SocketCommand::GetPower) {
             Ok(SocketResponse::Power(power)) => Ok(power),
             Err(e) => Err(e),
-            _ => unreachable!("Invalid response for power command"),
+            _ => unreachable!("Response to power command is checked by run_command"),
+        }
+    }
+    // The socket is always asked before switching, a cached state may be out of date and
+    // switching would then do the opposite.
+    pub fn turn_on(&self) -> Result<(), SmartHomeError> {
+        if !self.ask_on()? {
+            self.switch()?;
+        }
+        Ok(())
+    }
+    pub fn turn_off(&self) -> Result<(), SmartHomeError> {
+        if self.ask_on()? {
+            self.switch()?;
         }
+        Ok(())
     }
     pub fn is_on(&self) -> Result<bool, SmartHomeError> {
+        match self.fresh(|cache| cache.on) {
+            Some(on) => Ok(on),
+            None => self.ask_on(),
+        }
+    }
+    fn ask_on(&self) -> Result<bool, SmartHomeError> {
         match self.run_command(SocketCommand::IsOn) {
             Ok(SocketResponse::On(is_on)) => Ok(is_on),
             Err(e) => Err(e),
-            _ => unreachable!("Invalid response for IsOn command"),
+            _ => unreachable!("Response to IsOn command is checked by run_command"),
+        }
+    }
+    // Cached state if it is fresh enough, the socket is asked otherwise.
+    pub fn status(&self) -> Result<SocketStatus, SmartHomeError> {
+        match self.fresh_status() {
+            Some(cached) => Ok(cached.value),
+            None => self.refresh(),
+        }
+    }
+
+    // On-state and power in one round-trip, the cache is not used. Sockets without
+    // GetStatus get both commands in one batch instead.
+    pub fn refresh(&self) -> Result<SocketStatus, SmartHomeError> {
+        self.refresh_until(None)
+    }
+
+    // Refresh which fails with a timeout instead of running past the end.
+    pub fn refresh_before(&self, end: Instant) -> Result<SocketStatus, SmartHomeError> {
+        self.refresh_until(Some(end))
+    }
+
+    fn refresh_until(&self, end: Option<Instant>) -> Result<SocketStatus, SmartHomeError> {
+        if self.has_status.load(Ordering::Relaxed) {
+            let response = self
+                .run_batch_until(&[SocketCommand::GetStatus], end)
+                .map(|mut responses| responses.remove(0));
+            match response {
+                Ok(SocketResponse::Status(status)) => return Ok(status),
+                Err(err) if is_unsupported(&err) => self.has_status.store(false, Ordering::Relaxed),
+                Err(err) => return Err(err),
+                _ => unreachable!("Response to status command is checked by run_command"),
+            }
+        }
+        match self.run_batch_until(&[SocketCommand::IsOn, SocketCommand::GetPower], end)?[..] {
+            [SocketResponse::On(on), SocketResponse::Power(power)] => {
+                Ok(SocketStatus { on, power })
+            }
+            _ => unreachable!("Responses to the status batch are checked by run_batch"),
+        }
+    }
+}
+
+impl SmartSocket {
+    fn text(&self, command: SocketCommand) -> Result<String, SmartHomeError> {
+        match self.run_command(command)? {
+            SocketResponse::Text(text) => Ok(text),
+            _ => unreachable!("Response to {command:?} command is checked by run_command"),
+        }
+    }
+    pub fn id(&self) -> Result<String, SmartHomeError> {
+        self.text(SocketCommand::GetId)
+    }
+    pub fn firmware(&self) -> Result<String, SmartHomeError> {
+        self.text(SocketCommand::GetFirmware)
+    }
+    pub fn model(&self) -> Result<String, SmartHomeError> {
+        self.text(SocketCommand::GetModel)
+    }
+    pub fn capabilities(&self) -> Result<Capabilities, SmartHomeError> {
+        match self.run_command(SocketCommand::GetCapabilities)? {
+            SocketResponse::Capabilities(capabilities) => Ok(capabilities),
+            _ => unreachable!("Response to capabilities command is checked by run_command"),
+        }
+    }
+    pub fn identity(&self) -> Result<SocketIdentity, SmartHomeError> {
+        Ok(SocketIdentity {
+            id: self.id()?,
+            firmware: self.firmware()?,
+            model: self.model()?,
+            capabilities: self.capabilities()?,
+        })
+    }
+    // Fails when the socket is not the expected device, e.g. after an address change.
+    pub fn verify_id(&self, expected: &str) -> Result<(), SmartHomeError> {
+        let id = self.id()?;
+        if id != expected {
+            return Err(SmartHomeError::ValidationError(format!(
+                "expected socket {expected}, connected to {id}"
+            )));
         }
+        Ok(())
     }
 }
 
-pub trait ReadWrite: Read + Write + Debug {}
+pub trait ReadWrite: Read + Write + Debug + Send {}
 
-impl<T: Read + Write + Debug> ReadWrite for T {}
+impl<T: Read + Write + Debug + Send> ReadWrite for T {}
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::emulator::{EMULATED_MODEL, EmulatedStream, Fleet, FleetDescription, FleetRoom};
+    use crate::errors::ErrorCode;
+    use std::sync::{Arc, Mutex};
     #[derive(Debug)]
     pub(crate) struct FakeSocket {
         response: SocketResponse,
@@ -151,7 +759,7 @@ mod tests {
 
     impl Read for FakeSocket {
         fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-            let resp: [u8; 5] = self.response.into();
+            let resp = self.response.to_bytes();
             buf.copy_from_slice(&resp);
             Ok(5)
         }
@@ -164,6 +772,284 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_socket_events() {
+        let smart_socket = SmartSocket::new(FakeSocket::default());
+        let rx = smart_socket.subscribe_channel();
+        smart_socket.switch().unwrap();
+        smart_socket.is_on().unwrap();
+        assert_eq!(rx.try_recv(), Ok(DeviceEvent::DeviceOnline));
+        assert_eq!(rx.try_recv(), Ok(DeviceEvent::SocketSwitched(true)));
+        assert!(rx.try_recv().is_err());
+
+        let broken = SmartSocket::new(std::io::Cursor::new(Vec::new()));
+        let rx = broken.subscribe_channel();
+        assert!(broken.is_on().is_err());
+        assert!(broken.is_on().is_err());
+        assert_eq!(rx.try_recv(), Ok(DeviceEvent::DeviceOffline));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_protocol_error() {
+        let smart_socket = SmartSocket::new(FakeSocket {
+            response: SocketResponse::Power(10.),
+        });
+        let err = smart_socket.is_on().unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Protocol);
+        assert_eq!(err.context().unwrap().command.as_deref(), Some("IsOn"));
+        assert_eq!(smart_socket.get_power().unwrap(), 10.);
+
+        let broken = SmartSocket::new(std::io::Cursor::new(Vec::new()));
+        let err = broken.switch().unwrap_err();
+        assert!(matches!(err.root(), SmartHomeError::ConnectionError(_)));
+        assert!(err.to_string().ends_with("(command Switch)"));
+    }
+
+    #[test]
+    fn test_response_bytes() {
+        for response in [
+            SocketResponse::On(true),
+            SocketResponse::Power(12.5),
+            SocketResponse::Text("Kitchen-Kettle".to_string()),
+            SocketResponse::Capabilities(Capabilities::of(&SocketCommand::ALL)),
+            SocketResponse::Status(SocketStatus {
+                on: true,
+                power: 1500.,
+            }),
+            SocketResponse::Unknown,
+        ] {
+            let bytes = response.to_bytes();
+            let decoded = SocketResponse::read_from(&mut bytes.as_slice()).unwrap();
+            assert_eq!(decoded, response);
+        }
+
+        let text = SocketResponse::Text("ж".repeat(600)).to_bytes();
+        let decoded = SocketResponse::read_from(&mut text.as_slice()).unwrap();
+        assert_eq!(decoded, SocketResponse::Text("ж".repeat(512)));
+
+        // A too long text is skipped whole, the next response is still read
+        let mut bytes = vec![2, 0, 0, 0x07, 0xd0];
+        bytes.extend_from_slice(&[b'x'; 2000]);
+        bytes.extend_from_slice(&SocketResponse::On(true).to_bytes());
+        let mut reader = bytes.as_slice();
+        assert!(SocketResponse::read_from(&mut reader).is_err());
+        assert_eq!(
+            SocketResponse::read_from(&mut reader).unwrap(),
+            SocketResponse::On(true)
+        );
+    }
+
+    #[test]
+    fn test_identity() {
+        let socket = SmartSocket::new(EmulatedStream::new());
+        let identity = socket.identity().unwrap();
+        assert_eq!(identity.id, "emulated");
+        assert_eq!(identity.model, EMULATED_MODEL);
+        assert!(identity.capabilities.supports(SocketCommand::GetPower));
+        assert!(!identity.capabilities.supports(SocketCommand::Unknown));
+        assert!(socket.verify_id("emulated").is_ok());
+        assert_eq!(
+            socket.verify_id("Kitchen-Kettle").unwrap_err().code(),
+            ErrorCode::Validation
+        );
+
+        // Devices without identity commands answer Unknown
+        let old = SmartSocket::new(FakeSocket {
+            response: SocketResponse::Unknown,
+        });
+        let err = old.id().unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Protocol);
+        assert!(err.to_string().contains("not supported"));
+    }
+
+    // Socket from before GetStatus which records every write.
+    #[derive(Debug, Default)]
+    struct LegacySocket {
+        socket: EmulatedStream,
+        writes: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl Write for LegacySocket {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes.lock().unwrap().push(buf.to_vec());
+            let old: Vec<u8> = buf
+                .iter()
+                .map(|&command| match SocketCommand::from(command) {
+                    SocketCommand::GetStatus => u8::from(SocketCommand::Unknown),
+                    _ => command,
+                })
+                .collect();
+            self.socket.write(&old)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for LegacySocket {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.socket.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_batch() {
+        let socket = SmartSocket::new(EmulatedStream::new());
+        let responses = socket
+            .run_batch(&[
+                SocketCommand::IsOn,
+                SocketCommand::Switch,
+                SocketCommand::GetPower,
+            ])
+            .unwrap();
+        assert_eq!(
+            responses,
+            [
+                SocketResponse::On(false),
+                SocketResponse::On(true),
+                SocketResponse::Power(1000.)
+            ]
+        );
+        assert_eq!(
+            socket.status().unwrap(),
+            SocketStatus {
+                on: true,
+                power: 1000.
+            }
+        );
+        assert!(socket.run_batch(&[]).unwrap().is_empty());
+
+        // A wrong response fails the batch, the stream stays in step
+        let legacy = SmartSocket::new(LegacySocket::default());
+        let err = legacy
+            .run_batch(&[SocketCommand::GetStatus, SocketCommand::Switch])
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Protocol);
+        assert!(err.to_string().ends_with("(command GetStatus)"));
+        assert!(legacy.is_on().unwrap());
+    }
+
+    #[test]
+    fn test_status_fallback() {
+        let legacy = LegacySocket::default();
+        let writes = legacy.writes.clone();
+        let socket = SmartSocket::new(legacy);
+        let expected = SocketStatus {
+            on: false,
+            power: 0.,
+        };
+        assert_eq!(socket.status().unwrap(), expected);
+        assert_eq!(socket.status().unwrap(), expected);
+        // GetStatus is tried once, then both commands go in one write
+        assert_eq!(*writes.lock().unwrap(), [vec![7], vec![1, 2], vec![1, 2]]);
+    }
+
+    #[test]
+    fn test_cache() {
+        let legacy = LegacySocket::default();
+        let writes = legacy.writes.clone();
+        let sent = || writes.lock().unwrap().len();
+        let socket = SmartSocket::new(legacy);
+        assert!(socket.cached_status().is_none());
+        socket.is_on().unwrap();
+        socket.is_on().unwrap();
+        assert_eq!(sent(), 2);
+
+        socket.set_max_age(Some(Duration::from_secs(60)));
+        assert!(!socket.is_on().unwrap());
+        assert_eq!(sent(), 2);
+        assert_eq!(socket.get_power().unwrap(), 0.);
+        assert_eq!(sent(), 3);
+        let cached = socket.fresh_status().unwrap();
+        assert_eq!(cached.value, socket.status().unwrap());
+        assert_eq!(sent(), 3);
+
+        // Switching makes the cached power unknown
+        socket.switch().unwrap();
+        assert!(socket.is_on().unwrap());
+        assert!(socket.fresh_status().is_none());
+        assert_eq!(socket.get_power().unwrap(), 1000.);
+        assert_eq!(sent(), 5);
+        assert_eq!(socket.refresh().unwrap().power, 1000.);
+        assert_eq!(sent(), 7);
+
+        socket.set_max_age(Some(Duration::from_millis(1)));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(socket.fresh_status().is_none());
+        assert!(socket.cached_status().unwrap().age >= Duration::from_millis(5));
+        assert!(socket.is_on().unwrap());
+        assert_eq!(sent(), 8);
+    }
+
+    #[test]
+    fn test_turn_on_with_stale_cache() {
+        let stream = EmulatedStream::new();
+        let socket = SmartSocket::new(stream.clone());
+        socket.set_max_age(Some(Duration::from_secs(60)));
+        assert!(!socket.is_on().unwrap());
+
+        // Another client switches the socket on behind the cache
+        let other = SmartSocket::new(stream.clone());
+        other.switch().unwrap();
+        assert!(!socket.is_on().unwrap());
+
+        socket.turn_on().unwrap();
+        assert!(other.is_on().unwrap());
+        other.switch().unwrap();
+        socket.turn_off().unwrap();
+        assert!(!other.is_on().unwrap());
+    }
+
+    #[test]
+    fn test_reconnect_timeout() {
+        // Nothing answers on this address, connecting would hang without a timeout
+        let mut socket = SmartSocket::new(FakeSocket::default());
+        socket.address = Some("10.255.255.1:9".to_string());
+        socket.connection().in_step = false;
+        socket
+            .set_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let started = Instant::now();
+        assert!(socket.is_on().is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        socket.set_timeout(None).unwrap();
+        let started = Instant::now();
+        let end = started + Duration::from_millis(200);
+        assert!(socket.refresh_before(end).is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_reconnect_after_eof() {
+        let fleet = Fleet::new(&FleetDescription {
+            base_port: 0,
+            rooms: vec![FleetRoom {
+                name: "Room".to_string(),
+                sockets: 1,
+                thermometers: 0,
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let address = fleet.config().rooms[0].devices[0].address.clone();
+        let _fleet = fleet.start();
+
+        // The stream is closed as after a restart of the plug
+        let mut socket = SmartSocket::new(std::io::Cursor::new(Vec::new()));
+        socket.address = Some(address);
+        let err = socket.is_on().unwrap_err();
+        assert!(matches!(err.root(), SmartHomeError::ConnectionError(_)));
+        assert!(!socket.is_on().unwrap());
+        assert!(socket.connection().tcp.is_some());
+
+        let broken = SmartSocket::new(std::io::Cursor::new(Vec::new()));
+        assert!(broken.is_on().is_err());
+        let err = broken.is_on().unwrap_err();
+        assert!(err.to_string().contains("no address to connect again"));
+    }
+
     #[test]
     fn test_socket_command() {
         let smart_socket = SmartSocket::new(FakeSocket::default());
@@ -1,4 +1,6 @@
 use crate::SmartHomeError;
+use crate::events::{DeviceEvent, Notifier, Observable};
+use crate::metrics::DeviceStats;
 
 use super::SmartDeviceConnect;
 use std::{
@@ -9,18 +11,28 @@ use std::{
         atomic::{AtomicBool, Ordering},
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
+const OFFLINE_AFTER: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
 pub struct SmartThermometer {
     temperature: Arc<Temperature>,
     finished: Arc<AtomicBool>,
+    notifier: Arc<Notifier<DeviceEvent>>,
+    stats: Arc<DeviceStats>,
 }
 
 impl SmartThermometer {
     pub fn get_temperature(&self) -> f32 {
         self.temperature.get()
     }
+    pub fn last_update(&self) -> Option<Instant> {
+        self.temperature.updated()
+    }
+    pub fn stats(&self) -> &DeviceStats {
+        &self.stats
+    }
 }
 
 pub trait UdpLike {
@@ -39,28 +51,65 @@ impl UdpLike for UdpSocket {
 }
 
 impl SmartThermometer {
-    pub fn new(mut stream: impl UdpLike + Send + 'static) -> Self {
+    pub fn new(stream: impl UdpLike + Send + 'static) -> Self {
+        Self::new_with_offline_timeout(stream, OFFLINE_AFTER)
+    }
+
+    // Thermometer is reported offline when no datagram came for `offline_after`.
+    pub fn new_with_offline_timeout(
+        mut stream: impl UdpLike + Send + 'static,
+        offline_after: Duration,
+    ) -> Self {
         let finished = Arc::new(AtomicBool::new(false));
         let temperature = Arc::new(Temperature::default());
+        let notifier = Arc::new(Notifier::default());
+        let stats = Arc::new(DeviceStats::default());
 
         let finished_clone = finished.clone();
         let temperature_clone = temperature.clone();
+        let notifier_clone = notifier.clone();
+        let stats_clone = stats.clone();
 
         thread::spawn(move || {
-            println!("Thread started");
+            let mut online = None;
+            let mut last_datagram = Instant::now();
             loop {
                 if finished_clone.load(Ordering::SeqCst) {
                     return;
                 }
 
-                let mut buf = [0; 4];
-                if let Err(err) = stream.recv_from(&mut buf) {
-                    println!("can't receive datagram: {err}");
+                // Larger than a reading, so longer datagrams are not cut to look like one.
+                let mut buf = [0; 16];
+                let size = match stream.recv_from(&mut buf) {
+                    Ok((size, _)) => size,
+                    Err(err) => {
+                        if !matches!(
+                            err.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) {
+                            eprintln!("can't receive datagram: {err}");
+                        }
+                        if online != Some(false) && last_datagram.elapsed() > offline_after {
+                            online = Some(false);
+                            notifier_clone.emit(&DeviceEvent::DeviceOffline);
+                        }
+                        continue;
+                    }
+                };
+                let val = f32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+                let valid = size == 4 && val.is_finite();
+                stats_clone.datagram(valid);
+                if !valid {
+                    continue;
                 }
 
-                let val = f32::from_be_bytes(buf);
+                last_datagram = Instant::now();
+                if online != Some(true) {
+                    online = Some(true);
+                    notifier_clone.emit(&DeviceEvent::DeviceOnline);
+                }
                 temperature_clone.set(val);
-                println!("Temperature: {val}");
+                notifier_clone.emit(&DeviceEvent::TemperatureUpdated(val));
                 thread::sleep(std::time::Duration::from_secs(1));
             }
         });
@@ -68,10 +117,19 @@ impl SmartThermometer {
         Self {
             temperature,
             finished,
+            notifier,
+            stats,
         }
     }
 }
 
+impl Observable for SmartThermometer {
+    type Event = DeviceEvent;
+    fn notifier(&self) -> &Notifier<DeviceEvent> {
+        &self.notifier
+    }
+}
+
 impl SmartDeviceConnect for SmartThermometer {
     fn connect(address: impl ToSocketAddrs) -> Result<Self, SmartHomeError> {
         let socket = UdpSocket::bind(address)?;
@@ -86,7 +144,7 @@ impl Drop for SmartThermometer {
 }
 
 #[derive(Default, Debug)]
-struct Temperature(Mutex<f32>);
+struct Temperature(Mutex<(f32, Option<Instant>)>);
 
 impl Temperature {
     pub fn get(&self) -> f32 {
@@ -94,7 +152,15 @@ impl Temperature {
             Ok(g) => g,
             Err(poison_error) => poison_error.into_inner(),
         };
-        *guard
+        guard.0
+    }
+
+    pub fn updated(&self) -> Option<Instant> {
+        let guard = match self.0.lock() {
+            Ok(g) => g,
+            Err(poison_error) => poison_error.into_inner(),
+        };
+        guard.1
     }
 
     pub fn set(&self, val: f32) {
@@ -102,7 +168,7 @@ impl Temperature {
             Ok(g) => g,
             Err(poison_error) => poison_error.into_inner(),
         };
-        *guard = val;
+        *guard = (val, Some(Instant::now()));
     }
 }
 
@@ -144,5 +210,40 @@ mod tests {
         let termo = SmartThermometer::new(stream);
         thread::sleep(std::time::Duration::from_secs(2));
         assert_eq!(termo.get_temperature(), 23.0);
+        assert!(termo.last_update().is_some());
+    }
+
+    struct SilentUdpSocket;
+
+    impl UdpLike for SilentUdpSocket {
+        fn send_to(&mut self, buf: &[u8], _target: SocketAddr) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn recv_from(&mut self, _buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            thread::sleep(Duration::from_millis(10));
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    #[test]
+    fn test_thermometer_events() {
+        let mut stream = FakeUdpSocket::new();
+        stream
+            .send_to(&21f32.to_be_bytes(), SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap();
+        let termo = SmartThermometer::new(stream);
+        let events = termo.subscribe_channel();
+        let timeout = Duration::from_secs(3);
+        // Online event may be emitted before the subscription, readings are repeated
+        assert!(
+            (0..5)
+                .any(|_| events.recv_timeout(timeout) == Ok(DeviceEvent::TemperatureUpdated(21.)))
+        );
+
+        let termo =
+            SmartThermometer::new_with_offline_timeout(SilentUdpSocket, Duration::from_millis(200));
+        let events = termo.subscribe_channel();
+        assert_eq!(events.recv_timeout(timeout), Ok(DeviceEvent::DeviceOffline));
+        assert!(termo.last_update().is_none());
     }
 }
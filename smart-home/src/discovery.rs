@@ -0,0 +1,439 @@
+use crate::config::{DeviceConfig, DeviceKind, HomeConfig, RoomConfig};
+use crate::metadata::{DeviceInfo, RoomInfo};
+use crate::{SmartDevice, SmartHomeError};
+use std::{
+    fmt,
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+// Discovery is a UDP exchange: a client sends the probe to the discovery port, usually as
+// a broadcast, and every responder answers with one line per device it serves:
+//
+//   SMART-HOME DISCOVER
+//   SMART-HOME socket Kitchen-Kettle 5000
+//
+// The device address is the address the answer came from with the announced port. For
+// sockets it is where they accept connections. Thermometers send readings to the announced
+// port of the home, so their config binds that port on this host.
+//
+// Only one responder can own the discovery port of a host. Others send their announcement
+// lines to it over loopback every REGISTER_PERIOD and it answers probes for them too, until
+// they stop registering. See `Responder::share`.
+
+pub const DISCOVERY_PORT: u16 = 4300;
+const PROBE: &str = "SMART-HOME DISCOVER";
+const PREFIX: &str = "SMART-HOME ";
+const REGISTER_PERIOD: Duration = Duration::from_secs(1);
+// Registered announcements are dropped when not renewed for this long.
+const REGISTRATION_TTL: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Announcement {
+    pub kind: DeviceKind,
+    pub id: String,
+    pub port: u16,
+}
+
+impl Announcement {
+    pub fn new(kind: DeviceKind, id: impl Into<String>, port: u16) -> Self {
+        Self {
+            kind,
+            id: id.into(),
+            port,
+        }
+    }
+}
+
+impl fmt::Display for Announcement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{PREFIX}{} {} {}", self.kind, self.id, self.port)
+    }
+}
+
+impl FromStr for Announcement {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SmartHomeError::ProtocolError(format!("invalid announcement '{s}'"));
+        let fields: Vec<&str> = s
+            .strip_prefix(PREFIX)
+            .ok_or_else(invalid)?
+            .split_whitespace()
+            .collect();
+        match fields[..] {
+            [kind, id, port] => Ok(Announcement::new(
+                kind.parse().map_err(|_| invalid())?,
+                id,
+                port.parse().map_err(|_| invalid())?,
+            )),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Device found on the network.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub kind: DeviceKind,
+    pub id: String,
+    pub address: SocketAddr,
+}
+
+impl Candidate {
+    pub fn config(&self) -> DeviceConfig {
+        // Loopback thermometers keep the address, so the home does not listen on every
+        // interface for devices of its own host.
+        let address = match self.kind {
+            DeviceKind::Thermometer if !self.address.ip().is_loopback() => {
+                SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), self.address.port())
+            }
+            _ => self.address,
+        };
+        DeviceConfig {
+            // Dots separate metadata fields from device names in the home config.
+            name: self.id.replace('.', "-"),
+            kind: self.kind,
+            address: address.to_string(),
+            tags: Vec::new(),
+            info: DeviceInfo::default(),
+        }
+    }
+
+    pub fn connect(&self) -> Result<SmartDevice, SmartHomeError> {
+        self.config().connect()
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.kind, self.id, self.address)
+    }
+}
+
+// Broadcasts the probe on the local network and collects answers until the timeout.
+// Responders bound to the loopback interface do not get broadcasts, so they are probed
+// separately.
+pub fn discover(timeout: Duration) -> Result<Vec<Candidate>, SmartHomeError> {
+    probe(
+        &[
+            (Ipv4Addr::BROADCAST, DISCOVERY_PORT).into(),
+            (Ipv4Addr::LOCALHOST, DISCOVERY_PORT).into(),
+        ],
+        timeout,
+    )
+}
+
+// Sends the probe to the given address, which may be a single responder.
+pub fn discover_at(
+    target: impl ToSocketAddrs,
+    timeout: Duration,
+) -> Result<Vec<Candidate>, SmartHomeError> {
+    let targets: Vec<SocketAddr> = target.to_socket_addrs()?.collect();
+    probe(&targets, timeout)
+}
+
+// Fails only when the probe could not be sent anywhere, a network without broadcast is
+// not an error.
+fn probe(targets: &[SocketAddr], timeout: Duration) -> Result<Vec<Candidate>, SmartHomeError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+    let mut sent = Err(SmartHomeError::ValidationError(
+        "no address to probe".to_string(),
+    ));
+    for target in targets {
+        match socket.send_to(PROBE.as_bytes(), target) {
+            Ok(_) => sent = Ok(()),
+            Err(err) if sent.is_err() => sent = Err(err.into()),
+            Err(_) => {}
+        }
+    }
+    sent?;
+
+    let deadline = Instant::now() + timeout;
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut buffer = [0u8; 2048];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        socket.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+        let (size, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // Answers of other protocols or versions are ignored.
+        let text = String::from_utf8_lossy(&buffer[..size]);
+        for announcement in text.lines().filter_map(|line| line.parse().ok()) {
+            let Announcement { kind, id, port } = announcement;
+            let candidate = Candidate {
+                kind,
+                id,
+                address: SocketAddr::new(from.ip(), port),
+            };
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates.sort_by(|a, b| (&a.id, a.address).cmp(&(&b.id, b.address)));
+    Ok(candidates)
+}
+
+// Answers discovery probes for the devices of an emulator or a device.
+#[derive(Debug)]
+pub struct Responder {
+    socket: UdpSocket,
+    announcements: Vec<Announcement>,
+    // Announcements of other responders of this host with their last registration.
+    registered: Vec<(Announcement, Instant)>,
+}
+
+impl Responder {
+    pub fn bind(
+        address: impl ToSocketAddrs,
+        announcements: Vec<Announcement>,
+    ) -> Result<Responder, SmartHomeError> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Responder {
+            socket,
+            announcements,
+            registered: Vec::new(),
+        })
+    }
+
+    // Serves probes on the address, or registers the announcements with the responder
+    // already serving it. Takes the address over when that responder goes away. Returns
+    // only when the address can't be used at all.
+    pub fn share(address: SocketAddr, announcements: Vec<Announcement>) -> SmartHomeError {
+        let owner = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), address.port());
+        loop {
+            match Responder::bind(address, announcements.clone()) {
+                Ok(responder) => return responder.serve(),
+                Err(SmartHomeError::ConnectionError(err)) if err.kind() == ErrorKind::AddrInUse => {
+                    if let Err(err) = register(owner, &announcements) {
+                        return err;
+                    }
+                    thread::sleep(REGISTER_PERIOD);
+                }
+                Err(err) => return err,
+            }
+        }
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, SmartHomeError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    // Answers every waiting probe and takes registrations, returns true if there were any
+    // probes.
+    pub fn poll(&mut self) -> Result<bool, SmartHomeError> {
+        let mut answered = false;
+        let mut buffer = [0u8; 2048];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((size, from)) if &buffer[..size] == PROBE.as_bytes() => {
+                    let answer = self.answer();
+                    self.socket.send_to(answer.as_bytes(), from)?;
+                    answered = true;
+                }
+                // Only responders of this host may register.
+                Ok((size, from)) if from.ip().is_loopback() => {
+                    let text = String::from_utf8_lossy(&buffer[..size]);
+                    for announcement in text.lines().filter_map(|line| line.parse().ok()) {
+                        self.registered.retain(|(known, _)| *known != announcement);
+                        self.registered.push((announcement, Instant::now()));
+                    }
+                }
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(answered),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // Answers probes until the socket fails.
+    pub fn serve(mut self) -> SmartHomeError {
+        loop {
+            match self.poll() {
+                Ok(true) => {}
+                Ok(false) => thread::sleep(Duration::from_millis(10)),
+                Err(err) => return err,
+            }
+        }
+    }
+
+    fn answer(&mut self) -> String {
+        self.registered
+            .retain(|(_, registered)| registered.elapsed() < REGISTRATION_TTL);
+        self.announcements
+            .iter()
+            .chain(self.registered.iter().map(|(announcement, _)| announcement))
+            .map(|announcement| format!("{announcement}\n"))
+            .collect()
+    }
+}
+
+// Asks the responder owning the discovery port to answer for these announcements.
+fn register(owner: SocketAddr, announcements: &[Announcement]) -> Result<(), SmartHomeError> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let lines: String = announcements
+        .iter()
+        .map(|announcement| format!("{announcement}\n"))
+        .collect();
+    socket.send_to(lines.as_bytes(), owner)?;
+    Ok(())
+}
+
+impl HomeConfig {
+    // Adds candidates which are not in the config yet to the room, creating it if needed.
+    // Returns the number of added devices.
+    pub fn add_discovered(&mut self, room: &str, candidates: &[Candidate]) -> usize {
+        let known: Vec<(String, DeviceKind)> = self
+            .rooms
+            .iter()
+            .flat_map(|room| &room.devices)
+            .map(|device| (device.address.clone(), device.kind))
+            .collect();
+        let new: Vec<&Candidate> = candidates
+            .iter()
+            .filter(|candidate| !known.contains(&(candidate.config().address, candidate.kind)))
+            .collect();
+        if new.is_empty() {
+            return 0;
+        }
+        let index = match self.rooms.iter().position(|r| r.name == room) {
+            Some(index) => index,
+            None => {
+                self.rooms.push(RoomConfig {
+                    name: room.to_string(),
+                    devices: Vec::new(),
+                    info: RoomInfo::default(),
+                });
+                self.rooms.len() - 1
+            }
+        };
+        let devices = &mut self.rooms[index].devices;
+        let mut added = 0;
+        for candidate in new {
+            let config = candidate.config();
+            if devices.iter().all(|device| device.name != config.name) {
+                devices.push(config);
+                added += 1;
+            }
+        }
+        added
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement() {
+        let announcement = Announcement::new(DeviceKind::Socket, "Kitchen-Kettle", 5000);
+        assert_eq!(
+            announcement.to_string(),
+            "SMART-HOME socket Kitchen-Kettle 5000"
+        );
+        assert_eq!(
+            announcement.to_string().parse::<Announcement>().unwrap(),
+            announcement
+        );
+        assert!("SMART-HOME socket 5000".parse::<Announcement>().is_err());
+        assert!("OTHER socket Kettle 5000".parse::<Announcement>().is_err());
+    }
+
+    #[test]
+    fn test_discover() {
+        let mut responder = Responder::bind(
+            "127.0.0.1:0",
+            vec![
+                Announcement::new(DeviceKind::Socket, "Kettle", 5000),
+                Announcement::new(DeviceKind::Thermometer, "Outdoor", 5001),
+            ],
+        )
+        .unwrap();
+        let address = responder.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while !responder.poll().unwrap() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let candidates = discover_at(address, Duration::from_millis(300)).unwrap();
+        server.join().unwrap();
+        assert_eq!(
+            candidates,
+            [
+                Candidate {
+                    kind: DeviceKind::Socket,
+                    id: "Kettle".to_string(),
+                    address: "127.0.0.1:5000".parse().unwrap(),
+                },
+                Candidate {
+                    kind: DeviceKind::Thermometer,
+                    id: "Outdoor".to_string(),
+                    address: "127.0.0.1:5001".parse().unwrap(),
+                },
+            ]
+        );
+
+        let mut config = HomeConfig::new("Home");
+        assert_eq!(config.add_discovered("Found", &candidates), 2);
+        assert_eq!(config.add_discovered("Found", &candidates), 0);
+        let outdoor = &config.rooms[0].devices[1];
+        assert_eq!(outdoor.name, "Outdoor");
+        assert_eq!(outdoor.address, "127.0.0.1:5001");
+    }
+
+    #[test]
+    fn test_shared_discovery() {
+        // Two emulators of one host, the second registers with the first
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        for (kind, id, device_port) in [
+            (DeviceKind::Socket, "Kettle", 5000),
+            (DeviceKind::Thermometer, "Outdoor", 5001),
+        ] {
+            let announcements = vec![Announcement::new(kind, id, device_port)];
+            thread::spawn(move || Responder::share(address, announcements));
+            thread::sleep(Duration::from_millis(100));
+        }
+        let candidates = discover_at(address, Duration::from_millis(300)).unwrap();
+        let ids: Vec<&str> = candidates.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["Kettle", "Outdoor"]);
+    }
+
+    #[test]
+    fn test_remote_thermometer() {
+        let port = UdpSocket::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let candidate = Candidate {
+            kind: DeviceKind::Thermometer,
+            id: "Outdoor".to_string(),
+            address: SocketAddr::from(([192, 0, 2, 10], port)),
+        };
+        // Readings come to this host, so the port is bound here
+        assert_eq!(candidate.config().address, format!("0.0.0.0:{port}"));
+        assert!(candidate.connect().is_ok());
+
+        let mut config = HomeConfig::new("Home");
+        assert_eq!(
+            config.add_discovered("Found", std::slice::from_ref(&candidate)),
+            1
+        );
+        assert_eq!(config.add_discovered("Found", &[candidate]), 0);
+    }
+}
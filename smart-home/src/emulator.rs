@@ -0,0 +1,684 @@
+use crate::SmartHomeError;
+use crate::config::{
+    ConfigLine, ConfigLines, DeviceConfig, DeviceKind, HomeConfig, RoomConfig, read_config,
+    unknown_key,
+};
+use crate::devices::smartsocket::{Capabilities, SocketCommand, SocketResponse, SocketStatus};
+use crate::devices::termo::UdpLike;
+use crate::discovery::{Announcement, Responder};
+use crate::metadata::{DeviceInfo, RoomInfo};
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::Path,
+    str::FromStr,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+pub const EMULATED_MODEL: &str = "SH-EMU-1";
+
+#[derive(Debug)]
+pub struct EmulatedSocket {
+    is_on: bool,
+    power: f32,
+    id: String,
+}
+
+impl Default for EmulatedSocket {
+    fn default() -> Self {
+        Self::with_id("emulated")
+    }
+}
+
+impl EmulatedSocket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_id(id: impl Into<String>) -> Self {
+        Self {
+            is_on: false,
+            power: 0.0,
+            id: id.into(),
+        }
+    }
+    pub fn process_command(&mut self, command: SocketCommand) -> SocketResponse {
+        match command {
+            SocketCommand::Switch => {
+                self.is_on = !self.is_on;
+                self.power = if self.is_on { 1000. } else { 0.0 };
+                SocketResponse::On(self.is_on)
+            }
+            SocketCommand::GetPower => SocketResponse::Power(self.power),
+            SocketCommand::IsOn => SocketResponse::On(self.is_on),
+            SocketCommand::GetId => SocketResponse::Text(self.id.clone()),
+            SocketCommand::GetFirmware => {
+                SocketResponse::Text(env!("CARGO_PKG_VERSION").to_string())
+            }
+            SocketCommand::GetModel => SocketResponse::Text(EMULATED_MODEL.to_string()),
+            SocketCommand::GetCapabilities => {
+                SocketResponse::Capabilities(Capabilities::of(&SocketCommand::ALL))
+            }
+            SocketCommand::GetStatus => SocketResponse::Status(SocketStatus {
+                on: self.is_on,
+                power: self.power,
+            }),
+            SocketCommand::Unknown => SocketResponse::Unknown,
+        }
+    }
+}
+
+// In-process socket for tests and demos: commands written to the stream are
+// answered by an emulated socket. Clones share the same socket.
+#[derive(Debug, Clone, Default)]
+pub struct EmulatedStream(Arc<Mutex<(EmulatedSocket, Vec<u8>)>>);
+
+impl EmulatedStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Write for EmulatedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut guard = lock(&self.0);
+        let (socket, output) = &mut *guard;
+        for command in buf {
+            let response = socket.process_command((*command).into());
+            output.extend_from_slice(&response.to_bytes());
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for EmulatedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut guard = lock(&self.0);
+        let len = buf.len().min(guard.1.len());
+        buf[..len].copy_from_slice(&guard.1[..len]);
+        guard.1.drain(..len);
+        Ok(len)
+    }
+}
+
+// In-process source of thermometer datagrams always reporting the last set temperature.
+#[derive(Debug, Clone)]
+pub struct EmulatedSensor(Arc<Mutex<f32>>);
+
+impl EmulatedSensor {
+    pub fn new(temperature: f32) -> Self {
+        Self(Arc::new(Mutex::new(temperature)))
+    }
+    pub fn set(&self, temperature: f32) {
+        *lock(&self.0) = temperature;
+    }
+}
+
+impl UdpLike for EmulatedSensor {
+    fn send_to(&mut self, buf: &[u8], _target: SocketAddr) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        buf[..4].copy_from_slice(&lock(&self.0).to_be_bytes());
+        Ok((4, SocketAddr::from(([127, 0, 0, 1], 0))))
+    }
+}
+
+// Home with one room of emulated sockets, for tests of code switching sockets.
+#[cfg(test)]
+pub(crate) fn socket_home(room: &str, sockets: &[&str]) -> crate::Home {
+    let mut home = crate::Home::new("Test");
+    let mut devices = crate::Room::default();
+    for name in sockets {
+        devices.add_device(*name, crate::SmartSocket::new(EmulatedStream::new()).into());
+    }
+    home.add_room(room, devices);
+    home
+}
+
+#[cfg(test)]
+pub(crate) fn socket_is_on(home: &crate::Home, room: &str, device: &str) -> bool {
+    home.find_device(room, device)
+        .unwrap()
+        .as_socket()
+        .unwrap()
+        .is_on()
+        .unwrap()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(g) => g,
+        Err(poison_error) => poison_error.into_inner(),
+    }
+}
+
+// Fleet description is an ini-like file with the number of devices per room:
+//
+//   name = Fleet
+//   host = 127.0.0.1
+//   base_port = 5000
+//   period_ms = 1000
+//   [Kitchen]
+//   sockets = 2
+//   thermometers = 1
+//
+// With `base_port = 0` every device gets any free port. With `discovery_port = 4300` the
+// fleet answers discovery probes on that port, devices are announced as `<room>-<device>`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FleetRoom {
+    pub name: String,
+    pub sockets: usize,
+    pub thermometers: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FleetDescription {
+    pub name: String,
+    pub host: String,
+    pub base_port: u16,
+    pub period: Duration,
+    pub discovery_port: Option<u16>,
+    pub rooms: Vec<FleetRoom>,
+}
+
+impl Default for FleetDescription {
+    fn default() -> Self {
+        Self {
+            name: "Fleet".to_string(),
+            host: "127.0.0.1".to_string(),
+            base_port: 5000,
+            period: Duration::from_secs(1),
+            discovery_port: None,
+            rooms: Vec::new(),
+        }
+    }
+}
+
+impl FleetDescription {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SmartHomeError> {
+        read_config(path.as_ref())?.parse()
+    }
+}
+
+impl FromStr for FleetDescription {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fleet = FleetDescription::default();
+        for line in ConfigLines::new(s) {
+            match line? {
+                ConfigLine::Section(name) => fleet.rooms.push(FleetRoom {
+                    name: name.to_string(),
+                    sockets: 0,
+                    thermometers: 0,
+                }),
+                ConfigLine::Value(number, key, value) => {
+                    let invalid = || {
+                        SmartHomeError::ConfigError(format!(
+                            "Line {number}: invalid value '{value}' for '{key}'"
+                        ))
+                    };
+                    match (fleet.rooms.last_mut(), key) {
+                        (None, "name") => fleet.name = value.to_string(),
+                        (None, "host") => fleet.host = value.to_string(),
+                        (None, "base_port") => {
+                            fleet.base_port = value.parse().map_err(|_| invalid())?
+                        }
+                        (None, "discovery_port") => {
+                            fleet.discovery_port = Some(value.parse().map_err(|_| invalid())?)
+                        }
+                        (None, "period_ms") => {
+                            fleet.period = value
+                                .parse()
+                                .ok()
+                                .filter(|millis| *millis > 0)
+                                .map(Duration::from_millis)
+                                .ok_or_else(invalid)?
+                        }
+                        (Some(room), "sockets") => {
+                            room.sockets = value.parse().map_err(|_| invalid())?
+                        }
+                        (Some(room), "thermometers") => {
+                            room.thermometers = value.parse().map_err(|_| invalid())?
+                        }
+                        _ => return Err(unknown_key(number, key)),
+                    }
+                }
+            }
+        }
+        Ok(fleet)
+    }
+}
+
+struct FleetSocket {
+    listener: TcpListener,
+    state: EmulatedSocket,
+    clients: Vec<Client>,
+}
+
+// A connected client and the part of its last response it did not take yet.
+struct Client {
+    stream: TcpStream,
+    pending: Vec<u8>,
+}
+
+impl Client {
+    // Writes as much of the pending response as the client takes without blocking.
+    // Returns false if the client is gone.
+    fn flush(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+impl FleetSocket {
+    // Accepts new clients and answers every complete command from the connected ones.
+    // Returns true if something was done.
+    fn poll(&mut self) -> bool {
+        let mut busy = false;
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) if stream.set_nonblocking(true).is_ok() => {
+                    self.clients.push(Client {
+                        stream,
+                        pending: Vec::new(),
+                    });
+                    busy = true;
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        let state = &mut self.state;
+        self.clients.retain_mut(|client| {
+            if !client.flush() {
+                return false;
+            }
+            // Commands of a client which does not read its responses wait until it does.
+            let mut command = [0u8];
+            while client.pending.is_empty() {
+                match client.stream.read(&mut command) {
+                    Ok(0) => return false,
+                    Ok(_) => {
+                        busy = true;
+                        client.pending = state.process_command(command[0].into()).to_bytes();
+                        if !client.flush() {
+                            return false;
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => return true,
+                    Err(_) => return false,
+                }
+            }
+            true
+        });
+        busy
+    }
+}
+
+// Many emulated sockets and thermometers served by one thread.
+pub struct Fleet {
+    config: HomeConfig,
+    sockets: Vec<FleetSocket>,
+    thermometers: Vec<SocketAddr>,
+    sender: UdpSocket,
+    period: Duration,
+    responder: Option<Responder>,
+}
+
+impl Fleet {
+    pub fn new(description: &FleetDescription) -> Result<Fleet, SmartHomeError> {
+        let mut next_port = description.base_port;
+        let mut take_port = || {
+            let port = next_port;
+            if next_port != 0 {
+                next_port = next_port.checked_add(1).ok_or(SmartHomeError::ConfigError(
+                    "Not enough ports for the fleet".to_string(),
+                ))?;
+            }
+            Ok::<u16, SmartHomeError>(port)
+        };
+
+        let mut config = HomeConfig::new(&description.name);
+        let mut sockets = Vec::new();
+        let mut thermometers = Vec::new();
+        for room in &description.rooms {
+            let mut devices = Vec::new();
+            for number in 1..=room.sockets {
+                let listener = TcpListener::bind((description.host.as_str(), take_port()?))?;
+                listener.set_nonblocking(true)?;
+                devices.push(DeviceConfig {
+                    name: format!("Socket{number}"),
+                    kind: DeviceKind::Socket,
+                    address: listener.local_addr()?.to_string(),
+                    tags: Vec::new(),
+                    info: DeviceInfo::default(),
+                });
+                sockets.push(FleetSocket {
+                    listener,
+                    state: EmulatedSocket::with_id(format!("{}-Socket{number}", room.name)),
+                    clients: Vec::new(),
+                });
+            }
+            for number in 1..=room.thermometers {
+                // Thermometers listen on their own, the port is only probed to be free.
+                let address =
+                    UdpSocket::bind((description.host.as_str(), take_port()?))?.local_addr()?;
+                devices.push(DeviceConfig {
+                    name: format!("Thermometer{number}"),
+                    kind: DeviceKind::Thermometer,
+                    address: address.to_string(),
+                    tags: Vec::new(),
+                    info: DeviceInfo::default(),
+                });
+                thermometers.push(address);
+            }
+            config.rooms.push(RoomConfig {
+                name: room.name.clone(),
+                devices,
+                info: RoomInfo::default(),
+            });
+        }
+
+        let sender = UdpSocket::bind((description.host.as_str(), 0))?;
+        sender.set_nonblocking(true)?;
+        let responder = match description.discovery_port {
+            Some(port) => Some(Responder::bind(
+                (description.host.as_str(), port),
+                announcements(&config)?,
+            )?),
+            None => None,
+        };
+        Ok(Fleet {
+            config,
+            sockets,
+            thermometers,
+            sender,
+            period: description.period,
+            responder,
+        })
+    }
+
+    pub fn config(&self) -> &HomeConfig {
+        &self.config
+    }
+
+    pub fn discovery_address(&self) -> Option<SocketAddr> {
+        self.responder.as_ref()?.local_addr().ok()
+    }
+
+    pub fn start(self) -> FleetHandle {
+        let finished = Arc::new(AtomicBool::new(false));
+        let finished_clone = finished.clone();
+        let thread = thread::spawn(move || self.run(&finished_clone));
+        FleetHandle {
+            finished,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn run(mut self, finished: &AtomicBool) {
+        let mut next_send = Instant::now();
+        while !finished.load(Ordering::SeqCst) {
+            let mut busy = false;
+            for socket in &mut self.sockets {
+                busy |= socket.poll();
+            }
+            if let Some(responder) = &mut self.responder {
+                busy |= responder.poll().unwrap_or(false);
+            }
+            if Instant::now() >= next_send {
+                self.send_temperatures();
+                next_send += self.period;
+            }
+            if !busy {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    fn send_temperatures(&self) {
+        for address in &self.thermometers {
+            let temperature = rand::random_range(10.0..30.0) as f32;
+            // Thermometer may not be started yet, such datagrams are just lost.
+            let _ = self.sender.send_to(&temperature.to_be_bytes(), address);
+        }
+    }
+}
+
+fn announcements(config: &HomeConfig) -> Result<Vec<Announcement>, SmartHomeError> {
+    let mut announcements = Vec::new();
+    for room in &config.rooms {
+        for device in &room.devices {
+            let address: SocketAddr = device.address.parse().map_err(|_| {
+                SmartHomeError::ConfigError(format!("Invalid address {}", device.address))
+            })?;
+            announcements.push(Announcement::new(
+                device.kind,
+                format!("{}-{}", room.name, device.name),
+                address.port(),
+            ));
+        }
+    }
+    Ok(announcements)
+}
+
+pub struct FleetHandle {
+    finished: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FleetHandle {
+    pub fn join(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for FleetHandle {
+    fn drop(&mut self) {
+        self.finished.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::discover_at;
+    use crate::{Report, SmartDevice};
+
+    #[test]
+    fn test_emulated_socket() {
+        let mut socket = EmulatedSocket::new();
+        assert_eq!(
+            socket.process_command(SocketCommand::IsOn),
+            SocketResponse::On(false)
+        );
+        assert_eq!(
+            socket.process_command(SocketCommand::Switch),
+            SocketResponse::On(true)
+        );
+        assert_eq!(
+            socket.process_command(SocketCommand::GetPower),
+            SocketResponse::Power(1000.)
+        );
+        assert_eq!(
+            socket.process_command(SocketCommand::Unknown),
+            SocketResponse::Unknown
+        );
+        assert_eq!(
+            socket.process_command(SocketCommand::GetId),
+            SocketResponse::Text("emulated".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_description() {
+        let fleet: FleetDescription = "
+name = Big Home
+base_port = 0
+period_ms = 100
+[Kitchen]
+sockets = 2
+thermometers = 1
+[Hall]
+sockets = 1
+"
+        .parse()
+        .unwrap();
+        assert_eq!(fleet.name, "Big Home");
+        assert_eq!(fleet.host, "127.0.0.1");
+        assert_eq!(fleet.base_port, 0);
+        assert_eq!(fleet.period, Duration::from_millis(100));
+        assert_eq!(fleet.rooms.len(), 2);
+        assert_eq!(fleet.rooms[0].sockets, 2);
+        assert_eq!(fleet.rooms[0].thermometers, 1);
+        assert_eq!(fleet.rooms[1].thermometers, 0);
+
+        assert!(
+            "[Kitchen]\nlamps = 1\n"
+                .parse::<FleetDescription>()
+                .is_err()
+        );
+        assert!("base_port = many\n".parse::<FleetDescription>().is_err());
+        assert!("period_ms = 0\n".parse::<FleetDescription>().is_err());
+        let fleet: FleetDescription = "discovery_port = 4300\n".parse().unwrap();
+        assert_eq!(fleet.discovery_port, Some(4300));
+    }
+
+    #[test]
+    fn test_fleet_home() {
+        let description = FleetDescription {
+            base_port: 0,
+            period: Duration::from_millis(50),
+            rooms: vec![
+                FleetRoom {
+                    name: "Room1".to_string(),
+                    sockets: 3,
+                    thermometers: 1,
+                },
+                FleetRoom {
+                    name: "Room2".to_string(),
+                    sockets: 1,
+                    thermometers: 0,
+                },
+            ],
+            ..Default::default()
+        };
+        let fleet = Fleet::new(&description).unwrap();
+        let config = fleet.config().clone();
+        assert_eq!(config.device_count(), 5);
+        let _handle = fleet.start();
+
+        let mut home = config.connect().unwrap();
+        if let SmartDevice::SmartSocket(socket) = home.get_device("Room1", "Socket2").unwrap() {
+            socket.switch().unwrap();
+            assert!(socket.is_on().unwrap());
+            assert_eq!(socket.get_power().unwrap(), 1000.);
+        } else {
+            panic!("Socket2 is not a socket");
+        }
+        if let SmartDevice::SmartSocket(socket) = home.get_device("Room1", "Socket1").unwrap() {
+            assert!(!socket.is_on().unwrap());
+        }
+        assert!(home.report().contains("Room: Room2"));
+    }
+
+    #[test]
+    fn test_fleet_client_not_reading() {
+        let fleet = Fleet::new(&FleetDescription {
+            base_port: 0,
+            rooms: vec![FleetRoom {
+                name: "Room".to_string(),
+                sockets: 1,
+                thermometers: 0,
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let address = fleet.config().rooms[0].devices[0].address.clone();
+        let home = fleet.config().connect().unwrap();
+        let handle = fleet.start();
+
+        // Far more responses than the socket buffers hold, none of them read
+        let mut stream = TcpStream::connect(&address).unwrap();
+        stream
+            .set_write_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let writer = thread::spawn(move || {
+            let commands = vec![u8::from(SocketCommand::GetModel); 1 << 20];
+            let _ = stream.write_all(&commands);
+            stream
+        });
+        thread::sleep(Duration::from_millis(200));
+
+        let socket = home
+            .find_device("Room", "Socket1")
+            .unwrap()
+            .as_socket()
+            .unwrap();
+        socket.set_timeout(Some(Duration::from_secs(1))).unwrap();
+        assert!(!socket.is_on().unwrap());
+        let started = Instant::now();
+        drop(handle);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_fleet_discovery() {
+        let description = FleetDescription {
+            base_port: 0,
+            discovery_port: Some(0),
+            rooms: vec![FleetRoom {
+                name: "Hall".to_string(),
+                sockets: 1,
+                thermometers: 1,
+            }],
+            ..Default::default()
+        };
+        let fleet = Fleet::new(&description).unwrap();
+        let address = fleet.discovery_address().unwrap();
+        let config = fleet.config().clone();
+        let _handle = fleet.start();
+
+        let candidates = discover_at(address, Duration::from_millis(200)).unwrap();
+        let found: Vec<String> = candidates.iter().map(ToString::to_string).collect();
+        let devices = &config.rooms[0].devices;
+        assert_eq!(
+            found,
+            [
+                format!("socket Hall-Socket1 {}", devices[0].address),
+                format!("thermometer Hall-Thermometer1 {}", devices[1].address),
+            ]
+        );
+        let device = candidates[0].connect().unwrap();
+        // The socket knows its own id, so the address can be checked
+        device
+            .as_socket()
+            .unwrap()
+            .verify_id(&candidates[0].id)
+            .unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+use std::{error::Error, fmt, io};
+
+#[derive(Debug)]
+pub enum SmartHomeError {
+    DeviceNotFound(String),
+    RoomNotFound(String),
+    ConnectionError(io::Error),
+    ConfigError(String),
+    WrongDeviceType(String),
+    AlreadyExists(String),
+    // Device answered with something the protocol does not allow.
+    ProtocolError(String),
+    // Device did not answer in time.
+    Timeout(io::Error),
+    // Value is not acceptable, like an empty name or a malformed device path.
+    ValidationError(String),
+    // Rule or schedule could not be run.
+    AutomationError {
+        rule: String,
+        source: Box<SmartHomeError>,
+    },
+    // Database could not be read or written.
+    StorageError(String),
+    // Where the error happened, see `SmartHomeError::context`.
+    WithContext {
+        context: ErrorContext,
+        source: Box<SmartHomeError>,
+    },
+}
+
+// Room, device, address and command an error relates to, whichever are known.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ErrorContext {
+    pub room: Option<String>,
+    pub device: Option<String>,
+    pub address: Option<String>,
+    pub command: Option<String>,
+}
+
+// Stable error codes for the CLI exit codes, HTTP statuses and JSON error bodies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    DeviceNotFound,
+    RoomNotFound,
+    Connection,
+    Config,
+    WrongDeviceType,
+    AlreadyExists,
+    Protocol,
+    Timeout,
+    Validation,
+    Automation,
+    Storage,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::DeviceNotFound => "device_not_found",
+            ErrorCode::RoomNotFound => "room_not_found",
+            ErrorCode::Connection => "connection",
+            ErrorCode::Config => "config",
+            ErrorCode::WrongDeviceType => "wrong_device_type",
+            ErrorCode::AlreadyExists => "already_exists",
+            ErrorCode::Protocol => "protocol",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::Validation => "validation",
+            ErrorCode::Automation => "automation",
+            ErrorCode::Storage => "storage",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl SmartHomeError {
+    // Code of the error itself, context does not change it.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SmartHomeError::DeviceNotFound(_) => ErrorCode::DeviceNotFound,
+            SmartHomeError::RoomNotFound(_) => ErrorCode::RoomNotFound,
+            SmartHomeError::ConnectionError(_) => ErrorCode::Connection,
+            SmartHomeError::ConfigError(_) => ErrorCode::Config,
+            SmartHomeError::WrongDeviceType(_) => ErrorCode::WrongDeviceType,
+            SmartHomeError::AlreadyExists(_) => ErrorCode::AlreadyExists,
+            SmartHomeError::ProtocolError(_) => ErrorCode::Protocol,
+            SmartHomeError::Timeout(_) => ErrorCode::Timeout,
+            SmartHomeError::ValidationError(_) => ErrorCode::Validation,
+            SmartHomeError::AutomationError { .. } => ErrorCode::Automation,
+            SmartHomeError::StorageError(_) => ErrorCode::Storage,
+            SmartHomeError::WithContext { source, .. } => source.code(),
+        }
+    }
+
+    // The error without its context and automation wrappers.
+    pub fn root(&self) -> &SmartHomeError {
+        match self {
+            SmartHomeError::AutomationError { source, .. }
+            | SmartHomeError::WithContext { source, .. } => source.root(),
+            err => err,
+        }
+    }
+
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            SmartHomeError::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    // Fills the context, fields already known from deeper down are kept.
+    pub fn with_context(self, update: impl FnOnce(&mut ErrorContext)) -> SmartHomeError {
+        let mut added = ErrorContext::default();
+        update(&mut added);
+        match self {
+            SmartHomeError::WithContext {
+                mut context,
+                source,
+            } => {
+                context.room = context.room.or(added.room);
+                context.device = context.device.or(added.device);
+                context.address = context.address.or(added.address);
+                context.command = context.command.or(added.command);
+                SmartHomeError::WithContext { context, source }
+            }
+            err => SmartHomeError::WithContext {
+                context: added,
+                source: Box::new(err),
+            },
+        }
+    }
+
+    pub fn in_device(self, room: &str, device: &str) -> SmartHomeError {
+        self.with_context(|context| {
+            context.room = Some(room.to_string());
+            context.device = Some(device.to_string());
+        })
+    }
+
+    pub fn in_rule(self, rule: &str) -> SmartHomeError {
+        SmartHomeError::AutomationError {
+            rule: rule.to_string(),
+            source: Box::new(self),
+        }
+    }
+}
+
+impl fmt::Display for SmartHomeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmartHomeError::DeviceNotFound(name) => write!(f, "Device {} not found", name),
+            SmartHomeError::RoomNotFound(name) => write!(f, "Room {} not found", name),
+            SmartHomeError::ConnectionError(err) => write!(f, "Connection error: {err}"),
+            SmartHomeError::ConfigError(msg) => write!(f, "Config error: {msg}"),
+            SmartHomeError::WrongDeviceType(kind) => {
+                write!(f, "Command is not supported by {kind}")
+            }
+            SmartHomeError::AlreadyExists(name) => write!(f, "{} already exists", name),
+            SmartHomeError::ProtocolError(msg) => write!(f, "Protocol error: {msg}"),
+            SmartHomeError::Timeout(err) => write!(f, "Timeout: {err}"),
+            SmartHomeError::ValidationError(msg) => write!(f, "Invalid value: {msg}"),
+            SmartHomeError::AutomationError { rule, source } => {
+                write!(f, "Rule '{rule}' failed: {source}")
+            }
+            SmartHomeError::StorageError(msg) => write!(f, "Storage error: {msg}"),
+            SmartHomeError::WithContext { context, source } => write!(f, "{source} ({context})"),
+        }
+    }
+}
+
+// Like `room Kitchen, device Kettle, command IsOn`.
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [
+            ("room", &self.room),
+            ("device", &self.device),
+            ("address", &self.address),
+            ("command", &self.command),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!("{name} {}", value.as_ref()?)))
+        .collect();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+impl Error for SmartHomeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SmartHomeError::ConnectionError(err) | SmartHomeError::Timeout(err) => Some(err),
+            SmartHomeError::AutomationError { source, .. }
+            | SmartHomeError::WithContext { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SmartHomeError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => SmartHomeError::Timeout(err),
+            _ => SmartHomeError::ConnectionError(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let err = SmartHomeError::from(io::Error::from(io::ErrorKind::BrokenPipe))
+            .with_context(|context| context.command = Some("IsOn".to_string()))
+            .in_device("Kitchen", "Kettle");
+        assert_eq!(err.code(), ErrorCode::Connection);
+        assert!(matches!(err.root(), SmartHomeError::ConnectionError(_)));
+        assert_eq!(err.context().unwrap().command.as_deref(), Some("IsOn"));
+        assert!(
+            err.to_string()
+                .ends_with("(room Kitchen, device Kettle, command IsOn)")
+        );
+
+        // Chain goes through the context down to the io error
+        let source = err.source().unwrap();
+        assert!(source.to_string().starts_with("Connection error"));
+        assert!(source.source().unwrap().is::<io::Error>());
+    }
+
+    #[test]
+    fn test_codes() {
+        let timeout = SmartHomeError::from(io::Error::from(io::ErrorKind::TimedOut));
+        assert_eq!(timeout.code(), ErrorCode::Timeout);
+        let failed = timeout.in_rule("Heating");
+        assert_eq!(failed.code().as_str(), "automation");
+        assert!(matches!(failed.root(), SmartHomeError::Timeout(_)));
+        assert_eq!(
+            SmartHomeError::RoomNotFound("Attic".to_string()).code(),
+            ErrorCode::RoomNotFound
+        );
+    }
+}
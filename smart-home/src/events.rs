@@ -0,0 +1,187 @@
+use std::sync::{
+    Arc, Mutex, MutexGuard,
+    atomic::{AtomicU64, Ordering},
+    mpsc::{self, Receiver},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceEvent {
+    TemperatureUpdated(f32),
+    SocketSwitched(bool),
+    PowerChanged(f32),
+    DeviceOffline,
+    DeviceOnline,
+    // Device appeared under or disappeared from this name by a rename or a move.
+    Added,
+    Removed,
+}
+
+impl DeviceEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceEvent::TemperatureUpdated(_) => "temperature",
+            DeviceEvent::SocketSwitched(_) => "switched",
+            DeviceEvent::PowerChanged(_) => "power",
+            DeviceEvent::DeviceOffline => "offline",
+            DeviceEvent::DeviceOnline => "online",
+            DeviceEvent::Added => "added",
+            DeviceEvent::Removed => "removed",
+        }
+    }
+}
+
+// Event of a device inside a room.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomEvent {
+    pub device: String,
+    pub event: DeviceEvent,
+}
+
+// Event of a device inside a home.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HomeEvent {
+    pub room: String,
+    pub device: String,
+    pub event: DeviceEvent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+// Listener returns false when it is not interested in events anymore and can be dropped.
+type Listener<E> = Arc<dyn Fn(&E) -> bool + Send + Sync>;
+
+pub struct Notifier<E> {
+    listeners: Mutex<Vec<(SubscriptionId, Listener<E>)>>,
+    next_id: AtomicU64,
+}
+
+impl<E> Default for Notifier<E> {
+    fn default() -> Self {
+        Self {
+            listeners: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<E> Notifier<E> {
+    fn listeners(&self) -> MutexGuard<'_, Vec<(SubscriptionId, Listener<E>)>> {
+        match self.listeners.lock() {
+            Ok(g) => g,
+            Err(poison_error) => poison_error.into_inner(),
+        }
+    }
+
+    pub(crate) fn listen(
+        &self,
+        listener: impl Fn(&E) -> bool + Send + Sync + 'static,
+    ) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.listeners().push((id, Arc::new(listener)));
+        id
+    }
+
+    pub(crate) fn unlisten(&self, id: SubscriptionId) -> bool {
+        let mut listeners = self.listeners();
+        let count = listeners.len();
+        listeners.retain(|(listener_id, _)| *listener_id != id);
+        listeners.len() != count
+    }
+
+    // Listeners run without the lock held, so they may subscribe, unsubscribe or emit themselves.
+    pub(crate) fn emit(&self, event: &E) {
+        let listeners = self.listeners().clone();
+        let dead: Vec<SubscriptionId> = listeners
+            .into_iter()
+            .filter(|(_, listener)| !listener(event))
+            .map(|(id, _)| id)
+            .collect();
+        if !dead.is_empty() {
+            self.listeners().retain(|(id, _)| !dead.contains(id));
+        }
+    }
+}
+
+impl<E> std::fmt::Debug for Notifier<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Notifier({} listeners)", self.listeners().len())
+    }
+}
+
+pub trait Observable {
+    type Event: Clone + Send + 'static;
+
+    fn notifier(&self) -> &Notifier<Self::Event>;
+
+    fn subscribe(&self, callback: impl Fn(&Self::Event) + Send + Sync + 'static) -> SubscriptionId
+    where
+        Self: Sized,
+    {
+        self.notifier().listen(move |event| {
+            callback(event);
+            true
+        })
+    }
+
+    // Channel subscription is dropped automatically after the receiver is dropped.
+    fn subscribe_channel(&self) -> Receiver<Self::Event> {
+        let (tx, rx) = mpsc::channel();
+        self.notifier()
+            .listen(move |event: &Self::Event| tx.send(event.clone()).is_ok());
+        rx
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.notifier().unlisten(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifier() {
+        let notifier = Notifier::default();
+        let (tx, rx) = mpsc::channel();
+        notifier.listen(move |event: &DeviceEvent| tx.send(*event).is_ok());
+        notifier.emit(&DeviceEvent::SocketSwitched(true));
+        notifier.emit(&DeviceEvent::PowerChanged(10.));
+        assert_eq!(rx.recv().unwrap(), DeviceEvent::SocketSwitched(true));
+        assert_eq!(rx.recv().unwrap(), DeviceEvent::PowerChanged(10.));
+
+        drop(rx);
+        notifier.emit(&DeviceEvent::TemperatureUpdated(20.));
+        assert!(notifier.listeners().is_empty());
+    }
+
+    #[test]
+    fn test_unlisten() {
+        let notifier = Notifier::<DeviceEvent>::default();
+        let first = notifier.listen(|_| true);
+        let second = notifier.listen(|_| true);
+        assert_ne!(first, second);
+        assert!(notifier.unlisten(first));
+        assert!(!notifier.unlisten(first));
+        assert_eq!(notifier.listeners().len(), 1);
+    }
+
+    #[test]
+    fn test_reentrant_listener() {
+        let notifier = Arc::new(Notifier::<DeviceEvent>::default());
+        let (tx, rx) = mpsc::channel();
+        let inner = notifier.clone();
+        notifier.listen(move |event| {
+            if *event == DeviceEvent::DeviceOffline {
+                let tx = tx.clone();
+                inner.listen(move |event| tx.send(*event).is_ok());
+                inner.emit(&DeviceEvent::DeviceOnline);
+            }
+            false
+        });
+        notifier.emit(&DeviceEvent::DeviceOffline);
+        assert_eq!(rx.recv().unwrap(), DeviceEvent::DeviceOnline);
+        assert_eq!(notifier.listeners().len(), 1);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::automation::{Command, DeviceRef};
+use crate::{Home, Report, SmartDevice, SmartHomeError};
+use std::collections::BTreeSet;
+
+// Devices of a home sharing a tag, whatever room they are in.
+#[derive(Debug)]
+pub struct Group<'a> {
+    pub tag: String,
+    pub members: Vec<(DeviceRef, &'a SmartDevice)>,
+}
+
+#[derive(Debug, Default)]
+pub struct GroupPower {
+    pub total: f32,
+    pub sockets: usize,
+    // Sockets which did not answer are not counted in the total.
+    pub errors: Vec<(DeviceRef, SmartHomeError)>,
+}
+
+impl Group<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    // Runs the command on every socket of the group, thermometers are skipped.
+    pub fn execute(&self, command: Command) -> Vec<(DeviceRef, Result<(), SmartHomeError>)> {
+        self.members
+            .iter()
+            .filter_map(|(device, member)| match member {
+                SmartDevice::SmartSocket(socket) => {
+                    let result = command
+                        .run(socket)
+                        .map_err(|err| err.in_device(&device.room, &device.device));
+                    Some((device.clone(), result))
+                }
+                SmartDevice::SmartThermometer(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn power(&self) -> GroupPower {
+        let mut power = GroupPower::default();
+        for (device, member) in &self.members {
+            if let SmartDevice::SmartSocket(socket) = member {
+                match socket.get_power() {
+                    Ok(value) => {
+                        power.total += value;
+                        power.sockets += 1;
+                    }
+                    Err(err) => power.errors.push((device.clone(), err)),
+                }
+            }
+        }
+        power
+    }
+}
+
+impl Report for Group<'_> {
+    fn report(&self) -> String {
+        let mut report = format!("Group: {}\n", self.tag);
+        for (device, member) in &self.members {
+            report.push_str(&format!(
+                "- {:20}: {}\n",
+                device.to_string(),
+                member.report()
+            ));
+        }
+        let power = self.power();
+        report.push_str(&format!(
+            "Total power: {:.2} ({} sockets",
+            power.total, power.sockets
+        ));
+        if !power.errors.is_empty() {
+            report.push_str(&format!(", {} unreachable", power.errors.len()));
+        }
+        report.push_str(")\n");
+        report
+    }
+}
+
+impl Home {
+    pub fn tag_device(
+        &mut self,
+        room_name: &str,
+        device_name: &str,
+        tag: impl Into<String>,
+    ) -> Result<(), SmartHomeError> {
+        self.get_room_mut(room_name)
+            .ok_or(SmartHomeError::RoomNotFound(room_name.to_string()))?
+            .tag_device(device_name, tag)
+    }
+
+    pub fn untag_device(
+        &mut self,
+        room_name: &str,
+        device_name: &str,
+        tag: &str,
+    ) -> Result<bool, SmartHomeError> {
+        Ok(self
+            .get_room_mut(room_name)
+            .ok_or(SmartHomeError::RoomNotFound(room_name.to_string()))?
+            .untag_device(device_name, tag))
+    }
+
+    // All tags used in the home.
+    pub fn tags(&self) -> BTreeSet<&str> {
+        self.into_iter()
+            .flat_map(|(_, room)| {
+                room.into_iter()
+                    .flat_map(|(name, _)| room.device_tags(name).map(String::as_str))
+            })
+            .collect()
+    }
+
+    pub fn group(&self, tag: &str) -> Group<'_> {
+        let mut members: Vec<(DeviceRef, &SmartDevice)> = self
+            .devices()
+            .filter(|(room, name, _)| {
+                self.get_room(room)
+                    .is_some_and(|room| room.has_tag(name, tag))
+            })
+            .map(|(room, name, device)| (DeviceRef::new(room, name), device))
+            .collect();
+        members.sort_by(|(a, _), (b, _)| (&a.room, &a.device).cmp(&(&b.room, &b.device)));
+        Group {
+            tag: tag.to_string(),
+            members,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatedSensor, EmulatedStream, socket_is_on};
+    use crate::{Room, SmartSocket, SmartThermometer};
+    use std::io::Cursor;
+
+    fn home() -> Home {
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        room.add_device("Heater", SmartSocket::new(EmulatedStream::new()).into());
+        room.add_device("Lamp", SmartSocket::new(EmulatedStream::new()).into());
+        home.add_room("Bathroom", room);
+        let mut room = Room::default();
+        room.add_device("Heater", SmartSocket::new(EmulatedStream::new()).into());
+        room.add_device(
+            "Thermometer",
+            SmartThermometer::new(EmulatedSensor::new(18.)).into(),
+        );
+        home.add_room("Garage", room);
+        home.tag_device("Bathroom", "Heater", "heaters").unwrap();
+        home.tag_device("Garage", "Heater", "heaters").unwrap();
+        home.tag_device("Garage", "Thermometer", "heaters").unwrap();
+        home.tag_device("Garage", "Thermometer", "outdoor").unwrap();
+        home
+    }
+
+    #[test]
+    fn test_group() {
+        let home = home();
+        assert_eq!(home.tags(), BTreeSet::from(["heaters", "outdoor"]));
+        let group = home.group("heaters");
+        let members: Vec<String> = group.members.iter().map(|(d, _)| d.to_string()).collect();
+        assert_eq!(
+            members,
+            ["Bathroom/Heater", "Garage/Heater", "Garage/Thermometer"]
+        );
+        assert!(home.group("critical").is_empty());
+
+        let results = group.execute(Command::On);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        let power = group.power();
+        assert_eq!((power.total, power.sockets), (2000., 2));
+        assert!(group.report().contains("Total power: 2000.00 (2 sockets)"));
+
+        assert!(!socket_is_on(&home, "Bathroom", "Lamp"));
+    }
+
+    #[test]
+    fn test_group_with_wildcard_names() {
+        let mut home = home();
+        let room = home.get_room_mut("Bathroom").unwrap();
+        room.add_device("Lamp*", SmartSocket::new(EmulatedStream::new()).into());
+        home.tag_device("Bathroom", "Lamp*", "lamps").unwrap();
+
+        let results = home.group("lamps").execute(Command::On);
+        assert_eq!(results.len(), 1);
+        assert!(socket_is_on(&home, "Bathroom", "Lamp*"));
+        assert!(!socket_is_on(&home, "Bathroom", "Lamp"));
+    }
+
+    #[test]
+    fn test_group_errors() {
+        let mut home = home();
+        home.get_room_mut("Bathroom")
+            .unwrap()
+            .add_device("Broken", SmartSocket::new(Cursor::new(Vec::new())).into());
+        home.tag_device("Bathroom", "Broken", "heaters").unwrap();
+        assert!(matches!(
+            home.tag_device("Attic", "Heater", "heaters"),
+            Err(SmartHomeError::RoomNotFound(_))
+        ));
+        assert!(
+            home.untag_device("Garage", "Thermometer", "outdoor")
+                .unwrap()
+        );
+        assert!(!home.tags().contains("outdoor"));
+
+        let group = home.group("heaters");
+        let results = group.execute(Command::Switch);
+        assert_eq!(results.iter().filter(|(_, r)| r.is_err()).count(), 1);
+        let power = group.power();
+        assert_eq!(power.sockets, 2);
+        assert_eq!(power.errors[0].0, DeviceRef::new("Bathroom", "Broken"));
+        assert!(group.report().contains("1 unreachable"));
+    }
+}
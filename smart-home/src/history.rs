@@ -0,0 +1,562 @@
+use crate::clock::{Clock, Date, DateTime, SystemClock, TimeOfDay, UtcOffset, from_unix_seconds};
+use crate::json::decimal;
+use crate::polling::{Polled, Snapshot};
+use crate::{DeviceState, SmartHomeError};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Readings are appended to CSV files in a directory, one file per UTC day:
+//
+//   history/2025-06-02.csv
+//   1748851200000,Kitchen,Kettle,power,1000
+//
+// Every line is the time in unix milliseconds, room, device, metric and value. Names with
+// commas or quotes are quoted. Lines which can not be read, like the last one after a
+// crash, are skipped; a recorder reopening such a file starts a new line first. Files older
+// than the retention are deleted when a new day starts.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    Temperature,
+    Power,
+    // 1 for on, 0 for off.
+    On,
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Metric::Temperature => "temperature",
+            Metric::Power => "power",
+            Metric::On => "on",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Metric {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "temperature" => Ok(Metric::Temperature),
+            "power" => Ok(Metric::Power),
+            "on" => Ok(Metric::On),
+            _ => Err(SmartHomeError::ValidationError(format!(
+                "unknown metric '{s}', expected temperature, power or on"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub time: SystemTime,
+    pub room: String,
+    pub device: String,
+    pub metric: Metric,
+    pub value: f64,
+}
+
+impl Sample {
+    pub fn new(
+        time: SystemTime,
+        room: impl Into<String>,
+        device: impl Into<String>,
+        metric: Metric,
+        value: f64,
+    ) -> Self {
+        Sample {
+            time,
+            room: room.into(),
+            device: device.into(),
+            metric,
+            value,
+        }
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{}",
+            unix_millis(self.time),
+            quote(&self.room),
+            quote(&self.device),
+            self.metric,
+            self.value
+        )
+    }
+}
+
+impl FromStr for Sample {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SmartHomeError::ValidationError(format!("invalid sample '{s}'"));
+        let fields = split(s).ok_or_else(invalid)?;
+        let [time, room, device, metric, value] = &fields[..] else {
+            return Err(invalid());
+        };
+        let millis: i64 = time.parse().map_err(|_| invalid())?;
+        Ok(Sample {
+            time: from_unix_millis(millis),
+            room: room.clone(),
+            device: device.clone(),
+            metric: metric.parse()?,
+            value: value.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+// Samples of one bucket of a downsampled range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    pub start: SystemTime,
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+// Samples of the polled devices, cached states are dated back by their age. Failed and
+// timed out devices have no samples.
+pub fn samples(snapshot: &Snapshot, now: SystemTime) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    for (room, device, polled) in snapshot.devices() {
+        let (state, time) = match polled {
+            Polled::State(state) => (state, now),
+            Polled::Cached(state, age) => (state, now - *age),
+            Polled::Failed(_) | Polled::TimedOut => continue,
+        };
+        let mut sample =
+            |metric, value| samples.push(Sample::new(time, room, device, metric, value));
+        match *state {
+            DeviceState::Socket { on, power } => {
+                sample(Metric::On, if on { 1. } else { 0. });
+                sample(Metric::Power, decimal(power));
+            }
+            DeviceState::Thermometer { temperature } => {
+                sample(Metric::Temperature, decimal(temperature))
+            }
+        }
+    }
+    samples
+}
+
+// Appends samples to the day file, starting a new file every UTC day.
+#[derive(Debug)]
+pub struct Recorder<C: Clock = SystemClock> {
+    dir: PathBuf,
+    retention: Option<Duration>,
+    clock: C,
+    file: Option<(Date, File)>,
+}
+
+impl Recorder<SystemClock> {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, SmartHomeError> {
+        Self::with_clock(dir, SystemClock)
+    }
+}
+
+impl<C: Clock> Recorder<C> {
+    pub fn with_clock(dir: impl AsRef<Path>, clock: C) -> Result<Self, SmartHomeError> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Recorder {
+            dir: dir.as_ref().to_path_buf(),
+            retention: None,
+            clock,
+            file: None,
+        })
+    }
+
+    // Day files older than the retention are deleted, by default they are kept.
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    pub fn record(&mut self, samples: &[Sample]) -> Result<(), SmartHomeError> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let text: String = samples.iter().map(|sample| format!("{sample}\n")).collect();
+        self.file()?.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    // Records the snapshot and returns the number of samples.
+    pub fn record_snapshot(&mut self, snapshot: &Snapshot) -> Result<usize, SmartHomeError> {
+        let samples = samples(snapshot, self.clock.now());
+        self.record(&samples)?;
+        Ok(samples.len())
+    }
+
+    fn file(&mut self) -> Result<&mut File, SmartHomeError> {
+        let today = date(self.clock.now());
+        if self.file.as_ref().is_none_or(|(day, _)| *day != today) {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(day_file(&self.dir, today))?;
+            if file.metadata()?.len() > 0 {
+                let mut last = [0u8];
+                file.seek(SeekFrom::End(-1))?;
+                file.read_exact(&mut last)?;
+                if last[0] != b'\n' {
+                    file.write_all(b"\n")?;
+                }
+            }
+            self.file = Some((today, file));
+            self.remove_expired()?;
+        }
+        match &mut self.file {
+            Some((_, file)) => Ok(file),
+            None => unreachable!("Day file is opened above"),
+        }
+    }
+
+    fn remove_expired(&self) -> Result<(), SmartHomeError> {
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        let now = self.clock.now();
+        for (day, path) in day_files(&self.dir)? {
+            // The last sample of a day is at most a day younger than its start
+            let expires = (start_of_day(day) + Duration::from_secs(86400)).checked_add(retention);
+            if expires.is_some_and(|expires| expires < now) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads what a recorder wrote.
+#[derive(Debug, Clone)]
+pub struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    pub fn open(dir: impl AsRef<Path>) -> Self {
+        History {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // Samples of the device in [from, to), oldest first.
+    pub fn range(
+        &self,
+        room: &str,
+        device: &str,
+        metric: Metric,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<Vec<Sample>, SmartHomeError> {
+        let (first, last) = (date(from), date(to));
+        let mut samples = Vec::new();
+        for (day, path) in day_files(&self.dir)? {
+            if day < first || day > last {
+                continue;
+            }
+            for line in BufReader::new(File::open(path)?).lines() {
+                let Ok(sample) = line?.parse::<Sample>() else {
+                    continue;
+                };
+                if sample.room == room
+                    && sample.device == device
+                    && sample.metric == metric
+                    && from <= sample.time
+                    && sample.time < to
+                {
+                    samples.push(sample);
+                }
+            }
+        }
+        samples.sort_by_key(|sample| sample.time);
+        Ok(samples)
+    }
+
+    // Range downsampled to buckets of the given length aligned to the unix epoch, buckets
+    // without samples are left out.
+    pub fn aggregate(
+        &self,
+        room: &str,
+        device: &str,
+        metric: Metric,
+        from: SystemTime,
+        to: SystemTime,
+        bucket: Duration,
+    ) -> Result<Vec<Aggregate>, SmartHomeError> {
+        let bucket_millis = bucket.as_millis().max(1) as i64;
+        let mut aggregates: Vec<Aggregate> = Vec::new();
+        for sample in self.range(room, device, metric, from, to)? {
+            let start = from_unix_millis(
+                unix_millis(sample.time).div_euclid(bucket_millis) * bucket_millis,
+            );
+            match aggregates.last_mut() {
+                Some(last) if last.start == start => {
+                    last.mean += (sample.value - last.mean) / (last.count + 1) as f64;
+                    last.count += 1;
+                    last.min = last.min.min(sample.value);
+                    last.max = last.max.max(sample.value);
+                }
+                _ => aggregates.push(Aggregate {
+                    start,
+                    count: 1,
+                    min: sample.value,
+                    max: sample.value,
+                    mean: sample.value,
+                }),
+            }
+        }
+        Ok(aggregates)
+    }
+}
+
+fn day_file(dir: &Path, day: Date) -> PathBuf {
+    dir.join(format!("{day}.csv"))
+}
+
+// Day files of the directory, other files are ignored.
+fn day_files(dir: &Path) -> Result<Vec<(Date, PathBuf)>, SmartHomeError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let day = path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_suffix(".csv")?.parse::<Date>().ok());
+        if let Some(day) = day {
+            files.push((day, path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn date(time: SystemTime) -> Date {
+    let time = DateTime::from_system_time(time, UtcOffset::default());
+    Date::new(time.year, time.month, time.day)
+}
+
+fn start_of_day(day: Date) -> SystemTime {
+    DateTime::to_system_time(
+        day.year,
+        day.month,
+        day.day,
+        TimeOfDay::new(0, 0),
+        UtcOffset::default(),
+    )
+}
+
+pub(crate) fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis() as i64,
+        Err(err) => -(err.duration().as_millis() as i64),
+    }
+}
+
+pub(crate) fn from_unix_millis(millis: i64) -> SystemTime {
+    from_unix_seconds(millis.div_euclid(1000))
+        + Duration::from_millis(millis.rem_euclid(1000) as u64)
+}
+
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Splits a CSV line, None if a quote is not closed.
+fn split(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::emulator::EmulatedStream;
+    use crate::{Home, Room, SmartSocket};
+
+    fn at(day: u8, hour: u8, minute: u8) -> SystemTime {
+        DateTime::to_system_time(
+            2025,
+            6,
+            day,
+            TimeOfDay::new(hour, minute),
+            UtcOffset::default(),
+        )
+    }
+
+    #[test]
+    fn test_sample_line() {
+        let sample = Sample::new(at(2, 7, 0), "Living, \"big\"", "Lamp", Metric::Power, 21.5);
+        let line = sample.to_string();
+        assert_eq!(line, r#"1748847600000,"Living, ""big""",Lamp,power,21.5"#);
+        assert_eq!(line.parse::<Sample>().unwrap(), sample);
+        assert!(
+            "1748847600000,\"Living,Lamp,power,1"
+                .parse::<Sample>()
+                .is_err()
+        );
+        assert!(
+            "1748847600000,Living,Lamp,volume,1"
+                .parse::<Sample>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_record_and_query() {
+        let dir = std::env::temp_dir().join(format!("history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let clock = ManualClock::new(at(1, 12, 0));
+        let mut recorder = Recorder::with_clock(&dir, clock.clone())
+            .unwrap()
+            .with_retention(Duration::from_secs(86400));
+        recorder
+            .record(&[Sample::new(at(1, 12, 0), "Room", "Lamp", Metric::Power, 5.)])
+            .unwrap();
+
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        room.add_device("Lamp", SmartSocket::new(EmulatedStream::new()).into());
+        home.add_room("Room", room);
+        clock.set(at(2, 7, 0));
+        for power in [10., 20., 60.] {
+            recorder
+                .record(&[Sample::new(
+                    clock.now(),
+                    "Room",
+                    "Lamp",
+                    Metric::Power,
+                    power,
+                )])
+                .unwrap();
+            clock.advance(Duration::from_secs(20 * 60));
+        }
+        let snapshot = home.poll(Duration::from_secs(1));
+        assert_eq!(recorder.record_snapshot(&snapshot).unwrap(), 2);
+        // Torn line of an interrupted write
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(day_file(&dir, Date::new(2025, 6, 2)))
+            .unwrap();
+        file.write_all(b"1748851200000,Room,La").unwrap();
+
+        let history = History::open(&dir);
+        let samples = history
+            .range("Room", "Lamp", Metric::Power, at(1, 0, 0), at(3, 0, 0))
+            .unwrap();
+        let values: Vec<f64> = samples.iter().map(|sample| sample.value).collect();
+        assert_eq!(values, [5., 10., 20., 60., 0.]);
+        assert_eq!(samples[4].time, at(2, 8, 0));
+        let on = history
+            .range("Room", "Lamp", Metric::On, at(2, 0, 0), at(3, 0, 0))
+            .unwrap();
+        assert_eq!(on.len(), 1);
+
+        let hourly = history
+            .aggregate(
+                "Room",
+                "Lamp",
+                Metric::Power,
+                at(2, 0, 0),
+                at(3, 0, 0),
+                Duration::from_secs(3600),
+            )
+            .unwrap();
+        assert_eq!(
+            hourly,
+            [
+                Aggregate {
+                    start: at(2, 7, 0),
+                    count: 3,
+                    min: 10.,
+                    max: 60.,
+                    mean: 30.,
+                },
+                Aggregate {
+                    start: at(2, 8, 0),
+                    count: 1,
+                    min: 0.,
+                    max: 0.,
+                    mean: 0.,
+                }
+            ]
+        );
+
+        // Day 1 is past the retention once day 3 starts
+        clock.set(at(3, 0, 30));
+        recorder.record(&samples[..1]).unwrap();
+        let days: Vec<Date> = day_files(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|(day, _)| day)
+            .collect();
+        assert_eq!(days, [Date::new(2025, 6, 2), Date::new(2025, 6, 3)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_after_partial_line() {
+        let dir = std::env::temp_dir().join(format!("history-reopen-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let clock = ManualClock::new(at(2, 7, 0));
+        let sample = |power| Sample::new(clock.now(), "Room", "Lamp", Metric::Power, power);
+        let mut recorder = Recorder::with_clock(&dir, clock.clone()).unwrap();
+        recorder.record(&[sample(10.)]).unwrap();
+        drop(recorder);
+        // Torn line of a crash
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(day_file(&dir, Date::new(2025, 6, 2)))
+            .unwrap();
+        file.write_all(b"1748851200000,Room,La").unwrap();
+
+        let mut recorder = Recorder::with_clock(&dir, clock.clone()).unwrap();
+        recorder.record(&[sample(20.)]).unwrap();
+        let values: Vec<f64> = History::open(&dir)
+            .range("Room", "Lamp", Metric::Power, at(2, 0, 0), at(3, 0, 0))
+            .unwrap()
+            .iter()
+            .map(|sample| sample.value)
+            .collect();
+        assert_eq!(values, [10., 20.]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,18 @@
+use crate::SmartDevice;
 use crate::SmartHomeError;
-use crate::{SmartDevice, rooms::Room};
+use crate::events::{DeviceEvent, HomeEvent, Notifier, Observable, RoomEvent, SubscriptionId};
+use crate::rooms::{Entries, Entry, Iter, Room};
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::sync::{Arc, Weak};
 
+// Rooms are kept in insertion order unless moved or sorted.
 #[derive(Debug)]
 pub struct Home {
     pub name: String,
-    rooms: HashMap<String, Room>,
+    rooms: Vec<(String, Room)>,
+    notifier: Arc<Notifier<HomeEvent>>,
+    forwarders: HashMap<String, SubscriptionId>,
 }
 impl Home {
     pub fn new<T>(name: T) -> Home
@@ -14,32 +21,187 @@ impl Home {
     {
         Home {
             name: name.into(),
-            rooms: HashMap::new(),
+            rooms: Vec::new(),
+            notifier: Arc::default(),
+            forwarders: HashMap::new(),
         }
     }
-    pub fn new_with_rooms<T>(name: T, rooms: HashMap<String, Room>) -> Home
+    pub fn new_with_rooms<T>(name: T, rooms: impl IntoIterator<Item = (String, Room)>) -> Home
     where
         T: Into<String>,
     {
-        Home {
-            name: name.into(),
-            rooms,
+        let mut home = Home::new(name);
+        for (name, room) in rooms {
+            home.add_room(name, room);
         }
+        home
+    }
+    fn position(&self, name: &str) -> Option<usize> {
+        self.rooms
+            .iter()
+            .position(|(room_name, _)| room_name == name)
     }
     pub fn get_room(&self, name: &str) -> Option<&Room> {
-        self.rooms.get(name)
+        self.position(name).map(|i| &self.rooms[i].1)
     }
     pub fn get_room_mut(&mut self, name: &str) -> Option<&mut Room> {
-        self.rooms.get_mut(name)
+        self.position(name).map(|i| &mut self.rooms[i].1)
     }
-    pub fn add_room<T>(&mut self, name: T, room: Room)
+    pub fn room_names(&self) -> impl Iterator<Item = &str> {
+        self.rooms.iter().map(|(name, _)| name.as_str())
+    }
+    // Room with an existing name is replaced in its place and returned.
+    pub fn add_room<T>(&mut self, name: T, room: Room) -> Option<Room>
     where
         T: Into<String>,
     {
-        self.rooms.insert(name.into(), room);
+        let name = name.into();
+        let forwarder = forward(&room, &self.notifier, name.clone());
+        if let Some(old) = self.forwarders.insert(name.clone(), forwarder)
+            && let Some(i) = self.position(&name)
+        {
+            let (_, old_room) = std::mem::replace(&mut self.rooms[i], (name, room));
+            old_room.unsubscribe(old);
+            return Some(old_room);
+        }
+        self.rooms.push((name, room));
+        None
+    }
+    pub fn try_add_room<T>(&mut self, name: T, room: Room) -> Result<(), SmartHomeError>
+    where
+        T: Into<String>,
+    {
+        let name = name.into();
+        if self.position(&name).is_some() {
+            return Err(SmartHomeError::AlreadyExists(name));
+        }
+        self.add_room(name, room);
+        Ok(())
+    }
+    pub fn entry(&mut self, name: impl Into<String>) -> Entry<'_, Room> {
+        Entry::new(self, name.into())
     }
-    pub fn remove_room(&mut self, name: &str) {
-        self.rooms.remove(name);
+    pub fn remove_room(&mut self, name: &str) -> Option<Room> {
+        let i = self.position(name)?;
+        let (_, room) = self.rooms.remove(i);
+        if let Some(forwarder) = self.forwarders.remove(name) {
+            room.unsubscribe(forwarder);
+        }
+        Some(room)
+    }
+    // Keeps the room in its place with all devices connected. Listeners of the home get
+    // Removed under the old room name and Added under the new one for every device.
+    pub fn rename_room(&mut self, name: &str, new_name: &str) -> Result<(), SmartHomeError> {
+        let i = self
+            .position(name)
+            .ok_or(SmartHomeError::RoomNotFound(name.to_string()))?;
+        if new_name.trim().is_empty() {
+            return Err(SmartHomeError::ValidationError(
+                "room name can not be empty".to_string(),
+            ));
+        }
+        if name == new_name {
+            return Ok(());
+        }
+        if self.position(new_name).is_some() {
+            return Err(SmartHomeError::AlreadyExists(new_name.to_string()));
+        }
+        self.rooms[i].0 = new_name.to_string();
+        let room = &self.rooms[i].1;
+        if let Some(forwarder) = self.forwarders.remove(name) {
+            room.unsubscribe(forwarder);
+        }
+        let forwarder = forward(room, &self.notifier, new_name.to_string());
+        self.forwarders.insert(new_name.to_string(), forwarder);
+        for (room_name, event) in [(name, DeviceEvent::Removed), (new_name, DeviceEvent::Added)] {
+            for device in room.device_names() {
+                self.notifier.emit(&HomeEvent {
+                    room: room_name.to_string(),
+                    device: device.to_string(),
+                    event,
+                });
+            }
+        }
+        Ok(())
+    }
+    pub fn rename_device(
+        &mut self,
+        room_name: &str,
+        name: &str,
+        new_name: &str,
+    ) -> Result<(), SmartHomeError> {
+        self.get_room_mut(room_name)
+            .ok_or(SmartHomeError::RoomNotFound(room_name.to_string()))?
+            .rename_device(name, new_name)
+    }
+    // Moves a connected device with its tags and metadata to the end of another room.
+    pub fn move_device(
+        &mut self,
+        from_room: &str,
+        to_room: &str,
+        name: &str,
+    ) -> Result<(), SmartHomeError> {
+        let from = self
+            .position(from_room)
+            .ok_or(SmartHomeError::RoomNotFound(from_room.to_string()))?;
+        let to = self
+            .position(to_room)
+            .ok_or(SmartHomeError::RoomNotFound(to_room.to_string()))?;
+        if self.rooms[from].1.get_device(name).is_none() {
+            return Err(SmartHomeError::DeviceNotFound(name.to_string()));
+        }
+        if from == to {
+            return Ok(());
+        }
+        if self.rooms[to].1.get_device(name).is_some() {
+            return Err(SmartHomeError::AlreadyExists(format!("{to_room}/{name}")));
+        }
+        let source = &mut self.rooms[from].1;
+        let detached = source.take_device(name).expect("device was found");
+        source.emit(name, DeviceEvent::Removed);
+        let target = &mut self.rooms[to].1;
+        target.put_device(name, detached);
+        target.emit(name, DeviceEvent::Added);
+        Ok(())
+    }
+    // Moves the room to `index`, indexes past the end move it to the end.
+    pub fn move_room(&mut self, name: &str, index: usize) -> Result<(), SmartHomeError> {
+        let from = self
+            .position(name)
+            .ok_or(SmartHomeError::RoomNotFound(name.to_string()))?;
+        let room = self.rooms.remove(from);
+        self.rooms.insert(index.min(self.rooms.len()), room);
+        Ok(())
+    }
+    pub fn sort_rooms(&mut self) {
+        self.sort_rooms_by(|a, b| a.cmp(b));
+    }
+    pub fn sort_rooms_by(&mut self, mut compare: impl FnMut(&str, &str) -> Ordering) {
+        self.rooms.sort_by(|(a, _), (b, _)| compare(a, b));
+    }
+    // Every device of the home as (room, device name, device).
+    pub fn devices(&self) -> impl Iterator<Item = (&str, &str, &SmartDevice)> {
+        self.rooms.iter().flat_map(|(room_name, room)| {
+            room.into_iter()
+                .map(move |(name, device)| (room_name.as_str(), name.as_str(), device))
+        })
+    }
+    pub fn devices_mut(&mut self) -> impl Iterator<Item = (&str, &str, &mut SmartDevice)> {
+        self.rooms.iter_mut().flat_map(|(room_name, room)| {
+            let room_name: &str = room_name;
+            room.iter_mut()
+                .map(move |(name, device)| (room_name, name.as_str(), device))
+        })
+    }
+    pub fn find_device(
+        &self,
+        room_name: &str,
+        device_name: &str,
+    ) -> Result<&SmartDevice, SmartHomeError> {
+        self.get_room(room_name)
+            .ok_or(SmartHomeError::RoomNotFound(room_name.to_string()))?
+            .get_device(device_name)
+            .ok_or(SmartHomeError::DeviceNotFound(device_name.to_string()))
     }
     pub fn get_device(
         &mut self,
@@ -56,11 +218,44 @@ impl Home {
     }
 }
 
+impl Entries<Room> for Home {
+    fn entries(&mut self) -> &mut Vec<(String, Room)> {
+        &mut self.rooms
+    }
+    fn insert(&mut self, name: String, value: Room) {
+        self.add_room(name, value);
+    }
+}
+
+impl Observable for Home {
+    type Event = HomeEvent;
+    fn notifier(&self) -> &Notifier<HomeEvent> {
+        &self.notifier
+    }
+}
+
+// Re-emits events of the room as events of the home while the home exists.
+fn forward(room: &Room, home: &Arc<Notifier<HomeEvent>>, name: String) -> SubscriptionId {
+    let home: Weak<Notifier<HomeEvent>> = Arc::downgrade(home);
+    room.notifier()
+        .listen(move |event: &RoomEvent| match home.upgrade() {
+            Some(home) => {
+                home.emit(&HomeEvent {
+                    room: name.clone(),
+                    device: event.device.clone(),
+                    event: event.event,
+                });
+                true
+            }
+            None => false,
+        })
+}
+
 impl<'a> IntoIterator for &'a Home {
     type Item = (&'a String, &'a Room);
-    type IntoIter = std::collections::hash_map::Iter<'a, String, Room>;
+    type IntoIter = Iter<'a, Room>;
     fn into_iter(self) -> Self::IntoIter {
-        self.rooms.iter()
+        Iter::new(&self.rooms)
     }
 }
 
@@ -69,6 +264,7 @@ mod tests {
     use std::io::Cursor;
 
     use crate::SmartSocket;
+    use crate::events::DeviceEvent;
 
     use super::*;
 
@@ -91,8 +287,8 @@ mod tests {
 
         assert_eq!(home.name, name);
         assert_eq!(home.rooms.len(), 2);
-        assert!(home.rooms.contains_key("Room 1"));
-        assert!(home.rooms.contains_key("Room 2"));
+        assert!(home.get_room("Room 1").is_some());
+        assert!(home.get_room("Room 2").is_some());
     }
     #[test]
     fn test_get_room() {
@@ -101,7 +297,7 @@ mod tests {
         let result = home.get_room("Room 1");
         assert!(result.is_none());
 
-        home.rooms.insert("Room 1".to_string(), Room::default());
+        home.add_room("Room 1", Room::default());
         let result = home.get_room("Room 1");
         assert!(result.is_some());
 
@@ -115,7 +311,7 @@ mod tests {
         let result = home.get_room_mut("Room 1");
         assert!(result.is_none());
 
-        home.rooms.insert("Room 1".to_string(), Room::default());
+        home.add_room("Room 1", Room::default());
         let result = home.get_room_mut("Room 1");
         assert!(result.is_some());
 
@@ -126,13 +322,13 @@ mod tests {
     fn test_add_room() {
         let mut home = Home::new("Test Home");
         home.add_room("Room 1".to_string(), Room::default());
-        assert!(home.rooms.contains_key("Room 1"));
+        assert!(home.get_room("Room 1").is_some());
 
         home.add_room("Room 1".to_string(), Room::default());
         assert_eq!(home.rooms.len(), 1);
 
         home.add_room("Room 2".to_string(), Room::default());
-        assert!(home.rooms.contains_key("Room 2"));
+        assert!(home.get_room("Room 2").is_some());
     }
     #[test]
     fn test_remove_room() {
@@ -142,9 +338,197 @@ mod tests {
 
         home.remove_room("Room 2");
         assert_eq!(home.rooms.len(), 1);
-        assert!(home.rooms.contains_key("Room 1"));
-        assert!(!home.rooms.contains_key("Room 2"));
+        assert!(home.get_room("Room 1").is_some());
+        assert!(home.get_room("Room 2").is_none());
     }
+    #[test]
+    fn test_room_order() {
+        let mut home = Home::new("Test Home");
+        for name in ["Kitchen", "Bathroom", "Hall"] {
+            home.add_room(name, Room::default());
+        }
+        let names = |home: &Home| home.room_names().map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(names(&home), ["Kitchen", "Bathroom", "Hall"]);
+        home.add_room("Bathroom", Room::default());
+        assert_eq!(names(&home), ["Kitchen", "Bathroom", "Hall"]);
+        home.move_room("Hall", 1).unwrap();
+        assert_eq!(names(&home), ["Kitchen", "Hall", "Bathroom"]);
+        assert!(matches!(
+            home.move_room("Attic", 0),
+            Err(SmartHomeError::RoomNotFound(_))
+        ));
+        home.sort_rooms();
+        let rooms: Vec<&String> = home.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(rooms, ["Bathroom", "Hall", "Kitchen"]);
+    }
+
+    #[test]
+    fn test_home_events() {
+        let mut home = Home::new("Test Home");
+        home.add_room("Room 1", Room::default());
+        let events = home.subscribe_channel();
+        // Device added after the room still reports to the home
+        home.get_room_mut("Room 1").unwrap().add_device(
+            "Device 1",
+            SmartDevice::from(SmartSocket::new(Cursor::new(Vec::new()))),
+        );
+        let device = home.get_device("Room 1", "Device 1").unwrap();
+        assert!(device.as_socket().unwrap().switch().is_err());
+        assert_eq!(
+            events.try_recv(),
+            Ok(HomeEvent {
+                room: "Room 1".to_string(),
+                device: "Device 1".to_string(),
+                event: DeviceEvent::DeviceOffline,
+            })
+        );
+
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let id = home.subscribe(move |_| {
+            calls_clone.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        });
+        home.notifier().emit(&HomeEvent {
+            room: String::new(),
+            device: String::new(),
+            event: DeviceEvent::DeviceOnline,
+        });
+        assert!(home.unsubscribe(id));
+        home.notifier().emit(&HomeEvent {
+            room: String::new(),
+            device: String::new(),
+            event: DeviceEvent::DeviceOnline,
+        });
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    fn socket_home() -> Home {
+        let mut home = Home::new("Test Home");
+        for (room_name, devices) in [
+            ("Kitchen", ["Lamp", "Kettle"]),
+            ("Hall", ["Lamp", "Heater"]),
+        ] {
+            let mut room = Room::default();
+            for name in devices {
+                room.add_device(name, SmartSocket::new(Cursor::new(Vec::new())).into());
+            }
+            home.add_room(room_name, room);
+        }
+        home
+    }
+
+    fn event(room: &str, device: &str, event: DeviceEvent) -> HomeEvent {
+        HomeEvent {
+            room: room.to_string(),
+            device: device.to_string(),
+            event,
+        }
+    }
+
+    #[test]
+    fn test_replaced_and_removed() {
+        let mut home = socket_home();
+        let replaced = home.add_room("Hall", Room::default()).unwrap();
+        assert_eq!(replaced.len(), 2);
+        assert!(home.get_room("Hall").unwrap().is_empty());
+        assert!(matches!(
+            home.try_add_room("Kitchen", Room::default()),
+            Err(SmartHomeError::AlreadyExists(_))
+        ));
+        assert_eq!(home.get_room("Kitchen").unwrap().len(), 2);
+        home.try_add_room("Attic", Room::default()).unwrap();
+        let removed = home.remove_room("Kitchen").unwrap();
+        assert!(removed.get_device("Kettle").is_some());
+        assert!(home.remove_room("Kitchen").is_none());
+
+        home.entry("Garage").or_insert_with(Room::default);
+        home.entry("Hall")
+            .and_modify(|room| room.info.floor = Some(1))
+            .or_insert_with(Room::default);
+        assert_eq!(home.get_room("Hall").unwrap().info.floor, Some(1));
+        assert_eq!(
+            home.room_names().collect::<Vec<_>>(),
+            ["Hall", "Attic", "Garage"]
+        );
+    }
+
+    #[test]
+    fn test_rename_room() {
+        let mut home = socket_home();
+        let events = home.subscribe_channel();
+        home.rename_room("Kitchen", "Cookery").unwrap();
+        assert_eq!(home.room_names().collect::<Vec<_>>(), ["Cookery", "Hall"]);
+        let received: Vec<HomeEvent> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            [
+                event("Kitchen", "Lamp", DeviceEvent::Removed),
+                event("Kitchen", "Kettle", DeviceEvent::Removed),
+                event("Cookery", "Lamp", DeviceEvent::Added),
+                event("Cookery", "Kettle", DeviceEvent::Added),
+            ]
+        );
+        let device = home.find_device("Cookery", "Kettle").unwrap();
+        assert!(device.as_socket().unwrap().is_on().is_err());
+        assert_eq!(
+            events.try_recv(),
+            Ok(event("Cookery", "Kettle", DeviceEvent::DeviceOffline))
+        );
+
+        assert!(matches!(
+            home.rename_room("Cookery", "Hall"),
+            Err(SmartHomeError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            home.rename_room("Kitchen", "Attic"),
+            Err(SmartHomeError::RoomNotFound(_))
+        ));
+        home.rename_device("Hall", "Heater", "Radiator").unwrap();
+        assert!(home.find_device("Hall", "Radiator").is_ok());
+    }
+
+    #[test]
+    fn test_move_device() {
+        let mut home = socket_home();
+        home.tag_device("Kitchen", "Kettle", "critical").unwrap();
+        let events = home.subscribe_channel();
+        home.move_device("Kitchen", "Hall", "Kettle").unwrap();
+        assert!(home.find_device("Kitchen", "Kettle").is_err());
+        let hall = home.get_room("Hall").unwrap();
+        assert_eq!(
+            hall.device_names().collect::<Vec<_>>(),
+            ["Lamp", "Heater", "Kettle"]
+        );
+        assert!(hall.has_tag("Kettle", "critical"));
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                event("Kitchen", "Kettle", DeviceEvent::Removed),
+                event("Hall", "Kettle", DeviceEvent::Added),
+            ]
+        );
+        let device = home.find_device("Hall", "Kettle").unwrap();
+        assert!(device.as_socket().unwrap().is_on().is_err());
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [event("Hall", "Kettle", DeviceEvent::DeviceOffline)]
+        );
+
+        assert!(matches!(
+            home.move_device("Kitchen", "Hall", "Lamp"),
+            Err(SmartHomeError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            home.move_device("Kitchen", "Attic", "Lamp"),
+            Err(SmartHomeError::RoomNotFound(_))
+        ));
+        assert!(matches!(
+            home.move_device("Kitchen", "Hall", "Heater"),
+            Err(SmartHomeError::DeviceNotFound(_))
+        ));
+        assert!(home.find_device("Kitchen", "Lamp").is_ok());
+    }
+
     #[test]
     fn test_get_device() {
         let mut home = Home::new("Test Home");
@@ -163,5 +547,15 @@ mod tests {
 
         let result = home.get_device("Room 2", "Device 2");
         assert!(result.is_err());
+
+        assert!(home.find_device("Room 1", "Device 1").is_ok());
+        assert!(matches!(
+            home.find_device("Room 1", "Device 2"),
+            Err(SmartHomeError::DeviceNotFound(_))
+        ));
+        assert!(matches!(
+            home.find_device("Room 2", "Device 1"),
+            Err(SmartHomeError::RoomNotFound(_))
+        ));
     }
 }
@@ -0,0 +1,560 @@
+use crate::automation::{Action, DeviceRef};
+use crate::errors::ErrorCode;
+use crate::{Home, Observable, Room, SmartDevice, SmartHomeError, json::Json, metrics};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, RwLock, RwLockReadGuard, mpsc},
+    thread,
+    time::Duration,
+};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+const DEVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// REST API over a shared home:
+//
+//   GET  /rooms                                   rooms with their devices
+//   GET  /rooms/{room}                            devices of the room with state
+//   GET  /rooms/{room}/devices/{device}           device state
+//   POST /rooms/{room}/devices/{device}/switch    toggle a socket
+//   GET  /devices/{room}/{device}                 state of devices matching the path
+//   POST /devices/{room}/{device}/{command}       switch, on or off every matching socket
+//   GET  /report                                  state of the whole home
+//   GET  /events                                  server-sent events with device changes
+//   GET  /metrics                                 Prometheus metrics, see `metrics`
+//
+// Names in the path are percent-encoded. `/devices` names may use `*` and `?` wildcards,
+// `\` makes them literal.
+// Every response but the event stream and metrics is JSON.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+}
+
+impl Request {
+    pub fn new(method: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            method: method.into(),
+            path: path.into(),
+        }
+    }
+
+    pub fn read_from(stream: &mut impl Read) -> io::Result<Request> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid request line",
+            ));
+        };
+        let request = Request::new(method, target.split('?').next().unwrap_or(target));
+
+        let mut content_length = 0;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.trim().eq_ignore_ascii_case("content-length")
+            {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+        // Body is not used by any route, it is only drained.
+        io::copy(&mut reader.take(content_length), &mut io::sink())?;
+        Ok(request)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Json,
+}
+
+impl Response {
+    pub fn ok(body: Json) -> Self {
+        Self { status: 200, body }
+    }
+
+    pub fn error(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: Json::object().with("error", message.into()),
+        }
+    }
+
+    pub fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+        let body = self.body.to_string();
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.status,
+            reason(self.status),
+            body.len()
+        )?;
+        stream.flush()
+    }
+}
+
+impl From<SmartHomeError> for Response {
+    fn from(err: SmartHomeError) -> Self {
+        let status = match err.code() {
+            ErrorCode::RoomNotFound | ErrorCode::DeviceNotFound => 404,
+            ErrorCode::WrongDeviceType | ErrorCode::Validation => 400,
+            ErrorCode::AlreadyExists => 409,
+            ErrorCode::Connection | ErrorCode::Protocol => 502,
+            ErrorCode::Timeout => 504,
+            ErrorCode::Config | ErrorCode::Automation | ErrorCode::Storage => 500,
+        };
+        let mut response = Response::error(status, err.to_string());
+        response.body = response.body.with("code", err.code().as_str());
+        response
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+// Requests only read the home, so a request waiting on a slow device does not block the
+// others, only those to the same device.
+fn read(home: &RwLock<Home>) -> RwLockReadGuard<'_, Home> {
+    match home.read() {
+        Ok(g) => g,
+        Err(poison_error) => poison_error.into_inner(),
+    }
+}
+
+fn get_room<'a>(home: &'a Home, name: &str) -> Result<&'a Room, SmartHomeError> {
+    home.get_room(name)
+        .ok_or(SmartHomeError::RoomNotFound(name.to_string()))
+}
+
+pub fn handle(home: &RwLock<Home>, request: &Request) -> Response {
+    let segments: Vec<String> = request
+        .path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let home = read(home);
+
+    let result = match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["rooms"]) => Ok(rooms(&home)),
+        ("GET", ["rooms", room]) => get_room(&home, room).map(|r| Json::room(room, r)),
+        ("GET", ["rooms", room, "devices", device]) => get_room(&home, room).and_then(|r| {
+            let d = r
+                .get_device(device)
+                .ok_or(SmartHomeError::DeviceNotFound(device.to_string()))?;
+            Ok(Json::device(device, d).with("info", r.device_info(device)))
+        }),
+        ("POST", ["rooms", room, "devices", device, "switch"]) => {
+            home.find_device(room, device).and_then(|d| {
+                let socket = d.as_socket()?;
+                socket.switch()?;
+                let status = socket.status()?;
+                Ok(Json::object()
+                    .with("name", *device)
+                    .with("on", status.on)
+                    .with("power", status.power))
+            })
+        }
+        ("GET", ["devices", room, device]) => select(&home, &DeviceRef::new(*room, *device)),
+        ("POST", ["devices", room, device, command @ ("switch" | "on" | "off")]) => command
+            .parse()
+            .and_then(|command| Action::new(DeviceRef::new(*room, *device), command).execute(&home))
+            .and_then(|()| select(&home, &DeviceRef::new(*room, *device))),
+        ("GET", ["report"]) => Ok(Json::home(&home)),
+        (_, ["rooms"] | ["rooms", _] | ["rooms", _, "devices", _] | ["report"])
+        | (_, ["rooms", _, "devices", _, "switch"] | ["devices", _, _])
+        | (_, ["devices", _, _, "switch" | "on" | "off"]) => {
+            return Response::error(405, "Method not allowed");
+        }
+        _ => return Response::error(404, "Not found"),
+    };
+    match result {
+        Ok(body) => Response::ok(body),
+        Err(err) => err.into(),
+    }
+}
+
+fn select(home: &Home, path: &DeviceRef) -> Result<Json, SmartHomeError> {
+    let devices: Vec<Json> = home
+        .resolve(path)?
+        .into_iter()
+        .map(|(room, name, device)| Json::device(name, device).with("room", room))
+        .collect();
+    Ok(Json::Array(devices))
+}
+
+fn rooms(home: &Home) -> Json {
+    let rooms: Vec<Json> = home
+        .into_iter()
+        .map(|(room_name, room)| {
+            let devices: Vec<Json> = room
+                .into_iter()
+                .map(|(device_name, device)| {
+                    Json::object()
+                        .with("name", device_name)
+                        .with("kind", device.kind().to_string())
+                })
+                .collect();
+            Json::object()
+                .with("name", room_name)
+                .with("devices", devices)
+        })
+        .collect();
+    Json::Array(rooms)
+}
+
+// Only `%XX` is decoded, `+` means a space in query strings but not in paths.
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+pub struct HttpServer {
+    listener: TcpListener,
+    home: Arc<RwLock<Home>>,
+}
+
+impl HttpServer {
+    // Sockets without a timeout get one, a hung plug would keep its requests waiting
+    // forever otherwise.
+    pub fn bind(address: impl ToSocketAddrs, home: Arc<RwLock<Home>>) -> io::Result<Self> {
+        for (_, _, device) in read(&home).devices() {
+            if let SmartDevice::SmartSocket(socket) = device
+                && socket.timeout().is_none()
+            {
+                socket
+                    .set_timeout(Some(DEVICE_TIMEOUT))
+                    .map_err(io::Error::other)?;
+            }
+        }
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            home,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Serves every connection in its own thread, never returns on success.
+    pub fn run(&self) -> io::Result<()> {
+        for connection in self.listener.incoming() {
+            let stream = match connection {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("can't receive connection: {err}");
+                    continue;
+                }
+            };
+            let home = self.home.clone();
+            thread::spawn(move || serve(stream, &home));
+        }
+        Ok(())
+    }
+
+    pub fn spawn(self) -> thread::JoinHandle<io::Result<()>> {
+        thread::spawn(move || self.run())
+    }
+}
+
+fn serve(mut stream: TcpStream, home: &RwLock<Home>) {
+    let response = match Request::read_from(&mut stream) {
+        Ok(request) if request.path == "/events" && request.method == "GET" => {
+            let _ = stream_events(stream, home);
+            return;
+        }
+        Ok(request) if request.path == "/events" => Response::error(405, "Method not allowed"),
+        Ok(request) if request.path == "/metrics" && request.method == "GET" => {
+            let _ = metrics::write_metrics(&mut stream, home);
+            return;
+        }
+        Ok(request) if request.path == "/metrics" => Response::error(405, "Method not allowed"),
+        Ok(request) => handle(home, &request),
+        Err(err) => Response::error(400, err.to_string()),
+    };
+    let _ = response.write_to(&mut stream);
+}
+
+// Streams events of all devices of the home until the client disconnects.
+fn stream_events(mut stream: TcpStream, home: &RwLock<Home>) -> io::Result<()> {
+    let events = read(home).subscribe_channel();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+    )?;
+    stream.flush()?;
+    loop {
+        match events.recv_timeout(KEEP_ALIVE) {
+            Ok(event) => write!(
+                stream,
+                "event: {}\ndata: {}\n\n",
+                event.event.name(),
+                Json::from(&event)
+            )?,
+            // Comment line lets us notice disconnected clients while devices are quiet.
+            Err(mpsc::RecvTimeoutError::Timeout) => write!(stream, ": keep-alive\n\n")?,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        stream.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::SmartDeviceConnect;
+    use crate::emulator::{Fleet, FleetDescription, FleetRoom};
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    fn fake_home() -> RwLock<Home> {
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        room.add_device(
+            "Socket",
+            crate::SmartSocket::new(Cursor::new(Vec::new())).into(),
+        );
+        home.add_room("Room 1", room);
+        RwLock::new(home)
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("Room%201"), "Room 1");
+        assert_eq!(percent_decode("Room+1"), "Room+1");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%+1x"), "%+1x");
+        assert_eq!(percent_decode("%D0%9A"), "К");
+    }
+
+    #[test]
+    fn test_routes() {
+        let home = fake_home();
+        let response = handle(&home, &Request::new("GET", "/rooms"));
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body.to_string(),
+            r#"[{"name":"Room 1","devices":[{"name":"Socket","kind":"socket"}]}]"#
+        );
+
+        let response = handle(&home, &Request::new("GET", "/rooms/Room%202"));
+        assert!(
+            response
+                .body
+                .to_string()
+                .ends_with(r#""code":"room_not_found"}"#)
+        );
+
+        let status = |method, path| handle(&home, &Request::new(method, path)).status;
+        assert_eq!(status("GET", "/rooms/Room%202"), 404);
+        assert_eq!(status("GET", "/rooms/Room%201/devices/Lamp"), 404);
+        assert_eq!(status("POST", "/rooms/Room%201/devices/Socket/switch"), 502);
+        assert_eq!(status("GET", "/rooms/Room%201/devices/Socket/switch"), 405);
+        assert_eq!(status("DELETE", "/report"), 405);
+        assert_eq!(status("GET", "/garage"), 404);
+        assert_eq!(status("GET", "/report"), 200);
+        assert_eq!(status("GET", "/devices/*/Sock*"), 200);
+        assert_eq!(status("GET", "/devices/*/Lamp*"), 404);
+        assert_eq!(status("DELETE", "/devices/*/Sock*"), 405);
+        assert_eq!(status("POST", "/devices/Room%201/*/on"), 502);
+    }
+
+    #[test]
+    fn test_read_request() {
+        let mut raw = Cursor::new(
+            "POST /rooms/R/devices/S/switch?x=1 HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\n{}",
+        );
+        let request = Request::read_from(&mut raw).unwrap();
+        assert_eq!(request, Request::new("POST", "/rooms/R/devices/S/switch"));
+        assert!(Request::read_from(&mut Cursor::new("\r\n")).is_err());
+    }
+
+    fn http(address: SocketAddr, method: &str, path: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{method} {path} HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap().to_string();
+        (status, body)
+    }
+
+    #[test]
+    fn test_server_with_emulators() {
+        let fleet = Fleet::new(&FleetDescription {
+            base_port: 0,
+            period: Duration::from_millis(50),
+            rooms: vec![FleetRoom {
+                name: "Kitchen".to_string(),
+                sockets: 1,
+                thermometers: 1,
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let home = fleet.config().connect().unwrap();
+        let _fleet = fleet.start();
+
+        let server = HttpServer::bind("127.0.0.1:0", Arc::new(RwLock::new(home))).unwrap();
+        let address = server.local_addr().unwrap();
+        server.spawn();
+
+        let (status, body) = http(address, "POST", "/rooms/Kitchen/devices/Socket1/switch");
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"name":"Socket1","on":true,"power":1000}"#);
+
+        let (status, body) = http(address, "GET", "/rooms/Kitchen/devices/Socket1");
+        assert_eq!(status, 200);
+        assert!(body.contains(r#""state":{"on":true,"power":1000}"#));
+
+        let (status, _) = http(
+            address,
+            "POST",
+            "/rooms/Kitchen/devices/Thermometer1/switch",
+        );
+        assert_eq!(status, 400);
+
+        let (status, body) = http(address, "POST", "/devices/*/*/off");
+        assert_eq!(status, 200);
+        assert!(body.contains(r#""state":{"on":false,"power":0}"#));
+        assert!(body.contains(r#""name":"Thermometer1""#));
+
+        let (status, body) = http(address, "GET", "/report");
+        assert_eq!(status, 200);
+        assert!(body.contains("Thermometer1"));
+
+        let (status, body) = http(address, "GET", "/metrics");
+        assert_eq!(status, 200);
+        assert!(body.contains(r#"smart_home_socket_on{room="Kitchen",device="Socket1"} 0"#));
+        assert_eq!(http(address, "POST", "/metrics").0, 405);
+    }
+
+    #[test]
+    fn test_hung_socket() {
+        // Accepts but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let hung_address = listener.local_addr().unwrap();
+        let hung = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(2));
+            drop(stream);
+        });
+        let fleet = Fleet::new(&FleetDescription {
+            base_port: 0,
+            rooms: vec![FleetRoom {
+                name: "Kitchen".to_string(),
+                sockets: 1,
+                thermometers: 0,
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let mut home = fleet.config().connect().unwrap();
+        let _fleet = fleet.start();
+        let socket = crate::SmartSocket::connect(hung_address).unwrap();
+        socket
+            .set_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        home.get_room_mut("Kitchen")
+            .unwrap()
+            .add_device("Hung", socket.into());
+
+        let server = HttpServer::bind("127.0.0.1:0", Arc::new(RwLock::new(home))).unwrap();
+        let address = server.local_addr().unwrap();
+        server.spawn();
+
+        let waiting =
+            thread::spawn(move || http(address, "POST", "/rooms/Kitchen/devices/Hung/switch"));
+        thread::sleep(Duration::from_millis(100));
+        let started = std::time::Instant::now();
+        let (status, _) = http(address, "POST", "/rooms/Kitchen/devices/Socket1/switch");
+        assert_eq!(status, 200);
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert_eq!(waiting.join().unwrap().0, 504);
+        hung.join().unwrap();
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let fleet = Fleet::new(&FleetDescription {
+            base_port: 0,
+            period: Duration::from_millis(50),
+            rooms: vec![FleetRoom {
+                name: "Kitchen".to_string(),
+                sockets: 1,
+                thermometers: 1,
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let home = fleet.config().connect().unwrap();
+        let _fleet = fleet.start();
+
+        let server = HttpServer::bind("127.0.0.1:0", Arc::new(RwLock::new(home))).unwrap();
+        let address = server.local_addr().unwrap();
+        server.spawn();
+
+        let mut events = TcpStream::connect(address).unwrap();
+        write!(events, "GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut events = BufReader::new(events);
+        let mut line = String::new();
+        events.read_line(&mut line).unwrap();
+        assert_eq!(line, "HTTP/1.1 200 OK\r\n");
+
+        http(address, "POST", "/rooms/Kitchen/devices/Socket1/switch");
+        let mut seen = (false, false);
+        while seen != (true, true) {
+            line.clear();
+            events.read_line(&mut line).unwrap();
+            if line.starts_with("data: ") {
+                seen.0 |= line.contains(
+                    r#"{"event":"switched","value":true,"room":"Kitchen","device":"Socket1"}"#,
+                );
+                seen.1 |= line.contains(r#""event":"temperature""#);
+            }
+        }
+    }
+}
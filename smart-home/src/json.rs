@@ -0,0 +1,296 @@
+use crate::{
+    DeviceState, Home, Room, SmartDevice,
+    events::{DeviceEvent, HomeEvent},
+    metadata::{DeviceInfo, RoomInfo},
+    polling::{Polled, REPORT_DEADLINE, Snapshot},
+};
+use std::fmt;
+
+// Minimal JSON value used for machine readable output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object() -> Json {
+        Json::Object(Vec::new())
+    }
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<Json>) -> Json {
+        if let Json::Object(fields) = &mut self {
+            fields.push((key.into(), value.into()));
+        }
+        self
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<f32> for Json {
+    fn from(value: f32) -> Self {
+        Json::Number(decimal(value))
+    }
+}
+
+// Going through the shortest decimal keeps 21.3f32 as 21.3 instead of 21.299999237060547
+pub(crate) fn decimal(value: f32) -> f64 {
+    value.to_string().parse().unwrap_or(f64::NAN)
+}
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        Json::Number(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl From<&String> for Json {
+    fn from(value: &String) -> Self {
+        Json::String(value.clone())
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(values: Vec<T>) -> Self {
+        Json::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl From<DeviceState> for Json {
+    fn from(state: DeviceState) -> Self {
+        match state {
+            DeviceState::Socket { on, power } => Json::object().with("on", on).with("power", power),
+            DeviceState::Thermometer { temperature } => {
+                Json::object().with("temperature", temperature)
+            }
+        }
+    }
+}
+
+impl From<DeviceEvent> for Json {
+    fn from(event: DeviceEvent) -> Self {
+        let json = Json::object().with("event", event.name());
+        match event {
+            DeviceEvent::TemperatureUpdated(value) | DeviceEvent::PowerChanged(value) => {
+                json.with("value", value)
+            }
+            DeviceEvent::SocketSwitched(on) => json.with("value", on),
+            DeviceEvent::DeviceOffline
+            | DeviceEvent::DeviceOnline
+            | DeviceEvent::Added
+            | DeviceEvent::Removed => json,
+        }
+    }
+}
+
+impl From<&HomeEvent> for Json {
+    fn from(event: &HomeEvent) -> Self {
+        Json::from(event.event)
+            .with("room", &event.room)
+            .with("device", &event.device)
+    }
+}
+
+impl From<&RoomInfo> for Json {
+    fn from(info: &RoomInfo) -> Self {
+        Json::object()
+            .with("display_name", info.display_name.as_ref())
+            .with("floor", info.floor.map(f64::from))
+            .with("area", info.area)
+            .with("description", info.description.as_ref())
+            .with("icon", info.icon.as_ref())
+    }
+}
+
+impl From<&DeviceInfo> for Json {
+    fn from(info: &DeviceInfo) -> Self {
+        Json::object()
+            .with("manufacturer", info.manufacturer.as_ref())
+            .with("model", info.model.as_ref())
+            .with("location", info.location.as_ref())
+            .with("installed", info.installed.map(|date| date.to_string()))
+    }
+}
+
+impl Json {
+    // Device description with its current state or the error of reading it.
+    pub fn device(name: &str, device: &SmartDevice) -> Json {
+        let json = Json::object()
+            .with("name", name)
+            .with("kind", device.kind().to_string());
+        match device.state() {
+            Ok(state) => json.with("state", state),
+            Err(err) => json.with("error", err.to_string()),
+        }
+    }
+    // Like `Json::device` with the state from a snapshot.
+    pub fn polled(name: &str, device: &SmartDevice, polled: Option<&Polled>) -> Json {
+        let json = Json::object()
+            .with("name", name)
+            .with("kind", device.kind().to_string());
+        match polled {
+            Some(Polled::State(state)) => json.with("state", *state),
+            Some(Polled::Cached(state, age)) => {
+                json.with("state", *state).with("age", age.as_secs_f64())
+            }
+            Some(Polled::Failed(err)) => json.with("error", err.to_string()),
+            Some(Polled::TimedOut) => json
+                .with("error", "no answer before the deadline")
+                .with("timed_out", true),
+            None => json.with("error", "not polled"),
+        }
+    }
+    pub fn room(name: &str, room: &Room) -> Json {
+        room_json(name, room, Json::device)
+    }
+    // Devices are polled in parallel within `REPORT_DEADLINE`.
+    pub fn home(home: &Home) -> Json {
+        Json::snapshot(home, &home.poll(REPORT_DEADLINE))
+    }
+    pub fn snapshot(home: &Home, snapshot: &Snapshot) -> Json {
+        let rooms: Vec<Json> = home
+            .into_iter()
+            .map(|(room_name, room)| {
+                room_json(room_name, room, |name, device| {
+                    Json::polled(name, device, snapshot.get(room_name, name))
+                })
+            })
+            .collect();
+        Json::object().with("name", &home.name).with("rooms", rooms)
+    }
+}
+
+fn room_json(name: &str, room: &Room, device_json: impl Fn(&str, &SmartDevice) -> Json) -> Json {
+    let devices: Vec<Json> = room
+        .into_iter()
+        .map(|(name, device)| device_json(name, device).with("info", room.device_info(name)))
+        .collect();
+    Json::object()
+        .with("name", name)
+        .with("info", &room.info)
+        .with("devices", devices)
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{value}"),
+            Json::Number(value) if value.is_finite() => write!(f, "{value}"),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SmartSocket;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_json_format() {
+        let json = Json::object()
+            .with("name", "Room \"1\"\n")
+            .with("on", true)
+            .with("power", 12.5f32)
+            .with("nothing", Option::<bool>::None)
+            .with("list", vec![1usize, 2]);
+        assert_eq!(
+            json.to_string(),
+            r#"{"name":"Room \"1\"\n","on":true,"power":12.5,"nothing":null,"list":[1,2]}"#
+        );
+        assert_eq!(Json::Number(f64::NAN).to_string(), "null");
+        assert_eq!(Json::from(21.3f32).to_string(), "21.3");
+    }
+
+    #[test]
+    fn test_json_device_error() {
+        let device = SmartDevice::from(SmartSocket::new(Cursor::new(Vec::new())));
+        let json = Json::device("Socket", &device).to_string();
+        assert!(json.starts_with(r#"{"name":"Socket","kind":"socket","error":"#));
+    }
+
+    #[test]
+    fn test_json_info() {
+        let mut room = Room::default();
+        room.info.set("floor", "1").unwrap();
+        room.add_device("Socket", SmartSocket::new(Cursor::new(Vec::new())).into());
+        let mut info = DeviceInfo::default();
+        info.set("installed", "2024-03-01").unwrap();
+        room.set_device_info("Socket", info).unwrap();
+        let json = Json::room("Living", &room).to_string();
+        assert!(json.contains(r#""info":{"display_name":null,"floor":1,"area":null"#));
+        assert!(json.contains(r#""installed":"2024-03-01""#));
+    }
+}
@@ -1,53 +1,102 @@
+pub mod automation;
+pub mod clock;
+pub mod config;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+#[cfg(feature = "sqlite")]
+pub mod database;
 pub mod devices;
+pub mod discovery;
+pub mod emulator;
+pub mod errors;
+pub mod events;
+pub mod groups;
+pub mod history;
 pub mod homes;
+pub mod http;
+pub mod json;
+pub mod metadata;
+pub mod metrics;
+pub mod paths;
+pub mod polling;
 pub mod report;
 pub mod rooms;
+pub mod scenes;
+pub mod scheduler;
+pub mod thermostat;
 
+pub use config::DeviceKind;
+pub use devices::DeviceState;
 pub use devices::smartsocket::SmartSocket;
 pub use devices::termo::SmartThermometer;
+pub use errors::SmartHomeError;
+pub use events::Observable;
 pub use homes::Home;
 pub use report::Report;
 pub use rooms::Room;
 
+use events::{DeviceEvent, Notifier};
+use metrics::DeviceStats;
+
 #[derive(Debug)]
 pub enum SmartDevice {
     SmartThermometer(SmartThermometer),
     SmartSocket(SmartSocket),
 }
 
-impl From<SmartSocket> for SmartDevice {
-    fn from(socket: SmartSocket) -> Self {
-        SmartDevice::SmartSocket(socket)
+impl SmartDevice {
+    pub fn kind(&self) -> DeviceKind {
+        match self {
+            SmartDevice::SmartThermometer(_) => DeviceKind::Thermometer,
+            SmartDevice::SmartSocket(_) => DeviceKind::Socket,
+        }
     }
-}
-
-impl From<SmartThermometer> for SmartDevice {
-    fn from(thermometer: SmartThermometer) -> Self {
-        SmartDevice::SmartThermometer(thermometer)
+    pub fn as_socket(&self) -> Result<&SmartSocket, SmartHomeError> {
+        match self {
+            SmartDevice::SmartSocket(socket) => Ok(socket),
+            _ => Err(SmartHomeError::WrongDeviceType(self.kind().to_string())),
+        }
+    }
+    pub fn as_thermometer(&self) -> Result<&SmartThermometer, SmartHomeError> {
+        match self {
+            SmartDevice::SmartThermometer(thermometer) => Ok(thermometer),
+            _ => Err(SmartHomeError::WrongDeviceType(self.kind().to_string())),
+        }
+    }
+    pub fn stats(&self) -> &DeviceStats {
+        match self {
+            SmartDevice::SmartThermometer(thermometer) => thermometer.stats(),
+            SmartDevice::SmartSocket(socket) => socket.stats(),
+        }
+    }
+    pub fn state(&self) -> Result<DeviceState, SmartHomeError> {
+        match self {
+            SmartDevice::SmartThermometer(thermometer) => Ok(DeviceState::Thermometer {
+                temperature: thermometer.get_temperature(),
+            }),
+            SmartDevice::SmartSocket(socket) => Ok(socket.status()?.into()),
+        }
     }
 }
 
-#[derive(Debug)]
-pub enum SmartHomeError {
-    DeviceNotFound(String),
-    RoomNotFound(String),
-    ConnectionError(std::io::Error),
-}
-
-impl std::fmt::Display for SmartHomeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Observable for SmartDevice {
+    type Event = DeviceEvent;
+    fn notifier(&self) -> &Notifier<DeviceEvent> {
         match self {
-            SmartHomeError::DeviceNotFound(name) => write!(f, "Device {} not found", name),
-            SmartHomeError::RoomNotFound(name) => write!(f, "Room {} not found", name),
-            SmartHomeError::ConnectionError(err) => write!(f, "Connection error: {err}"),
+            SmartDevice::SmartThermometer(thermometer) => thermometer.notifier(),
+            SmartDevice::SmartSocket(socket) => socket.notifier(),
         }
     }
 }
 
-impl std::error::Error for SmartHomeError {}
+impl From<SmartSocket> for SmartDevice {
+    fn from(socket: SmartSocket) -> Self {
+        SmartDevice::SmartSocket(socket)
+    }
+}
 
-impl From<std::io::Error> for SmartHomeError {
-    fn from(err: std::io::Error) -> Self {
-        SmartHomeError::ConnectionError(err)
+impl From<SmartThermometer> for SmartDevice {
+    fn from(thermometer: SmartThermometer) -> Self {
+        SmartDevice::SmartThermometer(thermometer)
     }
 }
@@ -0,0 +1,177 @@
+use crate::SmartHomeError;
+use crate::clock::Date;
+use std::fmt;
+
+// Descriptive data of rooms and devices. It is kept in the model and the home config,
+// but nothing in the home depends on it.
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RoomInfo {
+    pub display_name: Option<String>,
+    pub floor: Option<i32>,
+    // Square meters.
+    pub area: Option<f32>,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DeviceInfo {
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub location: Option<String>,
+    pub installed: Option<Date>,
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, SmartHomeError> {
+    value
+        .parse()
+        .map_err(|_| SmartHomeError::ConfigError(format!("Invalid {key} '{value}'")))
+}
+
+fn unknown(key: &str) -> SmartHomeError {
+    SmartHomeError::ConfigError(format!("Unknown metadata '{key}'"))
+}
+
+impl RoomInfo {
+    pub fn is_empty(&self) -> bool {
+        *self == RoomInfo::default()
+    }
+
+    // (key, value) pairs as they are written to the config.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        [
+            ("display_name", self.display_name.clone()),
+            ("floor", self.floor.map(|floor| floor.to_string())),
+            ("area", self.area.map(|area| area.to_string())),
+            ("description", self.description.clone()),
+            ("icon", self.icon.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect()
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SmartHomeError> {
+        let value = value.trim();
+        match key {
+            "display_name" => self.display_name = Some(value.to_string()),
+            "floor" => self.floor = Some(parse(key, value)?),
+            "area" => {
+                let area: f32 = parse(key, value)?;
+                if !area.is_finite() || area < 0. {
+                    return Err(SmartHomeError::ValidationError(format!(
+                        "area '{value}' must be a square meter count of zero or more"
+                    )));
+                }
+                self.area = Some(area);
+            }
+            "description" => self.description = Some(value.to_string()),
+            "icon" => self.icon = Some(value.to_string()),
+            _ => return Err(unknown(key)),
+        }
+        Ok(())
+    }
+}
+
+// Short summary like `Living room, floor 1, 24.5 m²`.
+impl fmt::Display for RoomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        parts.extend(self.display_name.clone());
+        parts.extend(self.floor.map(|floor| format!("floor {floor}")));
+        parts.extend(self.area.map(|area| format!("{area} m²")));
+        parts.extend(self.description.clone());
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+impl DeviceInfo {
+    pub fn is_empty(&self) -> bool {
+        *self == DeviceInfo::default()
+    }
+
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        [
+            ("manufacturer", self.manufacturer.clone()),
+            ("model", self.model.clone()),
+            ("location", self.location.clone()),
+            ("installed", self.installed.map(|date| date.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect()
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SmartHomeError> {
+        let value = value.trim();
+        match key {
+            "manufacturer" => self.manufacturer = Some(value.to_string()),
+            "model" => self.model = Some(value.to_string()),
+            "location" => self.location = Some(value.to_string()),
+            "installed" => self.installed = Some(value.parse()?),
+            _ => return Err(unknown(key)),
+        }
+        Ok(())
+    }
+}
+
+// Short summary like `Acme S-100, behind the sofa, installed 2024-03-01`.
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        let product: Vec<&str> = [&self.manufacturer, &self.model]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if !product.is_empty() {
+            parts.push(product.join(" "));
+        }
+        parts.extend(self.location.clone());
+        parts.extend(self.installed.map(|date| format!("installed {date}")));
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_room_info() {
+        let mut info = RoomInfo::default();
+        assert!(info.is_empty());
+        info.set("display_name", "Living room").unwrap();
+        info.set("floor", "1").unwrap();
+        info.set("area", "24.5").unwrap();
+        assert!(info.set("floor", "first").is_err());
+        for area in ["NaN", "inf", "-3"] {
+            assert!(matches!(
+                info.set("area", area),
+                Err(SmartHomeError::ValidationError(_))
+            ));
+        }
+        assert!(info.set("colour", "red").is_err());
+        assert_eq!(info.to_string(), "Living room, floor 1, 24.5 m²");
+        assert_eq!(
+            info.entries(),
+            [
+                ("display_name", "Living room".to_string()),
+                ("floor", "1".to_string()),
+                ("area", "24.5".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_device_info() {
+        let mut info = DeviceInfo::default();
+        info.set("model", "S-100").unwrap();
+        info.set("installed", "2024-03-01").unwrap();
+        assert!(info.set("installed", "2024-02-30").is_err());
+        assert_eq!(info.to_string(), "S-100, installed 2024-03-01");
+        info.set("manufacturer", "Acme").unwrap();
+        assert_eq!(info.to_string(), "Acme S-100, installed 2024-03-01");
+    }
+}
@@ -0,0 +1,562 @@
+use crate::errors::ErrorCode;
+use crate::json::decimal;
+use crate::polling::{Polled, REPORT_DEADLINE, Snapshot};
+use crate::{DeviceKind, DeviceState, Home, SmartHomeError};
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+// Prometheus metrics of a home in the text exposition format:
+//
+//   smart_home_temperature_celsius{room="Kitchen",device="Outdoor"} 21.5
+//   smart_home_command_errors_total{room="Kitchen",device="Kettle",kind="timeout"} 2
+//
+// Readings come from the socket caches, which a Refresher keeps up to date, so a scrape
+// asks only sockets without a cache. The client counters come from the devices, where
+// they count since the device was connected.
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Upper bounds of the command latency histogram buckets in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5.];
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Histogram {
+    // Observations per bucket, not cumulative.
+    counts: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, seconds: f64) {
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.counts[index] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    // (upper bound, cumulative count) pairs, the last bound is infinity.
+    pub fn buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        let mut buckets: Vec<(f64, u64)> = LATENCY_BUCKETS
+            .iter()
+            .zip(self.counts)
+            .map(|(le, count)| {
+                total += count;
+                (*le, total)
+            })
+            .collect();
+        buckets.push((f64::INFINITY, self.count));
+        buckets
+    }
+}
+
+// Client side counters of a device.
+#[derive(Debug, Default)]
+pub struct DeviceStats {
+    latency: Mutex<Histogram>,
+    errors: Mutex<Vec<(ErrorCode, u64)>>,
+    reconnects: AtomicU64,
+    received: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl DeviceStats {
+    pub(crate) fn command(&self, took: Duration) {
+        lock(&self.latency).observe(took.as_secs_f64());
+    }
+
+    pub(crate) fn error(&self, err: &SmartHomeError) {
+        let code = err.code();
+        let mut errors = lock(&self.errors);
+        match errors.iter_mut().find(|(known, _)| *known == code) {
+            Some((_, count)) => *count += 1,
+            None => errors.push((code, 1)),
+        }
+    }
+
+    pub(crate) fn reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Rejected datagrams are counted as received too.
+    pub(crate) fn datagram(&self, accepted: bool) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        if !accepted {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn latency(&self) -> Histogram {
+        lock(&self.latency).clone()
+    }
+
+    // Failed commands by error code, in code order.
+    pub fn errors(&self) -> Vec<(ErrorCode, u64)> {
+        let mut errors = lock(&self.errors).clone();
+        errors.sort_by_key(|(code, _)| code.as_str());
+        errors
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    pub fn datagrams_received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    pub fn datagrams_rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+// Name, help, kind of the counted devices and value of a client counter.
+type Counter = (
+    &'static str,
+    &'static str,
+    DeviceKind,
+    fn(&DeviceStats) -> u64,
+);
+
+// Text being written, every family starts with its HELP and TYPE lines.
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} {kind}");
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(label, value)| format!("{label}=\"{}\"", escape(value)))
+            .collect();
+        let _ = writeln!(
+            self.text,
+            "{name}{{{}}} {}",
+            labels.join(","),
+            number(value)
+        );
+    }
+
+    // Samples of the readings `value` gives a value for.
+    fn readings(
+        &mut self,
+        readings: &[(&str, &str, DeviceState)],
+        name: &str,
+        value: impl Fn(&DeviceState) -> Option<f64>,
+    ) {
+        for (room, device, state) in readings {
+            if let Some(value) = value(state) {
+                self.sample(name, &[("room", room), ("device", device)], value);
+            }
+        }
+    }
+}
+
+// Readings of the snapshot and client counters of the home devices.
+pub fn render(home: &Home, snapshot: &Snapshot) -> String {
+    let mut out = Exposition {
+        text: String::new(),
+    };
+
+    out.family(
+        "smart_home_device_up",
+        "gauge",
+        "Whether the device answered the poll of this scrape.",
+    );
+    for (room, device, polled) in snapshot.devices() {
+        let up = polled.state().is_some();
+        out.sample(
+            "smart_home_device_up",
+            &[("room", room), ("device", device)],
+            if up { 1. } else { 0. },
+        );
+    }
+
+    let readings: Vec<(&str, &str, DeviceState)> = snapshot
+        .devices()
+        .filter_map(|(room, device, polled)| Some((room, device, polled.state()?)))
+        .collect();
+    out.family(
+        "smart_home_temperature_celsius",
+        "gauge",
+        "Last temperature reading of a thermometer.",
+    );
+    out.readings(
+        &readings,
+        "smart_home_temperature_celsius",
+        |state| match state {
+            DeviceState::Thermometer { temperature } => Some(decimal(*temperature)),
+            _ => None,
+        },
+    );
+    out.family(
+        "smart_home_socket_power_watts",
+        "gauge",
+        "Power drawn through a socket.",
+    );
+    out.readings(
+        &readings,
+        "smart_home_socket_power_watts",
+        |state| match state {
+            DeviceState::Socket { power, .. } => Some(decimal(*power)),
+            _ => None,
+        },
+    );
+    out.family(
+        "smart_home_socket_on",
+        "gauge",
+        "Whether a socket is switched on.",
+    );
+    out.readings(&readings, "smart_home_socket_on", |state| match state {
+        DeviceState::Socket { on, .. } => Some(if *on { 1. } else { 0. }),
+        _ => None,
+    });
+    out.family(
+        "smart_home_cache_age_seconds",
+        "gauge",
+        "Age of a state taken from the socket cache instead of asking the socket.",
+    );
+    for (room, device, polled) in snapshot.devices() {
+        if let Polled::Cached(_, age) = polled {
+            out.sample(
+                "smart_home_cache_age_seconds",
+                &[("room", room), ("device", device)],
+                age.as_secs_f64(),
+            );
+        }
+    }
+
+    out.family(
+        "smart_home_command_duration_seconds",
+        "histogram",
+        "Round-trip time of socket command exchanges.",
+    );
+    for (room, device, device_ref) in home.devices() {
+        let latency = device_ref.stats().latency();
+        if latency.count() == 0 {
+            continue;
+        }
+        for (le, count) in latency.buckets() {
+            out.sample(
+                "smart_home_command_duration_seconds_bucket",
+                &[("room", room), ("device", device), ("le", &number(le))],
+                count as f64,
+            );
+        }
+        let labels = [("room", room), ("device", device)];
+        out.sample(
+            "smart_home_command_duration_seconds_sum",
+            &labels,
+            latency.sum(),
+        );
+        out.sample(
+            "smart_home_command_duration_seconds_count",
+            &labels,
+            latency.count() as f64,
+        );
+    }
+    out.family(
+        "smart_home_command_errors_total",
+        "counter",
+        "Failed device commands by error kind.",
+    );
+    for (room, device, device_ref) in home.devices() {
+        for (code, count) in device_ref.stats().errors() {
+            out.sample(
+                "smart_home_command_errors_total",
+                &[("room", room), ("device", device), ("kind", code.as_str())],
+                count as f64,
+            );
+        }
+    }
+    // Counters only get samples for the kind of device they are about.
+    let counters: [Counter; 3] = [
+        (
+            "smart_home_reconnects_total",
+            "Connections made again after a socket fell out of step.",
+            DeviceKind::Socket,
+            DeviceStats::reconnects,
+        ),
+        (
+            "smart_home_datagrams_received_total",
+            "Datagrams received from a thermometer.",
+            DeviceKind::Thermometer,
+            DeviceStats::datagrams_received,
+        ),
+        (
+            "smart_home_datagrams_rejected_total",
+            "Thermometer datagrams which were not a valid reading.",
+            DeviceKind::Thermometer,
+            DeviceStats::datagrams_rejected,
+        ),
+    ];
+    for (name, help, kind, value) in counters {
+        out.family(name, "counter", help);
+        for (room, device, device_ref) in home.devices() {
+            if device_ref.kind() == kind {
+                out.sample(
+                    name,
+                    &[("room", room), ("device", device)],
+                    value(device_ref.stats()) as f64,
+                );
+            }
+        }
+    }
+    out.text
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn number(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        value => value.to_string(),
+    }
+}
+
+// Answers a scrape with the metrics of the home, sockets without a cache are polled with
+// the report deadline.
+pub(crate) fn write_metrics(stream: &mut impl Write, home: &RwLock<Home>) -> io::Result<()> {
+    let body = {
+        let home = read(home);
+        render(&home, &home.cached(REPORT_DEADLINE))
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+// Serves only `GET /metrics`, for exposing metrics apart from the REST API.
+pub struct MetricsServer {
+    listener: TcpListener,
+    home: Arc<RwLock<Home>>,
+}
+
+impl MetricsServer {
+    pub fn bind(address: impl ToSocketAddrs, home: Arc<RwLock<Home>>) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            home,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(&self) -> io::Result<()> {
+        for connection in self.listener.incoming() {
+            let stream = match connection {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("can't receive connection: {err}");
+                    continue;
+                }
+            };
+            let home = self.home.clone();
+            thread::spawn(move || serve(stream, &home));
+        }
+        Ok(())
+    }
+
+    pub fn spawn(self) -> thread::JoinHandle<io::Result<()>> {
+        thread::spawn(move || self.run())
+    }
+}
+
+fn serve(mut stream: TcpStream, home: &RwLock<Home>) {
+    use crate::http::{Request, Response};
+    let _ = match Request::read_from(&mut stream) {
+        Ok(request) if request.path == "/metrics" && request.method == "GET" => {
+            write_metrics(&mut stream, home)
+        }
+        Ok(request) if request.path == "/metrics" => {
+            Response::error(405, "Method not allowed").write_to(&mut stream)
+        }
+        Ok(_) => Response::error(404, "Not found").write_to(&mut stream),
+        Err(err) => Response::error(400, err.to_string()).write_to(&mut stream),
+    };
+}
+
+fn read(home: &RwLock<Home>) -> RwLockReadGuard<'_, Home> {
+    match home.read() {
+        Ok(g) => g,
+        Err(poison_error) => poison_error.into_inner(),
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(g) => g,
+        Err(poison_error) => poison_error.into_inner(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::termo::UdpLike;
+    use crate::emulator::{EmulatedSensor, EmulatedStream};
+    use crate::{Room, SmartSocket, SmartThermometer};
+    use std::io::{Read, Write};
+    use std::time::Instant;
+
+    // Stream of a socket which went away.
+    #[derive(Debug)]
+    struct BrokenStream;
+
+    impl Read for BrokenStream {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::ConnectionReset.into())
+        }
+    }
+
+    impl Write for BrokenStream {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Sends datagrams which are too short to be a reading.
+    struct GarbageSensor;
+
+    impl UdpLike for GarbageSensor {
+        fn send_to(&mut self, buf: &[u8], _target: SocketAddr) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn recv_from(&mut self, _buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            thread::sleep(Duration::from_millis(10));
+            Ok((3, SocketAddr::from(([127, 0, 0, 1], 0))))
+        }
+    }
+
+    fn home() -> Home {
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        room.add_device("Kettle", SmartSocket::new(EmulatedStream::new()).into());
+        room.add_device("Broken", SmartSocket::new(BrokenStream).into());
+        room.add_device(
+            "Outdoor",
+            SmartThermometer::new(EmulatedSensor::new(21.5)).into(),
+        );
+        room.add_device("Garbage", SmartThermometer::new(GarbageSensor).into());
+        home.add_room("Kitchen \"A\"", room);
+        home
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.003);
+        histogram.observe(0.2);
+        histogram.observe(10.);
+        assert_eq!(histogram.count(), 3);
+        let buckets = histogram.buckets();
+        assert_eq!(buckets[0], (0.005, 1));
+        assert_eq!(buckets[5], (0.25, 2));
+        assert_eq!(buckets[9], (5., 2));
+        assert_eq!(buckets[10], (f64::INFINITY, 3));
+    }
+
+    #[test]
+    fn test_render() {
+        let home = home();
+        let device = |name| home.find_device("Kitchen \"A\"", name).unwrap();
+        // Both sensors send at least once
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while (device("Outdoor").stats().datagrams_received() == 0
+            || device("Garbage").stats().datagrams_received() == 0)
+            && Instant::now() < deadline
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        device("Kettle").as_socket().unwrap().switch().unwrap();
+
+        let text = render(&home, &home.poll(Duration::from_secs(1)));
+        let labels = |device: &str| format!(r#"{{room="Kitchen \"A\"",device="{device}"}}"#);
+        for line in [
+            "# TYPE smart_home_temperature_celsius gauge".to_string(),
+            format!("smart_home_temperature_celsius{} 21.5", labels("Outdoor")),
+            format!("smart_home_socket_on{} 1", labels("Kettle")),
+            format!("smart_home_socket_power_watts{} 1000", labels("Kettle")),
+            format!("smart_home_device_up{} 0", labels("Broken")),
+            format!("smart_home_device_up{} 1", labels("Kettle")),
+            format!(
+                "smart_home_command_duration_seconds_count{} 2",
+                labels("Kettle")
+            ),
+            format!("smart_home_reconnects_total{} 0", labels("Kettle")),
+            format!("smart_home_datagrams_rejected_total{} 0", labels("Outdoor")),
+        ] {
+            assert!(text.lines().any(|l| l == line), "no '{line}' in\n{text}");
+        }
+        assert!(text.contains(r#"device="Kettle",le="+Inf"} 2"#));
+        assert!(text.contains(r#"device="Broken",kind="connection"} 1"#));
+        assert!(!text.contains(
+            r#"smart_home_command_duration_seconds_count{room="Kitchen \"A\"",device="Broken"}"#
+        ));
+
+        let garbage = device("Garbage");
+        assert!(garbage.stats().datagrams_rejected() > 0);
+        assert_eq!(
+            garbage.stats().datagrams_received(),
+            garbage.stats().datagrams_rejected()
+        );
+        assert!(garbage.as_thermometer().unwrap().last_update().is_none());
+    }
+
+    #[test]
+    fn test_metrics_server() {
+        let server = MetricsServer::bind("127.0.0.1:0", Arc::new(RwLock::new(home()))).unwrap();
+        let address = server.local_addr().unwrap();
+        server.spawn();
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(CONTENT_TYPE));
+        assert!(response.contains("# TYPE smart_home_command_errors_total counter"));
+        assert!(get("/report").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::automation::DeviceRef;
+use crate::{Home, SmartDevice, SmartHomeError};
+
+// Device reference `<room>/<device>` may be a path pattern where both parts use `*`
+// (any text) and `?` (any character) wildcards: `Room1/Socket1`, `*/Socket*`, `Room2/*`.
+// A `\` before `*`, `?` or `\` makes it literal, `Room1/Heater\*` is only the device
+// named `Heater*`.
+impl DeviceRef {
+    pub fn is_pattern(&self) -> bool {
+        is_pattern(&self.room) || is_pattern(&self.device)
+    }
+
+    pub fn matches(&self, room: &str, device: &str) -> bool {
+        glob(&self.room, room) && glob(&self.device, device)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Any,
+    One,
+    Literal(char),
+}
+
+fn tokens(pattern: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '*' => Token::Any,
+            '?' => Token::One,
+            '\\' => match chars.next_if(|next| matches!(next, '*' | '?' | '\\')) {
+                Some(escaped) => Token::Literal(escaped),
+                None => Token::Literal('\\'),
+            },
+            c => Token::Literal(c),
+        });
+    }
+    tokens
+}
+
+fn is_pattern(s: &str) -> bool {
+    tokens(s)
+        .iter()
+        .any(|token| !matches!(token, Token::Literal(_)))
+}
+
+// Name an exact pattern stands for.
+fn literal(s: &str) -> String {
+    tokens(s)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Literal(c) => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+fn glob(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<Token>, Vec<char>) = (tokens(pattern), text.chars().collect());
+    // Position of the last `*` and the text position it currently covers up to.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(Token::Any) => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(Token::One) => (p, t) = (p + 1, t + 1),
+            Some(Token::Literal(c)) if *c == text[t] => (p, t) = (p + 1, t + 1),
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    (p, t) = (star_p + 1, star_t + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|token| *token == Token::Any)
+}
+
+impl Home {
+    // Devices matching the path, an exact path gives at most one.
+    pub fn select<'a>(
+        &'a self,
+        path: &'a DeviceRef,
+    ) -> impl Iterator<Item = (&'a str, &'a str, &'a SmartDevice)> {
+        self.devices()
+            .filter(|(room, device, _)| path.matches(room, device))
+    }
+
+    pub fn select_mut<'a>(
+        &'a mut self,
+        path: &'a DeviceRef,
+    ) -> impl Iterator<Item = (&'a str, &'a str, &'a mut SmartDevice)> {
+        self.devices_mut()
+            .filter(|(room, device, _)| path.matches(room, device))
+    }
+
+    // Like `select`, but fails when nothing matches: an exact path with the room or
+    // device error of `find_device`, a pattern with DeviceNotFound of the whole path.
+    pub fn resolve<'a>(
+        &'a self,
+        path: &'a DeviceRef,
+    ) -> Result<Vec<(&'a str, &'a str, &'a SmartDevice)>, SmartHomeError> {
+        if !path.is_pattern() {
+            self.find_device(&literal(&path.room), &literal(&path.device))?;
+            return Ok(self.select(path).collect());
+        }
+        let devices: Vec<_> = self.select(path).collect();
+        if devices.is_empty() {
+            return Err(SmartHomeError::DeviceNotFound(path.to_string()));
+        }
+        Ok(devices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Room, SmartSocket};
+    use std::io::Cursor;
+
+    fn home() -> Home {
+        let mut home = Home::new("Test");
+        for (room_name, devices) in [
+            ("Room1", ["Socket1", "Socket2", "Thermometer1"]),
+            ("Room2", ["Socket1", "Lamp", "Socket10"]),
+        ] {
+            let mut room = Room::default();
+            for device in devices {
+                room.add_device(device, SmartSocket::new(Cursor::new(Vec::new())).into());
+            }
+            home.add_room(room_name, room);
+        }
+        home
+    }
+
+    fn selected(home: &Home, path: &str) -> Vec<String> {
+        let path: DeviceRef = path.parse().unwrap();
+        let mut devices: Vec<String> = home
+            .select(&path)
+            .map(|(room, device, _)| format!("{room}/{device}"))
+            .collect();
+        devices.sort();
+        devices
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("Socket*", "Socket"));
+        assert!(glob("Socket*", "Socket10"));
+        assert!(glob("*1", "Socket1"));
+        assert!(!glob("*1", "Socket10"));
+        assert!(glob("S?cket*0", "Socket10"));
+        assert!(glob("*o*e*", "Socket"));
+        assert!(!glob("Socket", "Socket1"));
+        assert!(glob("**", ""));
+        assert!(glob(r"Heater\*", "Heater*"));
+        assert!(!glob(r"Heater\*", "Heater2"));
+        assert!(glob(r"\?\\*", r"?\x"));
+        assert!(glob(r"A\B", r"A\B"));
+    }
+
+    #[test]
+    fn test_literal_wildcards() {
+        let mut home = home();
+        let room = home.get_room_mut("Room1").unwrap();
+        room.add_device("Heater*", SmartSocket::new(Cursor::new(Vec::new())).into());
+        room.add_device("Heater2", SmartSocket::new(Cursor::new(Vec::new())).into());
+        assert_eq!(selected(&home, r"Room1/Heater\*"), ["Room1/Heater*"]);
+        assert_eq!(selected(&home, "Room1/Heater*").len(), 2);
+        let path: DeviceRef = r"Room1/Heater\*".parse().unwrap();
+        assert!(!path.is_pattern());
+        let resolved = home.resolve(&path).unwrap();
+        assert_eq!((resolved[0].0, resolved[0].1), ("Room1", "Heater*"));
+    }
+
+    #[test]
+    fn test_select() {
+        let mut home = home();
+        assert_eq!(selected(&home, "Room1/Socket1"), ["Room1/Socket1"]);
+        assert_eq!(
+            selected(&home, "*/Socket*"),
+            [
+                "Room1/Socket1",
+                "Room1/Socket2",
+                "Room2/Socket1",
+                "Room2/Socket10"
+            ]
+        );
+        assert_eq!(
+            selected(&home, "Room2/*"),
+            ["Room2/Lamp", "Room2/Socket1", "Room2/Socket10"]
+        );
+        assert!(selected(&home, "Room3/*").is_empty());
+
+        let path: DeviceRef = "*/Lamp".parse().unwrap();
+        assert_eq!(home.select_mut(&path).count(), 1);
+        assert!("Room1".parse::<DeviceRef>().is_err());
+        assert!("/Socket".parse::<DeviceRef>().is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let home = home();
+        let resolve = |path: &str| home.resolve(&path.parse().unwrap()).map(|d| d.len());
+        assert_eq!(resolve("*/Socket1").unwrap(), 2);
+        assert!(matches!(
+            resolve("Room3/Socket1"),
+            Err(SmartHomeError::RoomNotFound(_))
+        ));
+        assert!(matches!(
+            resolve("Room1/Lamp"),
+            Err(SmartHomeError::DeviceNotFound(_))
+        ));
+        assert!(matches!(
+            resolve("*/Heater*"),
+            Err(SmartHomeError::DeviceNotFound(path)) if path == "*/Heater*"
+        ));
+    }
+}
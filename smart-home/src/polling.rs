@@ -0,0 +1,391 @@
+use crate::errors::ErrorCode;
+use crate::{DeviceState, Home, SmartDevice, SmartHomeError};
+use std::{
+    sync::{
+        Arc, RwLock, RwLockReadGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// Deadline of reports which do not set their own.
+pub const REPORT_DEADLINE: Duration = Duration::from_secs(5);
+// Devices are queried by at most this many threads.
+const WORKERS: usize = 16;
+
+#[derive(Debug)]
+pub enum Polled {
+    State(DeviceState),
+    // State from the socket cache and its age.
+    Cached(DeviceState, Duration),
+    Failed(SmartHomeError),
+    // No answer before the deadline.
+    TimedOut,
+}
+
+impl Polled {
+    pub fn state(&self) -> Option<DeviceState> {
+        match self {
+            Polled::State(state) | Polled::Cached(state, _) => Some(*state),
+            _ => None,
+        }
+    }
+}
+
+impl From<Result<DeviceState, SmartHomeError>> for Polled {
+    fn from(result: Result<DeviceState, SmartHomeError>) -> Self {
+        match result {
+            Ok(state) => Polled::State(state),
+            Err(err) if err.code() == ErrorCode::Timeout => Polled::TimedOut,
+            Err(err) => Polled::Failed(err),
+        }
+    }
+}
+
+// States of all devices of a home taken at about the same time, in home order.
+#[derive(Debug)]
+pub struct Snapshot {
+    devices: Vec<(String, String, Polled)>,
+}
+
+impl Snapshot {
+    pub fn get(&self, room: &str, device: &str) -> Option<&Polled> {
+        self.devices
+            .iter()
+            .find(|(r, d, _)| r == room && d == device)
+            .map(|(_, _, polled)| polled)
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = (&str, &str, &Polled)> {
+        self.devices
+            .iter()
+            .map(|(room, device, polled)| (room.as_str(), device.as_str(), polled))
+    }
+
+    pub fn timed_out(&self) -> usize {
+        self.devices
+            .iter()
+            .filter(|(_, _, polled)| matches!(polled, Polled::TimedOut))
+            .count()
+    }
+}
+
+impl Home {
+    pub fn poll(&self, deadline: Duration) -> Snapshot {
+        poll(self.devices(), deadline)
+    }
+
+    // Like `poll`, but every socket is asked even if its cache is fresh.
+    pub fn refresh(&self, deadline: Duration) -> Snapshot {
+        collect(self.devices().collect(), deadline, Ask::Always)
+    }
+
+    // Like `poll`, but sockets with a cache give their last state however old, for readers
+    // of the caches a Refresher keeps up to date. Only sockets without one are asked.
+    pub fn cached(&self, deadline: Duration) -> Snapshot {
+        collect(self.devices().collect(), deadline, Ask::Uncached)
+    }
+}
+
+// Which sockets a poll asks instead of taking their cached state.
+#[derive(Debug, Clone, Copy)]
+enum Ask {
+    Always,
+    Stale,
+    Uncached,
+}
+
+// Queries the devices concurrently. Devices which did not answer before the deadline are
+// TimedOut. Socket queries give up at the deadline, also when waiting for a socket busy in
+// another thread or connecting again, so no query outlives it by much.
+// Sockets with a fresh cache are not asked.
+pub fn poll<'a>(
+    devices: impl IntoIterator<Item = (&'a str, &'a str, &'a SmartDevice)>,
+    deadline: Duration,
+) -> Snapshot {
+    collect(devices.into_iter().collect(), deadline, Ask::Stale)
+}
+
+fn collect(devices: Vec<(&str, &str, &SmartDevice)>, deadline: Duration, ask: Ask) -> Snapshot {
+    let end = Instant::now() + deadline;
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut results: Vec<Option<Polled>> = devices.iter().map(|_| None).collect();
+    thread::scope(|scope| {
+        for _ in 0..devices.len().min(WORKERS) {
+            let sender = sender.clone();
+            let (devices, next) = (&devices, &next);
+            scope.spawn(move || {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((_, _, device)) = devices.get(index) else {
+                        break;
+                    };
+                    let _ = sender.send((index, query(device, end, ask)));
+                }
+            });
+        }
+        drop(sender);
+        while let Some(left) = end.checked_duration_since(Instant::now()) {
+            match receiver.recv_timeout(left) {
+                Ok((index, polled)) => results[index] = Some(polled),
+                Err(_) => break,
+            }
+        }
+    });
+    Snapshot {
+        devices: devices
+            .iter()
+            .zip(results)
+            .map(|((room, name, _), polled)| {
+                (
+                    room.to_string(),
+                    name.to_string(),
+                    polled.unwrap_or(Polled::TimedOut),
+                )
+            })
+            .collect(),
+    }
+}
+
+fn query(device: &SmartDevice, end: Instant, ask: Ask) -> Polled {
+    if Instant::now() >= end {
+        return Polled::TimedOut;
+    }
+    let SmartDevice::SmartSocket(socket) = device else {
+        return device.state().into();
+    };
+    let cached = match ask {
+        Ask::Always => None,
+        Ask::Stale => socket.fresh_status(),
+        Ask::Uncached => socket.max_age().and(socket.cached_status()),
+    };
+    if let Some(cached) = cached {
+        return Polled::Cached(cached.value.into(), cached.age);
+    }
+    socket.refresh_before(end).map(DeviceState::from).into()
+}
+
+// Keeps socket caches up to date by refreshing the home in the background. Every refresh
+// reads the home until its deadline at most, the interval or REPORT_DEADLINE if that
+// is shorter.
+pub struct Refresher {
+    finished: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Refresher {
+    pub fn start(home: Arc<RwLock<Home>>, interval: Duration) -> Refresher {
+        let finished = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let finished = finished.clone();
+            move || {
+                while !finished.load(Ordering::SeqCst) {
+                    let started = Instant::now();
+                    read(&home).refresh(interval.min(REPORT_DEADLINE));
+                    while !finished.load(Ordering::SeqCst) && started.elapsed() < interval {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+            }
+        });
+        Refresher {
+            finished,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Refresher {
+    fn drop(&mut self) {
+        self.finished.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn read(home: &RwLock<Home>) -> RwLockReadGuard<'_, Home> {
+    match home.read() {
+        Ok(g) => g,
+        Err(poison_error) => poison_error.into_inner(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::SmartDeviceConnect;
+    use crate::emulator::{EmulatedSocket, EmulatedStream};
+    use crate::{Report, Room, SmartSocket, json::Json};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_poll_deadline() {
+        // Ignores the first connection, answers on the next one
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (_silent, _) = listener.accept().unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut socket = EmulatedSocket::new();
+            let mut command = [0u8];
+            while stream.read_exact(&mut command).is_ok() {
+                let response = socket.process_command(command[0].into());
+                stream.write_all(&response.to_bytes()).unwrap();
+            }
+        });
+
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        room.add_device("Fast", SmartSocket::new(EmulatedStream::new()).into());
+        room.add_device("Slow", SmartSocket::connect(address).unwrap().into());
+        home.add_room("Room", room);
+
+        let started = Instant::now();
+        let snapshot = home.poll(Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(matches!(
+            snapshot.get("Room", "Fast"),
+            Some(Polled::State(DeviceState::Socket { on: false, .. }))
+        ));
+        assert!(matches!(
+            snapshot.get("Room", "Slow"),
+            Some(Polled::TimedOut)
+        ));
+        assert_eq!(snapshot.timed_out(), 1);
+        assert!(
+            home.snapshot_report(&snapshot)
+                .contains("- Slow                : Error: no answer before the deadline\n")
+        );
+        assert!(
+            Json::snapshot(&home, &snapshot)
+                .to_string()
+                .contains(r#""name":"Slow","kind":"socket","error":"no answer before the deadline","timed_out":true"#)
+        );
+
+        // The late answer would be out of step, so the socket connects again
+        let slow = home
+            .get_device("Room", "Slow")
+            .unwrap()
+            .as_socket()
+            .unwrap();
+        assert_eq!(slow.timeout(), None);
+        assert!(!slow.is_on().unwrap());
+        assert!(home.report().contains("- Slow                : On: false"));
+        drop(home);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_poll_busy_socket() {
+        // Accepts but answers nothing, the connection is closed after a while
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(1500));
+            drop(stream);
+        });
+
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        room.add_device("Busy", SmartSocket::connect(address).unwrap().into());
+        home.add_room("Room", room);
+        let busy = home
+            .find_device("Room", "Busy")
+            .unwrap()
+            .as_socket()
+            .unwrap();
+
+        thread::scope(|scope| {
+            // Holds the connection while waiting for the answer without a timeout
+            let waiting = scope.spawn(|| busy.is_on());
+            thread::sleep(Duration::from_millis(100));
+            let started = Instant::now();
+            let snapshot = home.poll(Duration::from_millis(200));
+            assert!(started.elapsed() < Duration::from_millis(700));
+            assert!(matches!(
+                snapshot.get("Room", "Busy"),
+                Some(Polled::TimedOut)
+            ));
+            assert!(waiting.join().unwrap().is_err());
+        });
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_poll_cache() {
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        let socket = SmartSocket::new(EmulatedStream::new());
+        socket.set_max_age(Some(Duration::from_secs(60)));
+        room.add_device("Socket", socket.into());
+        home.add_room("Room", room);
+
+        let polled = home.poll(Duration::from_secs(1));
+        assert!(matches!(
+            polled.get("Room", "Socket"),
+            Some(Polled::State(_))
+        ));
+        let cached = home.poll(Duration::from_secs(1));
+        assert!(matches!(
+            cached.get("Room", "Socket"),
+            Some(Polled::Cached(DeviceState::Socket { on: false, .. }, _))
+        ));
+        assert!(
+            home.snapshot_report(&cached)
+                .contains("On: false\t Power: 0.00 (cached 0.0 s ago)")
+        );
+        assert!(
+            Json::snapshot(&home, &cached)
+                .to_string()
+                .contains(r#""age":"#)
+        );
+        let refreshed = home.refresh(Duration::from_secs(1));
+        assert!(matches!(
+            refreshed.get("Room", "Socket"),
+            Some(Polled::State(_))
+        ));
+
+        let socket = home
+            .find_device("Room", "Socket")
+            .unwrap()
+            .as_socket()
+            .unwrap();
+        socket.set_max_age(Some(Duration::ZERO));
+        thread::sleep(Duration::from_millis(5));
+        assert!(matches!(
+            home.cached(Duration::from_secs(1)).get("Room", "Socket"),
+            Some(Polled::Cached(_, age)) if *age >= Duration::from_millis(5)
+        ));
+        socket.set_max_age(None);
+        assert!(matches!(
+            home.cached(Duration::from_secs(1)).get("Room", "Socket"),
+            Some(Polled::State(_))
+        ));
+    }
+
+    #[test]
+    fn test_refresher() {
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        room.add_device("Socket", SmartSocket::new(EmulatedStream::new()).into());
+        home.add_room("Room", room);
+        let home = Arc::new(RwLock::new(home));
+
+        let refresher = Refresher::start(home.clone(), Duration::from_millis(20));
+        thread::sleep(Duration::from_millis(100));
+        drop(refresher);
+        let home = read(&home);
+        let socket = home
+            .find_device("Room", "Socket")
+            .unwrap()
+            .as_socket()
+            .unwrap();
+        assert!(socket.cached_status().unwrap().age < Duration::from_millis(100));
+    }
+}
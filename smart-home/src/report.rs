@@ -1,8 +1,13 @@
 use crate::{
+    DeviceState,
     SmartDevice,
     //SmartDevice,
-    devices::{smartsocket::SmartSocket, termo::SmartThermometer},
+    devices::{
+        smartsocket::{SmartSocket, SocketStatus},
+        termo::SmartThermometer,
+    },
     homes::Home,
+    polling::{Polled, REPORT_DEADLINE, Snapshot},
     rooms::Room,
 };
 
@@ -12,17 +17,18 @@ pub trait Report {
 
 impl Report for SmartThermometer {
     fn report(&self) -> String {
-        format!("Temperature: {:.2}", self.get_temperature())
+        state_report(&DeviceState::Thermometer {
+            temperature: self.get_temperature(),
+        })
     }
 }
 
 impl Report for SmartSocket {
     fn report(&self) -> String {
-        format!(
-            "On: {}\t Power: {:.2}",
-            self.is_on().expect("Error reading socket for report"),
-            self.get_power().expect("Error reading socket for report")
-        )
+        match self.status() {
+            Ok(SocketStatus { on, power }) => state_report(&DeviceState::Socket { on, power }),
+            Err(err) => format!("Error: {err}"),
+        }
     }
 }
 
@@ -35,25 +41,195 @@ impl Report for SmartDevice {
     }
 }
 
-impl Report for Room {
+impl Report for Polled {
     fn report(&self) -> String {
-        let mut report = "".to_string();
-        for (name, device) in self.into_iter() {
-            report.push_str(&format!("- {:20}: {}\n", name, device.report()));
+        match self {
+            Polled::State(state) => state_report(state),
+            Polled::Cached(state, age) => {
+                format!(
+                    "{} (cached {:.1} s ago)",
+                    state_report(state),
+                    age.as_secs_f32()
+                )
+            }
+            Polled::Failed(err) => format!("Error: {err}"),
+            Polled::TimedOut => "Error: no answer before the deadline".to_string(),
         }
-        report
     }
 }
 
+fn state_report(state: &DeviceState) -> String {
+    match state {
+        DeviceState::Socket { on, power } => format!("On: {}\t Power: {:.2}", on, power),
+        DeviceState::Thermometer { temperature } => format!("Temperature: {:.2}", temperature),
+    }
+}
+
+impl Report for Room {
+    fn report(&self) -> String {
+        room_report(
+            self,
+            self.into_iter()
+                .map(|(name, device)| (name, device.report())),
+        )
+    }
+}
+
+// Devices are polled in parallel, one slow device delays the report by the deadline at most.
 impl Report for Home {
     fn report(&self) -> String {
-        let mut report = format!("Home: {}\n", self.name);
-        for (name, room) in self.into_iter() {
-            report.push_str("***********\n");
-            report.push_str(&format!("Room: {}\n", name));
-            report.push_str(&room.report());
+        self.snapshot_report(&self.poll(REPORT_DEADLINE))
+    }
+}
+
+fn room_report<'a>(room: &Room, devices: impl Iterator<Item = (&'a String, String)>) -> String {
+    let mut report = "".to_string();
+    for (name, device_report) in devices {
+        report.push_str(&format!("- {:20}: {}", name, device_report));
+        match room.device_info(name) {
+            Some(info) if !info.is_empty() => report.push_str(&format!(" ({info})\n")),
+            _ => report.push('\n'),
+        }
+    }
+    report
+}
+
+fn home_report<'a>(
+    name: &str,
+    rooms: impl Iterator<Item = (&'a String, &'a Room, String)>,
+) -> String {
+    let mut report = format!("Home: {}\n", name);
+    for (name, room, room_report) in rooms {
+        report.push_str("***********\n");
+        report.push_str(&format!("Room: {}", name));
+        if !room.info.is_empty() {
+            report.push_str(&format!(" ({})", room.info));
+        }
+        report.push('\n');
+        report.push_str(&room_report);
+    }
+    report
+}
+
+// Device lines of a room taken from the snapshot.
+fn snapshot_room_report(name: &str, room: &Room, snapshot: &Snapshot, sorted: bool) -> String {
+    let mut devices: Vec<&String> = room.into_iter().map(|(device, _)| device).collect();
+    if sorted {
+        devices.sort();
+    }
+    room_report(
+        room,
+        devices.into_iter().map(|device| {
+            let report = snapshot
+                .get(name, device)
+                .map_or("Error: not polled".to_string(), Report::report);
+            (device, report)
+        }),
+    )
+}
+
+impl Home {
+    // Report of the states in a snapshot from `Home::poll`.
+    pub fn snapshot_report(&self, snapshot: &Snapshot) -> String {
+        home_report(
+            &self.name,
+            self.into_iter().map(|(name, room)| {
+                (
+                    name,
+                    room,
+                    snapshot_room_report(name, room, snapshot, false),
+                )
+            }),
+        )
+    }
+}
+
+// Reports with devices and rooms sorted by name instead of their own order.
+impl Room {
+    pub fn sorted_report(&self) -> String {
+        let mut devices: Vec<_> = self.into_iter().collect();
+        devices.sort_by_key(|(name, _)| *name);
+        room_report(
+            self,
+            devices
+                .into_iter()
+                .map(|(name, device)| (name, device.report())),
+        )
+    }
+}
+
+impl Home {
+    pub fn sorted_report(&self) -> String {
+        self.sorted_snapshot_report(&self.poll(REPORT_DEADLINE))
+    }
+
+    pub fn sorted_snapshot_report(&self, snapshot: &Snapshot) -> String {
+        let mut rooms: Vec<_> = self.into_iter().collect();
+        rooms.sort_by_key(|(name, _)| *name);
+        home_report(
+            &self.name,
+            rooms
+                .into_iter()
+                .map(|(name, room)| (name, room, snapshot_room_report(name, room, snapshot, true))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::EmulatedStream;
+    use crate::metadata::DeviceInfo;
+
+    fn home() -> Home {
+        let mut home = Home::new("Test");
+        for room_name in ["Kitchen", "Bathroom"] {
+            let mut room = Room::default();
+            for name in ["Lamp", "Heater"] {
+                room.add_device(name, SmartSocket::new(EmulatedStream::new()).into());
+            }
+            home.add_room(room_name, room);
         }
+        home
+    }
+
+    fn names(report: &str) -> Vec<&str> {
         report
+            .lines()
+            .filter_map(|line| line.strip_prefix("Room: ").or(line.strip_prefix("- ")))
+            .map(|line| line.split_whitespace().next().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn test_report_order() {
+        let home = home();
+        let report = home.report();
+        assert_eq!(report, home.report());
+        assert_eq!(
+            names(&report),
+            ["Kitchen", "Lamp", "Heater", "Bathroom", "Lamp", "Heater"]
+        );
+        assert_eq!(
+            names(&home.sorted_report()),
+            ["Bathroom", "Heater", "Lamp", "Kitchen", "Heater", "Lamp"]
+        );
+        assert!(report.contains("- Lamp                : On: false\t Power: 0.00\n"));
+    }
+
+    #[test]
+    fn test_report_info() {
+        let mut home = home();
+        let room = home.get_room_mut("Kitchen").unwrap();
+        room.info.set("floor", "1").unwrap();
+        room.info.set("area", "12").unwrap();
+        let mut info = DeviceInfo::default();
+        info.set("location", "above the sink").unwrap();
+        room.set_device_info("Lamp", info).unwrap();
+        let report = home.report();
+        assert!(report.contains("Room: Kitchen (floor 1, 12 m²)\n"));
+        assert!(report.contains("Power: 0.00 (above the sink)\n"));
+        assert!(report.contains("Room: Bathroom\n"));
     }
 }
 
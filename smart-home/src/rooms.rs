@@ -1,49 +1,343 @@
-use crate::SmartDevice;
-use std::collections::HashMap;
+use crate::events::{DeviceEvent, Notifier, Observable, RoomEvent, SubscriptionId};
+use crate::metadata::{DeviceInfo, RoomInfo};
+use crate::{SmartDevice, SmartHomeError};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Weak};
+// Devices are kept in insertion order unless moved or sorted.
 #[derive(Debug, Default)]
 pub struct Room {
-    devices: HashMap<String, SmartDevice>,
+    devices: Vec<(String, SmartDevice)>,
+    notifier: Arc<Notifier<RoomEvent>>,
+    forwarders: HashMap<String, SubscriptionId>,
+    tags: HashMap<String, BTreeSet<String>>,
+    device_info: HashMap<String, DeviceInfo>,
+    pub info: RoomInfo,
 }
 
 impl Room {
-    pub fn new_with_devices(devices: HashMap<String, SmartDevice>) -> Room {
-        Room { devices }
+    pub fn new_with_devices(devices: impl IntoIterator<Item = (String, SmartDevice)>) -> Room {
+        let mut room = Room::default();
+        for (name, device) in devices {
+            room.add_device(name, device);
+        }
+        room
+    }
+    fn position(&self, name: &str) -> Option<usize> {
+        self.devices
+            .iter()
+            .position(|(device_name, _)| device_name == name)
     }
     pub fn get_device(&self, name: &str) -> Option<&SmartDevice> {
-        self.devices.get(name)
+        self.position(name).map(|i| &self.devices[i].1)
     }
 
     pub fn get_device_mut(&mut self, name: &str) -> Option<&mut SmartDevice> {
-        self.devices.get_mut(name)
+        self.position(name).map(|i| &mut self.devices[i].1)
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut SmartDevice)> {
+        self.devices
+            .iter_mut()
+            .map(|(name, device)| (&*name, device))
+    }
+    pub fn device_names(&self) -> impl Iterator<Item = &str> {
+        self.devices.iter().map(|(name, _)| name.as_str())
     }
-    pub fn add_device<T>(&mut self, name: T, device: SmartDevice)
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+    // Device with an existing name is replaced in its place and returned, tags and
+    // metadata of the old device are dropped.
+    pub fn add_device<T>(&mut self, name: T, device: SmartDevice) -> Option<SmartDevice>
     where
         T: Into<String>,
     {
-        self.devices.insert(name.into(), device);
+        let name = name.into();
+        let forwarder = forward(&device, &self.notifier, name.clone());
+        if let Some(old) = self.forwarders.insert(name.clone(), forwarder)
+            && let Some(i) = self.position(&name)
+        {
+            self.tags.remove(&name);
+            self.device_info.remove(&name);
+            let (_, old_device) = std::mem::replace(&mut self.devices[i], (name, device));
+            old_device.unsubscribe(old);
+            return Some(old_device);
+        }
+        self.devices.push((name, device));
+        None
+    }
+    pub fn try_add_device<T>(&mut self, name: T, device: SmartDevice) -> Result<(), SmartHomeError>
+    where
+        T: Into<String>,
+    {
+        let name = name.into();
+        if self.position(&name).is_some() {
+            return Err(SmartHomeError::AlreadyExists(name));
+        }
+        self.add_device(name, device);
+        Ok(())
+    }
+    pub fn entry(&mut self, name: impl Into<String>) -> Entry<'_, SmartDevice> {
+        Entry::new(self, name.into())
+    }
+    pub fn remove_device(&mut self, name: &str) -> Option<SmartDevice> {
+        self.take_device(name).map(|detached| detached.device)
+    }
+    // Removes the device together with its tags and metadata.
+    pub(crate) fn take_device(&mut self, name: &str) -> Option<Detached> {
+        let i = self.position(name)?;
+        let (_, device) = self.devices.remove(i);
+        if let Some(forwarder) = self.forwarders.remove(name) {
+            device.unsubscribe(forwarder);
+        }
+        Some(Detached {
+            device,
+            tags: self.tags.remove(name).unwrap_or_default(),
+            info: self.device_info.remove(name),
+        })
     }
-    pub fn remove_device(&mut self, name: &str) {
-        self.devices.remove(name);
+    // Adds a device taken from a room, the name must be free.
+    pub(crate) fn put_device(&mut self, name: &str, detached: Detached) {
+        self.add_device(name, detached.device);
+        if !detached.tags.is_empty() {
+            self.tags.insert(name.to_string(), detached.tags);
+        }
+        if let Some(info) = detached.info {
+            self.device_info.insert(name.to_string(), info);
+        }
+    }
+    // Keeps the connection, position, tags and metadata of the device. Listeners of the
+    // room get Removed under the old name and Added under the new one.
+    pub fn rename_device(&mut self, name: &str, new_name: &str) -> Result<(), SmartHomeError> {
+        let i = self
+            .position(name)
+            .ok_or(SmartHomeError::DeviceNotFound(name.to_string()))?;
+        if new_name.trim().is_empty() {
+            return Err(SmartHomeError::ValidationError(
+                "device name can not be empty".to_string(),
+            ));
+        }
+        if name == new_name {
+            return Ok(());
+        }
+        if self.position(new_name).is_some() {
+            return Err(SmartHomeError::AlreadyExists(new_name.to_string()));
+        }
+        self.devices[i].0 = new_name.to_string();
+        let device = &self.devices[i].1;
+        if let Some(forwarder) = self.forwarders.remove(name) {
+            device.unsubscribe(forwarder);
+        }
+        let forwarder = forward(device, &self.notifier, new_name.to_string());
+        self.forwarders.insert(new_name.to_string(), forwarder);
+        if let Some(tags) = self.tags.remove(name) {
+            self.tags.insert(new_name.to_string(), tags);
+        }
+        if let Some(info) = self.device_info.remove(name) {
+            self.device_info.insert(new_name.to_string(), info);
+        }
+        self.emit(name, DeviceEvent::Removed);
+        self.emit(new_name, DeviceEvent::Added);
+        Ok(())
+    }
+    pub(crate) fn emit(&self, device: &str, event: DeviceEvent) {
+        self.notifier.emit(&RoomEvent {
+            device: device.to_string(),
+            event,
+        });
+    }
+    // Moves the device to `index`, indexes past the end move it to the end.
+    pub fn move_device(&mut self, name: &str, index: usize) -> Result<(), SmartHomeError> {
+        let from = self
+            .position(name)
+            .ok_or(SmartHomeError::DeviceNotFound(name.to_string()))?;
+        let device = self.devices.remove(from);
+        self.devices.insert(index.min(self.devices.len()), device);
+        Ok(())
+    }
+    pub fn sort_devices(&mut self) {
+        self.sort_devices_by(|a, b| a.cmp(b));
+    }
+    pub fn sort_devices_by(&mut self, mut compare: impl FnMut(&str, &str) -> Ordering) {
+        self.devices.sort_by(|(a, _), (b, _)| compare(a, b));
     }
 }
 
+impl Room {
+    pub fn tag_device(&mut self, name: &str, tag: impl Into<String>) -> Result<(), SmartHomeError> {
+        if self.position(name).is_none() {
+            return Err(SmartHomeError::DeviceNotFound(name.to_string()));
+        }
+        self.tags
+            .entry(name.to_string())
+            .or_default()
+            .insert(tag.into());
+        Ok(())
+    }
+    pub fn untag_device(&mut self, name: &str, tag: &str) -> bool {
+        self.tags.get_mut(name).is_some_and(|tags| tags.remove(tag))
+    }
+    pub fn device_tags(&self, name: &str) -> impl Iterator<Item = &String> {
+        self.tags.get(name).into_iter().flatten()
+    }
+    pub fn has_tag(&self, name: &str, tag: &str) -> bool {
+        self.tags.get(name).is_some_and(|tags| tags.contains(tag))
+    }
+}
+
+impl Room {
+    pub fn device_info(&self, name: &str) -> Option<&DeviceInfo> {
+        self.device_info.get(name)
+    }
+    // Returns the replaced metadata.
+    pub fn set_device_info(
+        &mut self,
+        name: &str,
+        info: DeviceInfo,
+    ) -> Result<Option<DeviceInfo>, SmartHomeError> {
+        if self.position(name).is_none() {
+            return Err(SmartHomeError::DeviceNotFound(name.to_string()));
+        }
+        Ok(self.device_info.insert(name.to_string(), info))
+    }
+}
+
+// Device taken out of a room with everything the room keeps about it.
+#[derive(Debug)]
+pub(crate) struct Detached {
+    device: SmartDevice,
+    tags: BTreeSet<String>,
+    info: Option<DeviceInfo>,
+}
+
+impl Observable for Room {
+    type Event = RoomEvent;
+    fn notifier(&self) -> &Notifier<RoomEvent> {
+        &self.notifier
+    }
+}
+
+// Re-emits events of the device as events of the room while the room exists.
+fn forward(device: &SmartDevice, room: &Arc<Notifier<RoomEvent>>, name: String) -> SubscriptionId {
+    let room: Weak<Notifier<RoomEvent>> = Arc::downgrade(room);
+    device.notifier().listen(move |event| match room.upgrade() {
+        Some(room) => {
+            room.emit(&RoomEvent {
+                device: name.clone(),
+                event: *event,
+            });
+            true
+        }
+        None => false,
+    })
+}
+
 #[macro_export]
 macro_rules! room {
     ($(($name: expr, $device: ty, $ip: expr)), +) => {
         {
             let room = Room::new_with_devices( [
             $(($name.to_string(), SmartDevice::from(<$device>::connect($ip).expect("Failed to connect"))),
-            )+].into());
+            )+]);
             room
         }
     };
 }
 
+type Named<T> = (String, T);
+
+// Iterator over (name, value) pairs of an ordered list, shared by rooms and homes.
+pub struct Iter<'a, T>(std::slice::Iter<'a, Named<T>>);
+
+impl<'a, T> Iter<'a, T> {
+    pub(crate) fn new(entries: &'a [Named<T>]) -> Self {
+        Iter(entries.iter())
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a String, &'a T);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(name, value)| (name, value))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<T> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(name, value)| (name, value))
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+// Ordered list of named values which an `Entry` can insert into.
+pub(crate) trait Entries<T> {
+    fn entries(&mut self) -> &mut Vec<Named<T>>;
+    fn insert(&mut self, name: String, value: T);
+}
+
+impl Entries<SmartDevice> for Room {
+    fn entries(&mut self) -> &mut Vec<Named<SmartDevice>> {
+        &mut self.devices
+    }
+    fn insert(&mut self, name: String, value: SmartDevice) {
+        self.add_device(name, value);
+    }
+}
+
+// Place of a named device in a room or of a room in a home, for inserting only when the
+// name is free.
+pub struct Entry<'a, T> {
+    owner: &'a mut dyn Entries<T>,
+    name: String,
+}
+
+impl<'a, T> Entry<'a, T> {
+    pub(crate) fn new(owner: &'a mut dyn Entries<T>, name: String) -> Self {
+        Entry { owner, name }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    fn position(&mut self) -> Option<usize> {
+        let name = &self.name;
+        self.owner.entries().iter().position(|(n, _)| n == name)
+    }
+    pub fn is_occupied(&mut self) -> bool {
+        self.position().is_some()
+    }
+    pub fn and_modify(mut self, f: impl FnOnce(&mut T)) -> Self {
+        if let Some(i) = self.position() {
+            f(&mut self.owner.entries()[i].1);
+        }
+        self
+    }
+    pub fn or_insert(self, value: T) -> &'a mut T {
+        self.or_insert_with(|| value)
+    }
+    pub fn or_insert_with(mut self, f: impl FnOnce() -> T) -> &'a mut T {
+        let i = match self.position() {
+            Some(i) => i,
+            None => {
+                self.owner.insert(self.name, f());
+                self.owner.entries().len() - 1
+            }
+        };
+        &mut self.owner.entries()[i].1
+    }
+}
+
 impl<'a> IntoIterator for &'a Room {
     type Item = (&'a String, &'a SmartDevice);
-    type IntoIter = std::collections::hash_map::Iter<'a, String, SmartDevice>;
+    type IntoIter = Iter<'a, SmartDevice>;
     fn into_iter(self) -> Self::IntoIter {
-        self.devices.iter()
+        Iter::new(&self.devices)
     }
 }
 
@@ -51,7 +345,9 @@ impl<'a> IntoIterator for &'a Room {
 mod tests {
     use std::io::Cursor;
 
-    use crate::SmartSocket;
+    use crate::emulator::EmulatedSensor;
+    use crate::events::DeviceEvent;
+    use crate::{SmartSocket, SmartThermometer};
 
     use super::*;
 
@@ -59,7 +355,7 @@ mod tests {
     fn test_new_room() {
         let room = Room::default();
         assert!(room.devices.is_empty());
-        assert!(room.devices.keys().count() == 0);
+        assert!(room.device_names().count() == 0);
     }
 
     #[test]
@@ -73,7 +369,7 @@ mod tests {
         let room = Room::new_with_devices(devices);
 
         assert_eq!(room.devices.len(), 1);
-        assert!(room.devices.contains_key("Socket"));
+        assert!(room.get_device("Socket").is_some());
     }
 
     #[test]
@@ -102,7 +398,26 @@ mod tests {
         let mut room = Room::default();
         let device = SmartDevice::from(SmartSocket::new(Cursor::new(Vec::new())));
         room.add_device("Socket", device);
-        assert!(room.devices.contains_key("Socket"));
+        assert!(room.get_device("Socket").is_some());
+    }
+
+    #[test]
+    fn test_room_events() {
+        let mut room = Room::default();
+        let device = SmartDevice::from(SmartSocket::new(Cursor::new(Vec::new())));
+        room.add_device("Socket", device);
+        let events = room.subscribe_channel();
+        if let Some(SmartDevice::SmartSocket(socket)) = room.get_device("Socket") {
+            assert!(socket.is_on().is_err());
+        }
+        assert_eq!(
+            events.try_recv(),
+            Ok(RoomEvent {
+                device: "Socket".to_string(),
+                event: DeviceEvent::DeviceOffline
+            })
+        );
+        assert!(events.try_recv().is_err());
     }
 
     #[test]
@@ -110,8 +425,185 @@ mod tests {
         let mut room = Room::default();
         let device = SmartSocket::new(Cursor::new(Vec::new())).into();
         room.add_device("Socket", device);
-        assert!(room.devices.contains_key("Socket"));
+        assert!(room.get_device("Socket").is_some());
         room.remove_device("Socket");
-        assert!(!room.devices.contains_key("Socket"));
+        assert!(room.get_device("Socket").is_none());
+    }
+
+    #[test]
+    fn test_replaced_and_removed() {
+        let mut room = Room::default();
+        let socket = || SmartDevice::from(SmartSocket::new(Cursor::new(Vec::new())));
+        assert!(room.add_device("Lamp", socket()).is_none());
+        let thermometer = SmartThermometer::new(EmulatedSensor::new(20.));
+        let replaced = room.add_device("Lamp", thermometer.into());
+        assert!(matches!(replaced, Some(SmartDevice::SmartSocket(_))));
+        assert!(matches!(
+            room.try_add_device("Lamp", socket()),
+            Err(SmartHomeError::AlreadyExists(name)) if name == "Lamp"
+        ));
+        assert!(matches!(
+            room.get_device("Lamp"),
+            Some(SmartDevice::SmartThermometer(_))
+        ));
+        room.try_add_device("Kettle", socket()).unwrap();
+        assert!(matches!(
+            room.remove_device("Lamp"),
+            Some(SmartDevice::SmartThermometer(_))
+        ));
+        assert!(room.remove_device("Lamp").is_none());
+    }
+
+    #[test]
+    fn test_replace_drops_tags_and_info() {
+        let mut room = Room::default();
+        let socket = || SmartDevice::from(SmartSocket::new(Cursor::new(Vec::new())));
+        room.add_device("Heater", socket());
+        room.tag_device("Heater", "heaters").unwrap();
+        let info = DeviceInfo {
+            model: Some("H-2000".to_string()),
+            ..Default::default()
+        };
+        room.set_device_info("Heater", info).unwrap();
+
+        assert!(room.add_device("Heater", socket()).is_some());
+        assert_eq!(room.device_tags("Heater").count(), 0);
+        assert_eq!(room.device_info("Heater"), None);
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut room = Room::default();
+        let socket = || SmartDevice::from(SmartSocket::new(Cursor::new(Vec::new())));
+        let mut entry = room.entry("Lamp");
+        assert!(!entry.is_occupied());
+        assert_eq!(entry.name(), "Lamp");
+        entry.or_insert_with(socket);
+        room.entry("Kettle").or_insert(socket());
+        let mut modified = false;
+        let device = room
+            .entry("Lamp")
+            .and_modify(|_| modified = true)
+            .or_insert_with(|| panic!("Lamp is already there"));
+        assert!(matches!(device, SmartDevice::SmartSocket(_)));
+        assert!(modified);
+        assert_eq!(room.device_names().collect::<Vec<_>>(), ["Lamp", "Kettle"]);
+
+        // Inserted devices report to the room like added ones
+        let events = room.subscribe_channel();
+        let kettle = room.get_device("Kettle").unwrap().as_socket().unwrap();
+        assert!(kettle.is_on().is_err());
+        assert_eq!(events.try_recv().unwrap().device, "Kettle");
+    }
+
+    #[test]
+    fn test_rename_device() {
+        let mut room = Room::default();
+        for name in ["Lamp", "Heater", "Kettle"] {
+            room.add_device(name, SmartSocket::new(Cursor::new(Vec::new())).into());
+        }
+        room.tag_device("Heater", "heaters").unwrap();
+        let events = room.subscribe_channel();
+        room.rename_device("Heater", "Radiator").unwrap();
+        let names: Vec<&str> = room.device_names().collect();
+        assert_eq!(names, ["Lamp", "Radiator", "Kettle"]);
+        assert!(room.has_tag("Radiator", "heaters"));
+        assert_eq!(room.device_tags("Heater").count(), 0);
+        let event = |device: &str, event| RoomEvent {
+            device: device.to_string(),
+            event,
+        };
+        assert_eq!(events.try_recv(), Ok(event("Heater", DeviceEvent::Removed)));
+        assert_eq!(events.try_recv(), Ok(event("Radiator", DeviceEvent::Added)));
+
+        // The device still reports, under the new name only
+        let socket = room.get_device("Radiator").unwrap().as_socket().unwrap();
+        assert!(socket.is_on().is_err());
+        assert_eq!(
+            events.try_recv(),
+            Ok(event("Radiator", DeviceEvent::DeviceOffline))
+        );
+        assert!(events.try_recv().is_err());
+
+        assert!(matches!(
+            room.rename_device("Lamp", "Kettle"),
+            Err(SmartHomeError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            room.rename_device("Lamp", " "),
+            Err(SmartHomeError::ValidationError(_))
+        ));
+        assert!(matches!(
+            room.rename_device("Heater", "Boiler"),
+            Err(SmartHomeError::DeviceNotFound(_))
+        ));
+        assert!(room.get_device("Lamp").is_some());
+    }
+
+    #[test]
+    fn test_device_order() {
+        let mut room = Room::default();
+        for name in ["Lamp", "Heater", "Kettle"] {
+            room.add_device(name, SmartSocket::new(Cursor::new(Vec::new())).into());
+        }
+        let names = |room: &Room| room.into_iter().map(|(n, _)| n.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&room), ["Lamp", "Heater", "Kettle"]);
+
+        // Replaced device keeps its place
+        room.add_device("Heater", SmartSocket::new(Cursor::new(Vec::new())).into());
+        assert_eq!(names(&room), ["Lamp", "Heater", "Kettle"]);
+
+        room.move_device("Kettle", 0).unwrap();
+        assert_eq!(names(&room), ["Kettle", "Lamp", "Heater"]);
+        room.move_device("Kettle", 10).unwrap();
+        assert_eq!(names(&room), ["Lamp", "Heater", "Kettle"]);
+        assert!(room.move_device("Fan", 0).is_err());
+
+        room.sort_devices();
+        assert_eq!(names(&room), ["Heater", "Kettle", "Lamp"]);
+        room.sort_devices_by(|a, b| b.cmp(a));
+        assert_eq!(
+            room.device_names().collect::<Vec<_>>(),
+            ["Lamp", "Kettle", "Heater"]
+        );
+        room.remove_device("Kettle");
+        assert_eq!(names(&room), ["Lamp", "Heater"]);
+    }
+
+    #[test]
+    fn test_device_tags() {
+        let mut room = Room::default();
+        let device = SmartSocket::new(Cursor::new(Vec::new())).into();
+        room.add_device("Heater", device);
+        room.tag_device("Heater", "heaters").unwrap();
+        room.tag_device("Heater", "critical").unwrap();
+        assert!(matches!(
+            room.tag_device("Lamp", "heaters"),
+            Err(SmartHomeError::DeviceNotFound(_))
+        ));
+        let tags: Vec<&String> = room.device_tags("Heater").collect();
+        assert_eq!(tags, ["critical", "heaters"]);
+        assert!(room.untag_device("Heater", "critical"));
+        assert!(!room.untag_device("Heater", "critical"));
+        assert!(room.has_tag("Heater", "heaters"));
+
+        room.remove_device("Heater");
+        assert_eq!(room.device_tags("Heater").count(), 0);
+    }
+
+    #[test]
+    fn test_device_info() {
+        let mut room = Room::default();
+        let device = SmartSocket::new(Cursor::new(Vec::new())).into();
+        room.add_device("Heater", device);
+        let info = DeviceInfo {
+            model: Some("H-2000".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(room.set_device_info("Heater", info.clone()).unwrap(), None);
+        assert_eq!(room.device_info("Heater"), Some(&info));
+        assert!(room.set_device_info("Lamp", info).is_err());
+        room.remove_device("Heater");
+        assert_eq!(room.device_info("Heater"), None);
     }
 }
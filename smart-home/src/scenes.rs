@@ -0,0 +1,348 @@
+use crate::automation::DeviceRef;
+use crate::config::{ConfigLine, ConfigLines, read_config, unknown_key};
+use crate::{Home, SmartDevice, SmartHomeError, SmartSocket};
+use std::{fmt, fs, path::Path, str::FromStr};
+
+// Scenes file has one section per scene with the wanted socket states:
+//
+//   [Away]
+//   Kitchen/Kettle = off
+//   Hall/Lamp = on
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Scene {
+    pub name: String,
+    pub sockets: Vec<(DeviceRef, bool)>,
+}
+
+impl Scene {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            sockets: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, device: DeviceRef, on: bool) -> Self {
+        self.sockets.push((device, on));
+        self
+    }
+
+    // Current state of every socket in the home. Sockets which can not be read are left
+    // out of the scene and returned with their errors.
+    pub fn capture(
+        name: impl Into<String>,
+        home: &Home,
+    ) -> (Scene, Vec<(DeviceRef, SmartHomeError)>) {
+        let mut scene = Scene::new(name);
+        let mut skipped = Vec::new();
+        for (room, name, device) in home.devices() {
+            if let SmartDevice::SmartSocket(socket) = device {
+                let device = DeviceRef::new(room, name);
+                match socket.is_on() {
+                    Ok(on) => scene.sockets.push((device, on)),
+                    Err(err) => skipped.push((device, err)),
+                }
+            }
+        }
+        (scene, skipped)
+    }
+
+    // Switches sockets which are not in the wanted state yet. With `rollback` nothing is
+    // switched unless every socket is found, and already switched sockets are restored
+    // when one of them fails.
+    pub fn apply(&self, home: &Home, rollback: bool) -> SceneReport {
+        let mut report = SceneReport {
+            scene: self.name.clone(),
+            results: Vec::new(),
+            rolled_back: false,
+        };
+        let mut sockets = Vec::new();
+        for (device, on) in &self.sockets {
+            let socket = home
+                .find_device(&device.room, &device.device)
+                .and_then(SmartDevice::as_socket);
+            let (socket, result) = match socket {
+                Ok(socket) => (Some(socket), Ok(())),
+                Err(err) => (None, Err(err)),
+            };
+            sockets.push(socket);
+            report.results.push(DeviceResult {
+                device: device.clone(),
+                on: *on,
+                previous: None,
+                result,
+                restored: None,
+            });
+        }
+        let missing: Vec<String> = self
+            .sockets
+            .iter()
+            .zip(&sockets)
+            .filter(|(_, socket)| socket.is_none())
+            .map(|((device, _), _)| device.to_string())
+            .collect();
+
+        for (result, socket) in report.results.iter_mut().zip(&sockets) {
+            let Some(socket) = socket else { continue };
+            result.result = if rollback && !missing.is_empty() {
+                Err(SmartHomeError::ValidationError(format!(
+                    "not applied, missing devices {}",
+                    missing.join(", ")
+                )))
+            } else {
+                socket.is_on().and_then(|previous| {
+                    result.previous = Some(previous);
+                    switch(socket, previous, result.on)
+                })
+            };
+        }
+
+        if rollback && missing.is_empty() && !report.is_success() {
+            report.rolled_back = true;
+            for (result, socket) in report.results.iter_mut().zip(&sockets) {
+                if let (Ok(()), Some(previous), Some(socket)) =
+                    (&result.result, result.previous, socket)
+                {
+                    result.restored = Some(switch(socket, result.on, previous));
+                }
+            }
+        }
+        report
+    }
+}
+
+fn switch(socket: &SmartSocket, current: bool, wanted: bool) -> Result<(), SmartHomeError> {
+    match (current, wanted) {
+        (false, true) => socket.turn_on(),
+        (true, false) => socket.turn_off(),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug)]
+pub struct DeviceResult {
+    pub device: DeviceRef,
+    pub on: bool,
+    // State before the scene was applied, None when it could not be read.
+    pub previous: Option<bool>,
+    pub result: Result<(), SmartHomeError>,
+    // Result of restoring the previous state on rollback.
+    pub restored: Option<Result<(), SmartHomeError>>,
+}
+
+#[derive(Debug)]
+pub struct SceneReport {
+    pub scene: String,
+    pub results: Vec<DeviceResult>,
+    pub rolled_back: bool,
+}
+
+impl SceneReport {
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|r| r.result.is_ok())
+    }
+}
+
+impl fmt::Display for SceneReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match (self.is_success(), self.rolled_back) {
+            (true, _) => "applied",
+            (false, true) => "failed, rolled back",
+            (false, false) => "partially applied",
+        };
+        write!(f, "Scene '{}' {outcome}", self.scene)?;
+        for result in &self.results {
+            let state = if result.on { "on" } else { "off" };
+            write!(f, "\n  {} {state}: ", result.device)?;
+            match &result.result {
+                Ok(()) => write!(f, "ok")?,
+                Err(err) => write!(f, "{err}")?,
+            }
+            match &result.restored {
+                Some(Ok(())) => write!(f, ", restored")?,
+                Some(Err(err)) => write!(f, ", restore failed: {err}")?,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SceneSet {
+    pub scenes: Vec<Scene>,
+}
+
+impl SceneSet {
+    pub fn load(path: impl AsRef<Path>) -> Result<SceneSet, SmartHomeError> {
+        read_config(path.as_ref())?.parse()
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SmartHomeError> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+    pub fn get(&self, name: &str) -> Option<&Scene> {
+        self.scenes.iter().find(|scene| scene.name == name)
+    }
+    // Replaces the scene with the same name.
+    pub fn insert(&mut self, scene: Scene) -> Option<Scene> {
+        match self.scenes.iter_mut().find(|s| s.name == scene.name) {
+            Some(existing) => Some(std::mem::replace(existing, scene)),
+            None => {
+                self.scenes.push(scene);
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Display for SceneSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, scene) in self.scenes.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}]", scene.name)?;
+            for (device, on) in &scene.sockets {
+                writeln!(f, "{device} = {}", if *on { "on" } else { "off" })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SceneSet {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut set = SceneSet::default();
+        for line in ConfigLines::new(s) {
+            match (line?, set.scenes.last_mut()) {
+                (ConfigLine::Section(name), _) => set.scenes.push(Scene::new(name)),
+                (ConfigLine::Value(number, key, _), None) => return Err(unknown_key(number, key)),
+                (ConfigLine::Value(number, key, value), Some(scene)) => {
+                    let on = match value {
+                        "on" => true,
+                        "off" => false,
+                        _ => {
+                            return Err(SmartHomeError::ConfigError(format!(
+                                "Line {number}: expected 'on' or 'off'"
+                            )));
+                        }
+                    };
+                    let device = key.parse().map_err(|_| unknown_key(number, key))?;
+                    scene.sockets.push((device, on));
+                }
+            }
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatedStream, socket_is_on};
+    use crate::{Room, SmartThermometer};
+    use std::io::Cursor;
+
+    const SCENES: &str = "
+[Away]
+Kitchen/Kettle = off
+Hall/Lamp = on
+
+[Night]
+Hall/Lamp = off
+";
+
+    fn home() -> Home {
+        let mut home = Home::new("Test");
+        let mut room = Room::default();
+        room.add_device("Kettle", SmartSocket::new(EmulatedStream::new()).into());
+        home.add_room("Kitchen", room);
+        let mut room = Room::default();
+        room.add_device("Lamp", SmartSocket::new(EmulatedStream::new()).into());
+        room.add_device("Broken", SmartSocket::new(Cursor::new(Vec::new())).into());
+        room.add_device(
+            "Thermometer",
+            SmartThermometer::new(crate::emulator::EmulatedSensor::new(20.)).into(),
+        );
+        home.add_room("Hall", room);
+        home
+    }
+
+    #[test]
+    fn test_parse_scenes() {
+        let set: SceneSet = SCENES.parse().unwrap();
+        assert_eq!(set.scenes.len(), 2);
+        assert_eq!(
+            set.get("Away").unwrap().sockets,
+            vec![
+                (DeviceRef::new("Kitchen", "Kettle"), false),
+                (DeviceRef::new("Hall", "Lamp"), true)
+            ]
+        );
+        let reparsed: SceneSet = set.to_string().parse().unwrap();
+        assert_eq!(reparsed, set);
+        assert!("[Bad]\nHall/Lamp = dim\n".parse::<SceneSet>().is_err());
+        assert!("[Bad]\nLamp = on\n".parse::<SceneSet>().is_err());
+    }
+
+    #[test]
+    fn test_apply_and_capture() {
+        let home = home();
+        let scene = Scene::new("Evening")
+            .with(DeviceRef::new("Kitchen", "Kettle"), true)
+            .with(DeviceRef::new("Hall", "Lamp"), true);
+        let report = scene.apply(&home, true);
+        assert!(report.is_success(), "{report}");
+        assert!(socket_is_on(&home, "Kitchen", "Kettle"));
+        assert_eq!(report.results[0].previous, Some(false));
+
+        // The broken socket can not be read and is skipped
+        let (captured, skipped) = Scene::capture("Evening", &home);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, DeviceRef::new("Hall", "Broken"));
+        assert_eq!(
+            captured.sockets,
+            vec![
+                (DeviceRef::new("Kitchen", "Kettle"), true),
+                (DeviceRef::new("Hall", "Lamp"), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rollback() {
+        let home = home();
+        let scene = Scene::new("Party")
+            .with(DeviceRef::new("Kitchen", "Kettle"), true)
+            .with(DeviceRef::new("Hall", "Broken"), true);
+        let report = scene.apply(&home, true);
+        assert!(!report.is_success());
+        assert!(report.rolled_back);
+        assert!(matches!(report.results[0].restored, Some(Ok(()))));
+        assert!(!socket_is_on(&home, "Kitchen", "Kettle"));
+
+        let report = scene.apply(&home, false);
+        assert!(!report.rolled_back);
+        assert!(socket_is_on(&home, "Kitchen", "Kettle"));
+        assert!(
+            report
+                .to_string()
+                .starts_with("Scene 'Party' partially applied")
+        );
+
+        // Missing devices stop the scene before anything is switched
+        let scene = Scene::new("Typo")
+            .with(DeviceRef::new("Kitchen", "Kettle"), false)
+            .with(DeviceRef::new("Hall", "Thermometer"), true);
+        let report = scene.apply(&home, true);
+        assert!(!report.is_success());
+        assert!(socket_is_on(&home, "Kitchen", "Kettle"));
+        assert!(matches!(
+            &report.results[0].result,
+            Err(SmartHomeError::ValidationError(msg)) if msg.ends_with("Hall/Thermometer")
+        ));
+    }
+}
@@ -0,0 +1,827 @@
+use crate::automation::{Action, Command};
+use crate::clock::{
+    Clock, Date, DateTime, SystemClock, TimeOfDay, UtcOffset, Weekday, civil_from_days,
+    format_duration, parse_duration, unix_seconds, weekday_from_days,
+};
+use crate::config::{offset_sections, read_config, unknown_key, with_line};
+use crate::{Home, SmartHomeError};
+use std::{
+    fmt, fs,
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+// Schedules file is an ini-like file with one section per schedule:
+//
+//   utc_offset = +03:00
+//
+//   [Morning kettle]
+//   when = weekdays 07:00
+//   do = Kitchen/Kettle on
+//   for = 30m
+//   missed = skip
+//   next = 2025-06-02 07:00
+//
+// `when` is one of `daily HH:MM`, `<days> HH:MM` (`weekdays`, `weekends`, `mon,wed`,
+// `mon-fri`), `cron <min> <hour> <day> <month> <weekday>` or `at YYYY-MM-DD HH:MM[:SS]`.
+// Action with `for` is reverted after the period by a one-shot `<name> (end)` schedule.
+// `next` keeps the pending run between restarts and is written by the scheduler.
+
+// Runs this late are treated as missed.
+const MISSED_AFTER: Duration = Duration::from_secs(60);
+// Cron expressions which never match (like `0 0 30 2 *`) stop searching after this.
+const SEARCH_DAYS: i64 = 366 * 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+    text: String,
+}
+
+impl CronExpr {
+    fn matches_day(&self, month: u8, day: u8, weekday: Weekday) -> bool {
+        if self.months & 1 << month == 0 {
+            return false;
+        }
+        let by_day = self.days & 1 << day != 0;
+        // Cron counts weekdays from Sunday
+        let by_weekday = self.weekdays & 1 << ((weekday.index() + 1) % 7) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => by_day || by_weekday,
+            _ => by_day && by_weekday,
+        }
+    }
+
+    fn times(&self) -> Vec<TimeOfDay> {
+        (0..24)
+            .filter(|hour| self.hours & 1 << hour != 0)
+            .flat_map(|hour| {
+                (0..60)
+                    .filter(|minute| self.minutes & 1 << minute != 0)
+                    .map(move |minute| TimeOfDay::new(hour, minute))
+            })
+            .collect()
+    }
+}
+
+// Parses a cron field like `*`, `5`, `1-5`, `*/15`, `0-30/10` or a comma separated list of them.
+fn parse_field(s: &str, min: u8, max: u8) -> Result<u64, SmartHomeError> {
+    let invalid = || SmartHomeError::ConfigError(format!("Invalid cron field '{s}'"));
+    let mut mask = 0;
+    for part in s.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (
+                from.parse().map_err(|_| invalid())?,
+                to.parse().map_err(|_| invalid())?,
+            ),
+            None => {
+                let value = range.parse().map_err(|_| invalid())?;
+                (value, if step > 1 { max } else { value })
+            }
+        };
+        if step == 0 || from < min || from > to || to > max {
+            return Err(invalid());
+        }
+        for value in (from..=to).step_by(step) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl fmt::Display for CronExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl FromStr for CronExpr {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(SmartHomeError::ConfigError(format!(
+                "Invalid cron expression '{s}', expected <minute> <hour> <day> <month> <weekday>"
+            )));
+        };
+        let mut weekday_mask = parse_field(weekdays, 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekday_mask & 1 << 7 != 0 {
+            weekday_mask = weekday_mask & !(1 << 7) | 1;
+        }
+        Ok(CronExpr {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_mask,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+            text: fields.join(" "),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum When {
+    Daily(TimeOfDay),
+    Weekly(Vec<Weekday>, TimeOfDay),
+    Cron(CronExpr),
+    At(SystemTime),
+}
+
+impl When {
+    // First occurrence strictly after `after`.
+    pub fn next_after(&self, after: SystemTime, utc_offset: UtcOffset) -> Option<SystemTime> {
+        match self {
+            When::Daily(time) => next_matching(after, utc_offset, |_, _, _| true, &[*time]),
+            When::Weekly(days, time) => next_matching(
+                after,
+                utc_offset,
+                |_, _, weekday| days.contains(&weekday),
+                &[*time],
+            ),
+            When::Cron(cron) => next_matching(
+                after,
+                utc_offset,
+                |month, day, weekday| cron.matches_day(month, day, weekday),
+                &cron.times(),
+            ),
+            When::At(at) => (*at > after).then_some(*at),
+        }
+    }
+
+    // One-shot schedules run even when created for the past.
+    fn first_run(&self, now: SystemTime, utc_offset: UtcOffset) -> Option<SystemTime> {
+        match self {
+            When::At(at) => Some(*at),
+            when => when.next_after(now, utc_offset),
+        }
+    }
+
+    pub(crate) fn format(&self, utc_offset: UtcOffset) -> String {
+        match self {
+            When::Daily(time) => format!("daily {time}"),
+            When::Weekly(days, time) => {
+                let days: Vec<String> = days.iter().map(ToString::to_string).collect();
+                format!("{} {time}", days.join(","))
+            }
+            When::Cron(cron) => format!("cron {cron}"),
+            When::At(at) => format!("at {}", format_time(*at, utc_offset)),
+        }
+    }
+
+    pub(crate) fn parse(s: &str, utc_offset: UtcOffset) -> Result<When, SmartHomeError> {
+        let s = s.trim();
+        let invalid = || SmartHomeError::ConfigError(format!("Invalid schedule '{s}'"));
+        let (kind, rest) = s.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        match kind {
+            "cron" => Ok(When::Cron(rest.parse()?)),
+            "at" => Ok(When::At(parse_time(rest, utc_offset)?)),
+            "daily" => Ok(When::Daily(rest.parse()?)),
+            "weekdays" => Ok(When::Weekly(Weekday::ALL[..5].to_vec(), rest.parse()?)),
+            "weekends" => Ok(When::Weekly(Weekday::ALL[5..].to_vec(), rest.parse()?)),
+            days => {
+                let mut weekdays = Vec::new();
+                for part in days.split(',') {
+                    match part.split_once('-') {
+                        Some((from, to)) => {
+                            let (from, to) = (from.parse::<Weekday>()?, to.parse::<Weekday>()?);
+                            if from > to {
+                                return Err(invalid());
+                            }
+                            weekdays.extend(&Weekday::ALL[from.index()..=to.index()]);
+                        }
+                        None => weekdays.push(part.parse().map_err(|_| invalid())?),
+                    }
+                }
+                weekdays.sort();
+                weekdays.dedup();
+                Ok(When::Weekly(weekdays, rest.parse()?))
+            }
+        }
+    }
+}
+
+fn next_matching(
+    after: SystemTime,
+    utc_offset: UtcOffset,
+    day_matches: impl Fn(u8, u8, Weekday) -> bool,
+    times: &[TimeOfDay],
+) -> Option<SystemTime> {
+    let first_day = (unix_seconds(after) + utc_offset.seconds()).div_euclid(86400);
+    for days in first_day..first_day + SEARCH_DAYS {
+        let (year, month, day) = civil_from_days(days);
+        if !day_matches(month, day, weekday_from_days(days)) {
+            continue;
+        }
+        for time in times {
+            let at = DateTime::to_system_time(year, month, day, *time, utc_offset);
+            if at > after {
+                return Some(at);
+            }
+        }
+    }
+    None
+}
+
+fn format_time(time: SystemTime, utc_offset: UtcOffset) -> String {
+    let date = DateTime::from_system_time(time, utc_offset);
+    let formatted = format!(
+        "{:04}-{:02}-{:02} {}",
+        date.year, date.month, date.day, date.time
+    );
+    match date.second {
+        0 => formatted,
+        second => format!("{formatted}:{second:02}"),
+    }
+}
+
+fn parse_time(s: &str, utc_offset: UtcOffset) -> Result<SystemTime, SmartHomeError> {
+    let invalid = || {
+        SmartHomeError::ConfigError(format!(
+            "Invalid time '{s}', expected YYYY-MM-DD HH:MM[:SS]"
+        ))
+    };
+    let (date, time) = s
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(invalid)?;
+    let date: Date = date.parse().map_err(|_| invalid())?;
+    let time = time.trim();
+    let (time, second) = match time.match_indices(':').nth(1) {
+        Some((i, _)) => (&time[..i], time[i + 1..].parse().map_err(|_| invalid())?),
+        None => (time, 0),
+    };
+    if second > 59 {
+        return Err(invalid());
+    }
+    let start =
+        DateTime::to_system_time(date.year, date.month, date.day, time.parse()?, utc_offset);
+    Ok(start + Duration::from_secs(second))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Missed {
+    // Run once after restart, however many runs were missed.
+    #[default]
+    Run,
+    Skip,
+}
+
+impl fmt::Display for Missed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Missed::Run => write!(f, "run"),
+            Missed::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+impl FromStr for Missed {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "run" => Ok(Missed::Run),
+            "skip" => Ok(Missed::Skip),
+            _ => Err(SmartHomeError::ConfigError(format!(
+                "Invalid missed runs policy '{s}', expected run or skip"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub name: String,
+    pub when: When,
+    pub action: Action,
+    // Action is reverted after this period.
+    pub duration: Option<Duration>,
+    pub missed: Missed,
+    // Pending run, None for a finished one-shot schedule.
+    pub next: Option<SystemTime>,
+}
+
+impl Schedule {
+    pub fn new(name: impl Into<String>, when: When, action: Action) -> Self {
+        Self {
+            name: name.into(),
+            when,
+            action,
+            duration: None,
+            missed: Missed::default(),
+            next: None,
+        }
+    }
+    pub fn lasting(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+    pub fn skip_missed(mut self) -> Self {
+        self.missed = Missed::Skip;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ScheduleSet {
+    pub utc_offset: UtcOffset,
+    pub schedules: Vec<Schedule>,
+}
+
+impl ScheduleSet {
+    pub fn load(path: impl AsRef<Path>) -> Result<ScheduleSet, SmartHomeError> {
+        read_config(path.as_ref())?.parse()
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SmartHomeError> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for ScheduleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "utc_offset = {}", self.utc_offset)?;
+        for schedule in &self.schedules {
+            writeln!(f)?;
+            writeln!(f, "[{}]", schedule.name)?;
+            writeln!(f, "when = {}", schedule.when.format(self.utc_offset))?;
+            writeln!(f, "do = {}", schedule.action)?;
+            if let Some(duration) = schedule.duration {
+                writeln!(f, "for = {}", format_duration(duration))?;
+            }
+            if schedule.missed != Missed::default() {
+                writeln!(f, "missed = {}", schedule.missed)?;
+            }
+            if let Some(next) = schedule.next {
+                writeln!(f, "next = {}", format_time(next, self.utc_offset))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ScheduleSet {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `at` and `next` times depend on utc_offset, so sections are parsed after it is known.
+        let (utc_offset, sections) = offset_sections(s)?;
+        let mut set = ScheduleSet {
+            utc_offset,
+            ..Default::default()
+        };
+
+        for (name, values) in sections {
+            let (mut when, mut action, mut duration, mut missed, mut next) =
+                (None, None, None, Missed::default(), None);
+            for (number, key, value) in values {
+                let at_line = |err| with_line(number, err);
+                match key {
+                    "when" => when = Some(When::parse(value, set.utc_offset).map_err(at_line)?),
+                    "do" => action = Some(value.parse().map_err(at_line)?),
+                    "for" => duration = Some(parse_duration(value).map_err(at_line)?),
+                    "missed" => missed = value.parse().map_err(at_line)?,
+                    "next" => next = Some(parse_time(value, set.utc_offset).map_err(at_line)?),
+                    _ => return Err(unknown_key(number, key)),
+                }
+            }
+            let (Some(when), Some(action)) = (when, action) else {
+                return Err(SmartHomeError::ConfigError(format!(
+                    "Schedule '{name}' needs both 'when' and 'do'"
+                )));
+            };
+            set.schedules.push(Schedule {
+                name,
+                when,
+                action,
+                duration,
+                missed,
+                next,
+            });
+        }
+        Ok(set)
+    }
+}
+
+#[derive(Debug)]
+pub enum Run {
+    Executed {
+        schedule: String,
+        action: Action,
+        scheduled: SystemTime,
+        // Run happened after a missed time, e.g. after restart.
+        late: bool,
+        result: Result<(), SmartHomeError>,
+    },
+    Skipped {
+        schedule: String,
+        action: Action,
+        scheduled: SystemTime,
+    },
+}
+
+impl fmt::Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Run::Executed {
+                schedule,
+                action,
+                late,
+                result,
+                ..
+            } => {
+                let late = if *late { " (missed)" } else { "" };
+                match result {
+                    Ok(()) => write!(f, "Schedule '{schedule}'{late}: {action}: ok"),
+                    Err(err) => write!(f, "Schedule '{schedule}'{late}: {action}: {err}"),
+                }
+            }
+            Run::Skipped {
+                schedule, action, ..
+            } => write!(f, "Schedule '{schedule}': missed {action}, skipped"),
+        }
+    }
+}
+
+fn revert(command: Command) -> Command {
+    match command {
+        Command::Switch => Command::Switch,
+        Command::On => Command::Off,
+        Command::Off => Command::On,
+    }
+}
+
+pub struct Scheduler<C: Clock = SystemClock> {
+    set: ScheduleSet,
+    clock: C,
+}
+
+impl Scheduler<SystemClock> {
+    pub fn new(set: ScheduleSet) -> Self {
+        Self::with_clock(set, SystemClock)
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SmartHomeError> {
+        Ok(Self::new(ScheduleSet::load(path)?))
+    }
+}
+
+impl<C: Clock> Scheduler<C> {
+    // Schedules without a pending run get the first one, already pending runs are kept.
+    pub fn with_clock(set: ScheduleSet, clock: C) -> Self {
+        let schedules = set.schedules;
+        let mut scheduler = Self {
+            set: ScheduleSet {
+                utc_offset: set.utc_offset,
+                schedules: Vec::new(),
+            },
+            clock,
+        };
+        for schedule in schedules {
+            scheduler.add(schedule);
+        }
+        scheduler
+    }
+
+    pub fn schedules(&self) -> &ScheduleSet {
+        &self.set
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SmartHomeError> {
+        self.set.save(path)
+    }
+
+    // Replaces the schedule with the same name.
+    pub fn add(&mut self, mut schedule: Schedule) -> Option<Schedule> {
+        if schedule.next.is_none() {
+            schedule.next = schedule
+                .when
+                .first_run(self.clock.now(), self.set.utc_offset);
+        }
+        let schedules = &mut self.set.schedules;
+        match schedules.iter_mut().find(|s| s.name == schedule.name) {
+            Some(existing) => Some(std::mem::replace(existing, schedule)),
+            None => {
+                schedules.push(schedule);
+                None
+            }
+        }
+    }
+
+    // One-shot action after `delay`.
+    pub fn add_timer(&mut self, name: impl Into<String>, action: Action, delay: Duration) {
+        let at = self.clock.now() + delay;
+        self.add(Schedule::new(name, When::At(at), action));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Schedule> {
+        let index = self.set.schedules.iter().position(|s| s.name == name)?;
+        Some(self.set.schedules.remove(index))
+    }
+
+    pub fn next_run(&self) -> Option<SystemTime> {
+        self.set.schedules.iter().filter_map(|s| s.next).min()
+    }
+
+    // Runs every due schedule once, in order of their scheduled time.
+    pub fn run_due(&mut self, home: &Home) -> Vec<Run> {
+        let now = self.clock.now();
+        let utc_offset = self.set.utc_offset;
+        let mut due: Vec<(SystemTime, usize)> = self
+            .set
+            .schedules
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.next.filter(|next| *next <= now).map(|next| (next, i)))
+            .collect();
+        due.sort();
+
+        let mut runs = Vec::new();
+        let mut reverts = Vec::new();
+        for (scheduled, i) in due {
+            let schedule = &mut self.set.schedules[i];
+            let late = now.duration_since(scheduled).unwrap_or_default() > MISSED_AFTER;
+            let window_passed = schedule.duration.is_some_and(|duration| {
+                scheduled
+                    .checked_add(duration)
+                    .is_some_and(|end| end <= now)
+            });
+            if late && (schedule.missed == Missed::Skip || window_passed) {
+                runs.push(Run::Skipped {
+                    schedule: schedule.name.clone(),
+                    action: schedule.action.clone(),
+                    scheduled,
+                });
+            } else {
+                let result = schedule.action.execute(home);
+                if let Some(end) = schedule
+                    .duration
+                    .and_then(|duration| scheduled.checked_add(duration))
+                {
+                    let action = Action::new(
+                        schedule.action.device.clone(),
+                        revert(schedule.action.command),
+                    );
+                    let name = format!("{} (end)", schedule.name);
+                    reverts.push(Schedule::new(name, When::At(end), action));
+                }
+                runs.push(Run::Executed {
+                    schedule: schedule.name.clone(),
+                    action: schedule.action.clone(),
+                    scheduled,
+                    late,
+                    result,
+                });
+            }
+            schedule.next = schedule.when.next_after(now, utc_offset);
+        }
+        self.set
+            .schedules
+            .retain(|s| s.next.is_some() || !matches!(s.when, When::At(_)));
+        for schedule in reverts {
+            self.add(schedule);
+        }
+        runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automation::DeviceRef;
+    use crate::clock::ManualClock;
+    use crate::emulator::{socket_home, socket_is_on};
+
+    const SCHEDULES: &str = "
+utc_offset = +03:00
+
+[Kettle]
+when = weekdays 07:00
+do = Kitchen/Kettle on
+for = 30m
+missed = skip
+
+[Lamp]
+when = cron */15 18-23 * * 0,6
+do = Hall/Lamp switch
+next = 2025-06-07 18:00
+
+[Once]
+when = at 2025-06-02 12:30:15
+do = Hall/Lamp off
+";
+
+    // Monday 2025-06-02 06:00 at +03:00
+    fn monday() -> SystemTime {
+        parse_time("2025-06-02 06:00", UtcOffset::from_minutes(180)).unwrap()
+    }
+
+    fn at(s: &str) -> SystemTime {
+        parse_time(s, UtcOffset::from_minutes(180)).unwrap()
+    }
+
+    fn kitchen() -> Home {
+        socket_home("Kitchen", &["Kettle"])
+    }
+
+    fn kettle_is_on(home: &Home) -> bool {
+        socket_is_on(home, "Kitchen", "Kettle")
+    }
+
+    #[test]
+    fn test_parse_schedules() {
+        let set: ScheduleSet = SCHEDULES.parse().unwrap();
+        assert_eq!(set.schedules.len(), 3);
+        let kettle = &set.schedules[0];
+        assert_eq!(
+            kettle.when,
+            When::Weekly(Weekday::ALL[..5].to_vec(), TimeOfDay::new(7, 0))
+        );
+        assert_eq!(
+            kettle.action,
+            Action::new(DeviceRef::new("Kitchen", "Kettle"), Command::On)
+        );
+        assert_eq!(kettle.duration, Some(Duration::from_secs(1800)));
+        assert_eq!(kettle.missed, Missed::Skip);
+        assert_eq!(set.schedules[1].next, Some(at("2025-06-07 18:00")));
+        assert_eq!(
+            set.schedules[2].when,
+            When::At(at("2025-06-02 12:30") + Duration::from_secs(15))
+        );
+
+        let reparsed: ScheduleSet = set.to_string().parse().unwrap();
+        assert_eq!(reparsed, set);
+
+        assert!(
+            "[Bad]\nwhen = daily 07:00\n"
+                .parse::<ScheduleSet>()
+                .is_err()
+        );
+        assert!(
+            "[Bad]\nwhen = cron 0 7 * *\ndo = R/S on\n"
+                .parse::<ScheduleSet>()
+                .is_err()
+        );
+        assert!(
+            "[Bad]\nwhen = someday 07:00\ndo = R/S on\n"
+                .parse::<ScheduleSet>()
+                .is_err()
+        );
+        assert!(
+            "[Bad]\nwhen = daily 07:00\ndo = R/S on\nfor = 200000000000000d\n"
+                .parse::<ScheduleSet>()
+                .is_err()
+        );
+        assert!(
+            "[Bad]\nwhen = at 999999999999-01-01 00:00\ndo = R/S on\n"
+                .parse::<ScheduleSet>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_next_after() {
+        let offset = UtcOffset::from_minutes(180);
+        let weekdays = When::parse("mon-fri 07:00", offset).unwrap();
+        assert_eq!(
+            weekdays.next_after(monday(), offset),
+            Some(at("2025-06-02 07:00"))
+        );
+        // Friday 07:00 is followed by Monday
+        assert_eq!(
+            weekdays.next_after(at("2025-06-06 07:00"), offset),
+            Some(at("2025-06-09 07:00"))
+        );
+        let cron = When::parse("cron */15 18-23 * * 0,6", offset).unwrap();
+        assert_eq!(
+            cron.next_after(monday(), offset),
+            Some(at("2025-06-07 18:00"))
+        );
+        assert_eq!(
+            cron.next_after(at("2025-06-07 23:45"), offset),
+            Some(at("2025-06-08 18:00"))
+        );
+        // Restricted day and weekday match either of them
+        let cron = When::parse("cron 0 12 13 * 5", offset).unwrap();
+        assert_eq!(
+            cron.next_after(monday(), offset),
+            Some(at("2025-06-06 12:00"))
+        );
+        let never = When::parse("cron 0 0 30 2 *", offset).unwrap();
+        assert_eq!(never.next_after(monday(), offset), None);
+        let once = When::At(at("2025-06-02 12:00"));
+        assert_eq!(once.next_after(at("2025-06-02 12:00"), offset), None);
+    }
+
+    #[test]
+    fn test_on_for_period() {
+        let home = kitchen();
+        let clock = ManualClock::new(monday());
+        let set: ScheduleSet = SCHEDULES.parse().unwrap();
+        let set = ScheduleSet {
+            schedules: set.schedules[..1].to_vec(),
+            ..set
+        };
+        let mut scheduler = Scheduler::with_clock(set, clock.clone());
+        assert_eq!(scheduler.next_run(), Some(at("2025-06-02 07:00")));
+        assert!(scheduler.run_due(&home).is_empty());
+
+        clock.set(at("2025-06-02 07:00"));
+        let runs = scheduler.run_due(&home);
+        assert!(matches!(
+            &runs[..],
+            [Run::Executed {
+                late: false,
+                result: Ok(()),
+                ..
+            }]
+        ));
+        assert!(kettle_is_on(&home));
+        assert_eq!(scheduler.next_run(), Some(at("2025-06-02 07:30")));
+
+        clock.set(at("2025-06-02 07:30"));
+        let runs = scheduler.run_due(&home);
+        assert!(
+            matches!(&runs[..], [Run::Executed { schedule, .. }] if schedule == "Kettle (end)")
+        );
+        assert!(!kettle_is_on(&home));
+        // The one-shot revert is gone after its run
+        assert_eq!(scheduler.schedules().schedules.len(), 1);
+        assert_eq!(scheduler.next_run(), Some(at("2025-06-03 07:00")));
+    }
+
+    #[test]
+    fn test_missed_runs() {
+        let home = kitchen();
+        let action = || Action::new(DeviceRef::new("Kitchen", "Kettle"), Command::On);
+        let clock = ManualClock::new(monday());
+        let set = ScheduleSet {
+            utc_offset: UtcOffset::from_minutes(180),
+            schedules: Vec::new(),
+        };
+        let mut scheduler = Scheduler::with_clock(set, clock.clone());
+        scheduler.add(Schedule::new(
+            "Daily",
+            When::Daily(TimeOfDay::new(3, 0)),
+            action(),
+        ));
+        scheduler
+            .add(Schedule::new("Skip", When::Daily(TimeOfDay::new(3, 0)), action()).skip_missed());
+        scheduler.add_timer("Timer", action(), Duration::from_secs(600));
+
+        // Pending runs survive a restart
+        let path = std::env::temp_dir().join(format!("schedules-{}.cfg", std::process::id()));
+        scheduler.save(&path).unwrap();
+        drop(scheduler);
+        clock.set(at("2025-06-04 08:00"));
+        let set = ScheduleSet::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut scheduler = Scheduler::with_clock(set, clock.clone());
+        assert_eq!(scheduler.next_run(), Some(at("2025-06-02 06:10")));
+
+        let runs = scheduler.run_due(&home);
+        let names: Vec<(&str, bool)> = runs
+            .iter()
+            .map(|run| match run {
+                Run::Executed { schedule, late, .. } => (schedule.as_str(), *late),
+                Run::Skipped { schedule, .. } => (schedule.as_str(), false),
+            })
+            .collect();
+        // Two missed days of `Daily` run once
+        assert_eq!(names, [("Timer", true), ("Daily", true), ("Skip", false)]);
+        assert!(matches!(runs[2], Run::Skipped { .. }));
+        assert!(kettle_is_on(&home));
+        assert_eq!(scheduler.schedules().schedules.len(), 2);
+        assert_eq!(scheduler.next_run(), Some(at("2025-06-05 03:00")));
+    }
+
+    #[test]
+    fn test_failed_action() {
+        let home = Home::new("Test");
+        let clock = ManualClock::new(monday());
+        let mut scheduler = Scheduler::with_clock(ScheduleSet::default(), clock);
+        let action = Action::new(DeviceRef::new("Kitchen", "Kettle"), Command::Off);
+        scheduler.add_timer("Timer", action, Duration::ZERO);
+        let runs = scheduler.run_due(&home);
+        assert!(matches!(
+            &runs[..],
+            [Run::Executed {
+                result: Err(SmartHomeError::RoomNotFound(_)),
+                ..
+            }]
+        ));
+        assert_eq!(scheduler.next_run(), None);
+    }
+}
@@ -0,0 +1,333 @@
+use crate::automation::DeviceRef;
+use crate::clock::{Clock, SystemClock, format_duration};
+use crate::{Home, Report, SmartHomeError};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
+
+// Readings older than this switch the socket off.
+const STALE_AFTER: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Heat,
+    Cool,
+    Off,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Heat => write!(f, "heat"),
+            Mode::Cool => write!(f, "cool"),
+            Mode::Off => write!(f, "off"),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = SmartHomeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "heat" => Ok(Mode::Heat),
+            "cool" => Ok(Mode::Cool),
+            "off" => Ok(Mode::Off),
+            _ => Err(SmartHomeError::ConfigError(format!(
+                "Invalid thermostat mode '{s}', expected heat, cool or off"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    // Mode is off, socket is off.
+    Off,
+    // Temperature is where it should be, socket is off.
+    Idle,
+    // Socket is on, heating or cooling.
+    Active,
+    // Socket should be switched but its minimal on/off time has not passed yet.
+    Holding { on: bool, remaining: Duration },
+    // No fresh reading, socket is switched off for safety.
+    Stale,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Off => write!(f, "off"),
+            Status::Idle => write!(f, "idle"),
+            Status::Active => write!(f, "active"),
+            Status::Holding { on, remaining } => write!(
+                f,
+                "holding {} for {}",
+                if *on { "on" } else { "off" },
+                format_duration(*remaining)
+            ),
+            Status::Stale => write!(f, "stale reading, cut off"),
+        }
+    }
+}
+
+// Drives a socket from a thermometer reading. Heating switches the socket on below
+// `setpoint - hysteresis / 2` and off above `setpoint + hysteresis / 2`, cooling the other
+// way around.
+pub struct Thermostat<C: Clock = SystemClock> {
+    pub thermometer: DeviceRef,
+    pub socket: DeviceRef,
+    pub setpoint: f32,
+    pub hysteresis: f32,
+    pub mode: Mode,
+    pub min_on: Duration,
+    pub min_off: Duration,
+    pub stale_after: Duration,
+    clock: C,
+    last_switch: Option<SystemTime>,
+    last: Option<Result<(f32, bool, Status), String>>,
+}
+
+impl Thermostat<SystemClock> {
+    pub fn new(thermometer: DeviceRef, socket: DeviceRef, setpoint: f32) -> Self {
+        Self {
+            thermometer,
+            socket,
+            setpoint,
+            hysteresis: 1.0,
+            mode: Mode::default(),
+            min_on: Duration::ZERO,
+            min_off: Duration::ZERO,
+            stale_after: STALE_AFTER,
+            clock: SystemClock,
+            last_switch: None,
+            last: None,
+        }
+    }
+}
+
+impl<C: Clock> Thermostat<C> {
+    pub fn with_clock<T: Clock>(self, clock: T) -> Thermostat<T> {
+        Thermostat {
+            thermometer: self.thermometer,
+            socket: self.socket,
+            setpoint: self.setpoint,
+            hysteresis: self.hysteresis,
+            mode: self.mode,
+            min_on: self.min_on,
+            min_off: self.min_off,
+            stale_after: self.stale_after,
+            clock,
+            last_switch: self.last_switch,
+            last: self.last,
+        }
+    }
+    pub fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+    pub fn with_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+    pub fn with_min_times(mut self, min_on: Duration, min_off: Duration) -> Self {
+        self.min_on = min_on;
+        self.min_off = min_off;
+        self
+    }
+    pub fn with_stale_after(mut self, stale_after: Duration) -> Self {
+        self.stale_after = stale_after;
+        self
+    }
+
+    // Status of the last `tick`.
+    pub fn status(&self) -> Option<Status> {
+        match self.last {
+            Some(Ok((_, _, status))) => Some(status),
+            _ => None,
+        }
+    }
+
+    // Reads the thermometer and switches the socket when needed.
+    pub fn tick(&mut self, home: &Home) -> Result<Status, SmartHomeError> {
+        let result = self.control(home);
+        self.last = Some(result.as_ref().copied().map_err(|e| e.to_string()));
+        result.map(|(_, _, status)| status)
+    }
+
+    fn control(&mut self, home: &Home) -> Result<(f32, bool, Status), SmartHomeError> {
+        let thermometer = home
+            .find_device(&self.thermometer.room, &self.thermometer.device)?
+            .as_thermometer()?;
+        let socket = home
+            .find_device(&self.socket.room, &self.socket.device)?
+            .as_socket()?;
+        let temperature = thermometer.get_temperature();
+        let on = socket.is_on()?;
+        let fresh = thermometer
+            .last_update()
+            .is_some_and(|updated| Instant::now().duration_since(updated) <= self.stale_after);
+
+        // Cut-offs ignore minimal on time
+        let forced = match self.mode {
+            _ if !fresh => Some(Status::Stale),
+            Mode::Off => Some(Status::Off),
+            Mode::Heat | Mode::Cool => None,
+        };
+        if let Some(status) = forced {
+            if on {
+                socket.turn_off()?;
+                self.last_switch = Some(self.clock.now());
+            }
+            return Ok((temperature, false, status));
+        }
+
+        let low = temperature < self.setpoint - self.hysteresis / 2.;
+        let high = temperature > self.setpoint + self.hysteresis / 2.;
+        let wanted = match self.mode {
+            Mode::Heat if low => true,
+            Mode::Cool if high => true,
+            Mode::Heat if high => false,
+            Mode::Cool if low => false,
+            _ => on,
+        };
+        let status = |on| if on { Status::Active } else { Status::Idle };
+        if wanted == on {
+            return Ok((temperature, on, status(on)));
+        }
+
+        let now = self.clock.now();
+        let min = if on { self.min_on } else { self.min_off };
+        let elapsed = self
+            .last_switch
+            .map_or(min, |at| now.duration_since(at).unwrap_or_default());
+        if elapsed < min {
+            let remaining = min - elapsed;
+            return Ok((temperature, on, Status::Holding { on, remaining }));
+        }
+        if wanted {
+            socket.turn_on()?;
+        } else {
+            socket.turn_off()?;
+        }
+        self.last_switch = Some(now);
+        Ok((temperature, wanted, status(wanted)))
+    }
+}
+
+impl<C: Clock> Report for Thermostat<C> {
+    fn report(&self) -> String {
+        let settings = format!("Mode: {}\t Setpoint: {:.2}", self.mode, self.setpoint);
+        match &self.last {
+            None => format!("{settings}\t Status: not started"),
+            Some(Ok((temperature, on, status))) => format!(
+                "{settings}\t Temperature: {temperature:.2}\t Socket: {}\t Status: {status}",
+                if *on { "on" } else { "off" }
+            ),
+            Some(Err(err)) => format!("{settings}\t Error: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SmartThermometer;
+    use crate::clock::ManualClock;
+    use crate::emulator::{EmulatedSensor, socket_home, socket_is_on};
+    use std::thread;
+    use std::time::UNIX_EPOCH;
+
+    fn bathroom(sensor: &EmulatedSensor) -> Home {
+        let mut home = socket_home("Bathroom", &["Heater"]);
+        home.get_room_mut("Bathroom")
+            .unwrap()
+            .add_device("Thermometer", SmartThermometer::new(sensor.clone()).into());
+        thread::sleep(Duration::from_millis(100));
+        home
+    }
+
+    fn thermostat(setpoint: f32) -> Thermostat {
+        Thermostat::new(
+            DeviceRef::new("Bathroom", "Thermometer"),
+            DeviceRef::new("Bathroom", "Heater"),
+            setpoint,
+        )
+    }
+
+    fn heater_is_on(home: &Home) -> bool {
+        socket_is_on(home, "Bathroom", "Heater")
+    }
+
+    #[test]
+    fn test_heating() {
+        let sensor = EmulatedSensor::new(19.0);
+        let home = bathroom(&sensor);
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let minute = Duration::from_secs(60);
+        let mut thermostat = thermostat(21.0)
+            .with_min_times(5 * minute, minute)
+            .with_clock(clock.clone());
+
+        assert_eq!(thermostat.tick(&home).unwrap(), Status::Active);
+        assert!(heater_is_on(&home));
+        assert!(thermostat.report().contains("Socket: on"));
+
+        // Within the band nothing changes
+        sensor.set(21.3);
+        thread::sleep(Duration::from_millis(1100));
+        assert_eq!(thermostat.tick(&home).unwrap(), Status::Active);
+
+        sensor.set(22.0);
+        thread::sleep(Duration::from_millis(1100));
+        clock.advance(2 * minute);
+        assert_eq!(
+            thermostat.tick(&home).unwrap(),
+            Status::Holding {
+                on: true,
+                remaining: 3 * minute
+            }
+        );
+        assert!(heater_is_on(&home));
+        clock.advance(3 * minute);
+        assert_eq!(thermostat.tick(&home).unwrap(), Status::Idle);
+        assert!(!heater_is_on(&home));
+
+        thermostat.mode = Mode::Cool;
+        clock.advance(minute);
+        assert_eq!(thermostat.tick(&home).unwrap(), Status::Active);
+        assert!(heater_is_on(&home));
+
+        thermostat.mode = Mode::Off;
+        assert_eq!(thermostat.tick(&home).unwrap(), Status::Off);
+        assert!(!heater_is_on(&home));
+    }
+
+    #[test]
+    fn test_stale_cut_off() {
+        let sensor = EmulatedSensor::new(15.0);
+        let home = bathroom(&sensor);
+        let mut thermostat = thermostat(21.0);
+        assert_eq!(thermostat.tick(&home).unwrap(), Status::Active);
+        assert!(heater_is_on(&home));
+
+        thermostat.stale_after = Duration::ZERO;
+        assert_eq!(thermostat.tick(&home).unwrap(), Status::Stale);
+        assert!(!heater_is_on(&home));
+        assert_eq!(thermostat.status(), Some(Status::Stale));
+    }
+
+    #[test]
+    fn test_missing_device() {
+        let home = Home::new("Test");
+        let mut thermostat = thermostat(21.0);
+        assert!(matches!(
+            thermostat.tick(&home),
+            Err(SmartHomeError::RoomNotFound(_))
+        ));
+        assert!(thermostat.report().contains("Error: "));
+        assert_eq!(thermostat.status(), None);
+    }
+}